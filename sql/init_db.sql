@@ -15,8 +15,17 @@ CREATE TABLE IF NOT EXISTS "repo" (
     "created_at" TEXT NOT NULL,
     "updated_at" TEXT NOT NULL,
     "status" TEXT NOT NULL,
+    "public" INTEGER NOT NULL DEFAULT 0,
+    "version" INTEGER NOT NULL DEFAULT 1,
+    "search_tokenizer" TEXT,
     FOREIGN KEY("owner") REFERENCES "user"("id")
 );
+CREATE TABLE IF NOT EXISTS "repo_topic" (
+    "repo_id" TEXT NOT NULL,
+    "topic" TEXT NOT NULL,
+    PRIMARY KEY("repo_id", "topic"),
+    FOREIGN KEY("repo_id") REFERENCES "repo"("id")
+);
 CREATE TABLE IF NOT EXISTS "post" (
     "id" TEXT PRIMARY KEY,
     "category" TEXT NOT NULL,
@@ -26,9 +35,110 @@ CREATE TABLE IF NOT EXISTS "post" (
     "updated_at" TEXT NOT NULL,
     "author" TEXT NOT NULL,
     "repo_id" TEXT NOT NULL,
+    "version" INTEGER NOT NULL DEFAULT 1,
+    "category_id" TEXT,
+    "state" TEXT NOT NULL DEFAULT 'published',
+    "publish_at" TEXT,
     FOREIGN KEY("author") REFERENCES "user"("id"),
     FOREIGN KEY("repo_id") REFERENCES "repo"("id")
 );
+CREATE TABLE IF NOT EXISTS "category" (
+    "id" TEXT PRIMARY KEY,
+    "repo_id" TEXT NOT NULL,
+    "name" TEXT NOT NULL COLLATE NOCASE,
+    "color" TEXT,
+    "icon" TEXT,
+    "sort_order" INTEGER NOT NULL,
+    "created_at" TEXT NOT NULL,
+    "updated_at" TEXT NOT NULL,
+    UNIQUE("repo_id", "name"),
+    FOREIGN KEY("repo_id") REFERENCES "repo"("id")
+);
+CREATE TABLE IF NOT EXISTS "post_tag" (
+    "post_id" TEXT NOT NULL,
+    "repo_id" TEXT NOT NULL,
+    "tag" TEXT NOT NULL,
+    PRIMARY KEY("post_id", "tag"),
+    FOREIGN KEY("post_id") REFERENCES "post"("id")
+);
+CREATE INDEX IF NOT EXISTS "post_tag_repo_tag" ON "post_tag" ("repo_id", "tag");
+CREATE TABLE IF NOT EXISTS "attachment" (
+    "repo_id" TEXT NOT NULL,
+    "sha256" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "content_type" TEXT NOT NULL,
+    "size" INTEGER NOT NULL,
+    "uploader" TEXT NOT NULL,
+    "created_at" TEXT NOT NULL,
+    PRIMARY KEY("repo_id", "sha256"),
+    FOREIGN KEY("repo_id") REFERENCES "repo"("id"),
+    FOREIGN KEY("uploader") REFERENCES "user"("id")
+);
+CREATE INDEX IF NOT EXISTS "attachment_sha256" ON "attachment" ("sha256");
+CREATE TABLE IF NOT EXISTS "post_attachment" (
+    "post_id" TEXT NOT NULL,
+    "repo_id" TEXT NOT NULL,
+    "sha256" TEXT NOT NULL,
+    PRIMARY KEY("post_id", "sha256"),
+    FOREIGN KEY("post_id") REFERENCES "post"("id")
+);
+CREATE INDEX IF NOT EXISTS "post_attachment_repo_sha256" ON "post_attachment" ("repo_id", "sha256");
+CREATE TABLE IF NOT EXISTS "post_link" (
+    "post_id" TEXT NOT NULL,
+    "repo_id" TEXT NOT NULL,
+    "target" TEXT NOT NULL,
+    PRIMARY KEY("post_id", "target"),
+    FOREIGN KEY("post_id") REFERENCES "post"("id")
+);
+CREATE INDEX IF NOT EXISTS "post_link_repo_target" ON "post_link" ("repo_id", "target" COLLATE NOCASE);
+CREATE TABLE IF NOT EXISTS "upload" (
+    "id" TEXT PRIMARY KEY,
+    "repo_id" TEXT NOT NULL,
+    "uploader" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "content_type" TEXT,
+    "length" INTEGER NOT NULL,
+    "received" INTEGER NOT NULL,
+    "sha256" TEXT,
+    "created_at" TEXT NOT NULL,
+    "expires_at" TEXT NOT NULL,
+    FOREIGN KEY("repo_id") REFERENCES "repo"("id"),
+    FOREIGN KEY("uploader") REFERENCES "user"("id")
+);
+CREATE TABLE IF NOT EXISTS "rendered_post" (
+    "post_id" TEXT PRIMARY KEY,
+    "content_hash" TEXT NOT NULL,
+    "html" TEXT NOT NULL,
+    "rendered_at" TEXT NOT NULL,
+    FOREIGN KEY("post_id") REFERENCES "post"("id")
+);
+CREATE TABLE IF NOT EXISTS "site_template" (
+    "repo_id" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "body" TEXT NOT NULL,
+    "updated_at" TEXT NOT NULL,
+    PRIMARY KEY("repo_id", "name"),
+    FOREIGN KEY("repo_id") REFERENCES "repo"("id")
+);
+CREATE TABLE IF NOT EXISTS "post_redirect" (
+    "post_id" TEXT NOT NULL,
+    "from_repo_id" TEXT NOT NULL,
+    "to_repo_id" TEXT NOT NULL,
+    "moved_at" TEXT NOT NULL,
+    PRIMARY KEY("post_id", "from_repo_id")
+);
+CREATE TABLE IF NOT EXISTS "post_revision" (
+    "post_id" TEXT NOT NULL,
+    "revision" INTEGER NOT NULL,
+    "title" TEXT NOT NULL,
+    "category" TEXT NOT NULL,
+    "content" TEXT NOT NULL,
+    "author" TEXT NOT NULL,
+    "updated_at" TEXT NOT NULL,
+    "archived_at" TEXT NOT NULL,
+    PRIMARY KEY("post_id", "revision"),
+    FOREIGN KEY("post_id") REFERENCES "post"("id")
+);
 CREATE TABLE IF NOT EXISTS "subscribe" (
     "id" TEXT PRIMARY KEY,
     "user_id" TEXT NOT NULL,
@@ -36,6 +146,24 @@ CREATE TABLE IF NOT EXISTS "subscribe" (
     FOREIGN KEY("user_id") REFERENCES "user"("id"),
     FOREIGN KEY("repo_id") REFERENCES "repo"("id")
 );
+CREATE TABLE IF NOT EXISTS "post_read" (
+    "user_id" TEXT NOT NULL,
+    "post_id" TEXT NOT NULL,
+    "read_seq" INTEGER NOT NULL,
+    "read_at" TEXT NOT NULL,
+    PRIMARY KEY("user_id", "post_id"),
+    FOREIGN KEY("user_id") REFERENCES "user"("id"),
+    FOREIGN KEY("post_id") REFERENCES "post"("id")
+);
+CREATE TABLE IF NOT EXISTS "repo_read" (
+    "user_id" TEXT NOT NULL,
+    "repo_id" TEXT NOT NULL,
+    "read_seq" INTEGER NOT NULL,
+    "read_at" TEXT NOT NULL,
+    PRIMARY KEY("user_id", "repo_id"),
+    FOREIGN KEY("user_id") REFERENCES "user"("id"),
+    FOREIGN KEY("repo_id") REFERENCES "repo"("id")
+);
 CREATE TABLE IF NOT EXISTS "comment" (
     "id" TEXT PRIMARY KEY,
     "post_id" TEXT NOT NULL,
@@ -49,4 +177,55 @@ CREATE TABLE IF NOT EXISTS "comment" (
     FOREIGN KEY("post_id") REFERENCES "post"("id"),
     FOREIGN KEY("repo_id") REFERENCES "repo"("id")
 );
+CREATE TABLE IF NOT EXISTS "reaction" (
+    "kind" TEXT NOT NULL,
+    "target_id" TEXT NOT NULL,
+    "user_id" TEXT NOT NULL,
+    "emoji" TEXT NOT NULL,
+    "created_at" TEXT NOT NULL,
+    PRIMARY KEY("kind", "target_id", "user_id", "emoji"),
+    FOREIGN KEY("user_id") REFERENCES "user"("id")
+);
+CREATE TABLE IF NOT EXISTS "change_log" (
+    "seq" INTEGER PRIMARY KEY AUTOINCREMENT,
+    "repo_id" TEXT NOT NULL,
+    "kind" TEXT NOT NULL,
+    "entity_id" TEXT NOT NULL,
+    "op" TEXT NOT NULL,
+    "changed_at" TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS "tombstone" (
+    "kind" TEXT NOT NULL,
+    "entity_id" TEXT NOT NULL,
+    "repo_id" TEXT NOT NULL,
+    "deleted_at" TEXT NOT NULL,
+    "deleted_by" TEXT NOT NULL,
+    PRIMARY KEY("kind", "entity_id"),
+    FOREIGN KEY("deleted_by") REFERENCES "user"("id")
+);
+-- the highest `change_log.seq` gc has dropped a delete at, older cursors would miss it
+CREATE TABLE IF NOT EXISTS "change_gc" (
+    "id" INTEGER PRIMARY KEY CHECK ("id" = 1),
+    "seq" INTEGER NOT NULL,
+    "gc_at" TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS "change_log_repo_seq" ON "change_log"("repo_id", "seq");
+CREATE INDEX IF NOT EXISTS "change_log_entity" ON "change_log"("kind", "entity_id");
+-- rows written before the change log existed
+INSERT INTO "change_log" ("repo_id", "kind", "entity_id", "op", "changed_at")
+    SELECT "repo_id", 'post', "id", 'upsert', "updated_at" FROM "post"
+    WHERE NOT EXISTS (SELECT 1 FROM "change_log" WHERE "kind" = 'post' AND "entity_id" = "post"."id");
+INSERT INTO "change_log" ("repo_id", "kind", "entity_id", "op", "changed_at")
+    SELECT "repo_id", 'comment', "id", 'upsert', "updated_at" FROM "comment"
+    WHERE NOT EXISTS (SELECT 1 FROM "change_log" WHERE "kind" = 'comment' AND "entity_id" = "comment"."id");
+CREATE VIRTUAL TABLE IF NOT EXISTS "search_index" USING fts5(
+    "kind" UNINDEXED,
+    "entity_id" UNINDEXED,
+    "repo_id" UNINDEXED,
+    "post_id" UNINDEXED,
+    "title",
+    "category",
+    "content",
+    tokenize = 'unicode61'
+);
 COMMIT;
@@ -1,13 +1,73 @@
+use std::path::PathBuf;
+
 use rusqlite::Connection;
 
 use crate::error::ServiceResult;
 
+/// columns added after a table was first created, as `(table, column, definition)`.
+/// `init_db.sql` already contains them for fresh databases, existing ones get them here.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("repo", "public", "INTEGER NOT NULL DEFAULT 0"),
+    ("repo", "version", "INTEGER NOT NULL DEFAULT 1"),
+    ("post", "version", "INTEGER NOT NULL DEFAULT 1"),
+    ("repo", "search_tokenizer", "TEXT"),
+    ("post", "category_id", "TEXT"),
+    ("post", "state", "TEXT NOT NULL DEFAULT 'published'"),
+    ("post", "publish_at", "TEXT"),
+];
+
+#[cfg(not(test))]
+fn db_path() -> PathBuf {
+    const DEFAULT_DB_PATH: &str = "xbb.db3";
+    PathBuf::from(
+        crate::SERVER_CONFIG
+            .db_path
+            .as_deref()
+            .unwrap_or(DEFAULT_DB_PATH),
+    )
+}
+
+/// tests get a database of their own in the temp dir, never the server's.
+#[cfg(test)]
+fn db_path() -> PathBuf {
+    std::env::temp_dir().join(format!("xbb-test-{}.db3", std::process::id()))
+}
+
 pub fn new_conn() -> ServiceResult<Connection> {
-    Ok(Connection::open("xbb.db3")?)
+    Ok(Connection::open(db_path())?)
 }
 
 pub fn init_db() -> anyhow::Result<()> {
     let conn = new_conn()?;
     conn.execute_batch(include_str!("../sql/init_db.sql"))?;
+    for (table, column, definition) in ADDED_COLUMNS {
+        if !has_column(&conn, table, column)? {
+            conn.execute_batch(&format!(
+                "ALTER TABLE \"{table}\" ADD COLUMN \"{column}\" {definition};"
+            ))?;
+        }
+    }
+    crate::model::category::derive_categories(&conn)?;
+    // content written before the search index existed
+    let indexed: i64 = conn.query_row("SELECT COUNT(*) FROM search_index", [], |row| row.get(0))?;
+    if indexed == 0 {
+        crate::model::search::reindex(None)?;
+    }
+    // posts saved before links were stored
+    let linked: i64 = conn.query_row("SELECT COUNT(*) FROM post_link", [], |row| row.get(0))?;
+    if linked == 0 {
+        crate::model::link::relink_posts(&conn)?;
+    }
     Ok(())
 }
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info(\"{table}\")"))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if row.get::<_, String>(1)? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
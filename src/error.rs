@@ -1,4 +1,8 @@
-use salvo::{async_trait, writing::Text, Depot, Request, Response, Writer};
+use salvo::{
+    async_trait,
+    writing::{Json, Text},
+    Depot, Request, Response, Writer,
+};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -13,6 +17,15 @@ pub enum ServiceError {
     NotFound(String),
     #[error("409, Conflict, {0}")]
     Conflict(String),
+    /// the write expected an older version, carries the current server copy.
+    #[error("409, Conflict, version mismatch")]
+    VersionConflict(serde_json::Value),
+    /// no longer available, like a change cursor older than what gc kept.
+    #[error("410, Gone, {0}")]
+    Gone(String),
+    /// moved elsewhere, carries the new location.
+    #[error("308, Permanent Redirect, {0}")]
+    Moved(String),
 
     #[error("500, Internal Server Error")]
     InternalServerError(String),
@@ -44,6 +57,25 @@ impl Writer for ServiceError {
                 res.status_code(salvo::http::StatusCode::CONFLICT);
                 res.render(Text::Plain(format!("409, Conflict, {}", err)));
             }
+            ServiceError::VersionConflict(current) => {
+                res.status_code(salvo::http::StatusCode::CONFLICT);
+                res.render(Json(serde_json::json!({
+                    "error": "version mismatch",
+                    "current": current,
+                })));
+            }
+            ServiceError::Gone(err) => {
+                res.status_code(salvo::http::StatusCode::GONE);
+                res.render(Text::Plain(format!("410, Gone, {}", err)));
+            }
+            ServiceError::Moved(location) => {
+                res.status_code(salvo::http::StatusCode::PERMANENT_REDIRECT);
+                if let Ok(value) = location.parse() {
+                    res.headers_mut()
+                        .insert(salvo::http::header::LOCATION, value);
+                }
+                res.render(Json(serde_json::json!({ "location": location })));
+            }
             ServiceError::InternalServerError(err) => {
                 res.status_code(salvo::http::StatusCode::INTERNAL_SERVER_ERROR);
                 tracing::error!("InternalServerError: {}", err);
@@ -59,8 +91,20 @@ impl From<rusqlite::Error> for ServiceError {
     }
 }
 
+impl From<std::io::Error> for ServiceError {
+    fn from(err: std::io::Error) -> Self {
+        ServiceError::InternalServerError(err.to_string())
+    }
+}
+
 impl From<salvo::http::ParseError> for ServiceError {
     fn from(err: salvo::http::ParseError) -> Self {
         ServiceError::BadRequest(err.to_string())
     }
 }
+
+impl From<zip::result::ZipError> for ServiceError {
+    fn from(err: zip::result::ZipError) -> Self {
+        ServiceError::InternalServerError(err.to_string())
+    }
+}
@@ -6,6 +6,7 @@ mod router;
 
 use std::path::Path;
 
+use anyhow::Context;
 use salvo::{
     conn::{
         rustls::{Keycert, RustlsConfig},
@@ -18,6 +19,9 @@ use salvo::{
 };
 
 const DEFAULT_PORT: u16 = 15443;
+const DEFAULT_TOMBSTONE_RETENTION_DAYS: u32 = 90;
+/// unreferenced attachments are kept this long, so a post can be pushed after its uploads.
+const ATTACHMENT_GC_GRACE_HOURS: i64 = 24;
 
 lazy_static::lazy_static! {
     static ref SERVER_CONFIG: opt::Config = {
@@ -29,8 +33,34 @@ lazy_static::lazy_static! {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // read config.json file
-    db::init_db()?;
     let config = &SERVER_CONFIG;
+    // checked before init_db, which may build the search index with it
+    if let Some(tokenizer) = &config.search_tokenizer {
+        let tokenizer = tokenizer
+            .parse()
+            .with_context(|| format!("invalid search_tokenizer {tokenizer:?} in config"))?;
+        model::search::set_default_tokenizer(tokenizer);
+    }
+    db::init_db()?;
+
+    // `xbb-server reindex` rebuilds the search index and exits
+    if std::env::args().nth(1).as_deref() == Some("reindex") {
+        let count = model::search::reindex(None)?;
+        println!("reindexed {count} posts and comments");
+        return Ok(());
+    }
+
+    // `xbb-server site <repo_id> <dir> [base_url]` renders the repo as a static site and exits
+    if std::env::args().nth(1).as_deref() == Some("site") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let [repo_id, dir, rest @ ..] = args.as_slice() else {
+            anyhow::bail!("usage: xbb-server site <repo_id> <dir> [base_url]");
+        };
+        let base_url = rest.first().map(String::as_str);
+        let count = model::site::write_site_dir(repo_id, base_url, Path::new(dir))?;
+        println!("rendered {count} posts into {dir}");
+        return Ok(());
+    }
 
     // log file
     let log_path_str = config.log_path.as_deref().unwrap_or("./");
@@ -44,6 +74,13 @@ async fn main() -> anyhow::Result<()> {
     let port = config.port.unwrap_or(DEFAULT_PORT);
     let address = format!("0.0.0.0:{}", port);
 
+    let retention_days = config
+        .tombstone_retention_days
+        .unwrap_or(DEFAULT_TOMBSTONE_RETENTION_DAYS);
+    tokio::spawn(gc_tombstones_task(retention_days));
+    tokio::spawn(gc_attachments_task());
+    tokio::spawn(publish_scheduled_task());
+
     let ssl_config = RustlsConfig::new(Keycert::new().cert(cert).key(key));
     let acceptor = TcpListener::new(address).rustls(ssl_config).bind().await;
 
@@ -63,6 +100,55 @@ fn set_config(depot: &mut Depot) {
     depot.insert("ClientVersion", SERVER_CONFIG.latest_version.clone());
 }
 
+/// daily cleanup of tombstones older than the retention horizon.
+async fn gc_tombstones_task(retention_days: u32) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(24 * 60 * 60));
+    loop {
+        interval.tick().await;
+        let before = chrono::Utc::now() - chrono::Duration::days(retention_days as i64);
+        match model::tombstone::gc_tombstones(before) {
+            Ok(removed) => tracing::info!("gc tombstones before {before}, removed {removed}"),
+            Err(err) => tracing::error!("gc tombstones failed: {err:?}"),
+        }
+    }
+}
+
+/// hourly cleanup of abandoned uploads, and daily of attachments no post links to anymore.
+async fn gc_attachments_task() {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+    for hour in 0u64.. {
+        interval.tick().await;
+        match model::upload::gc_uploads(chrono::Utc::now()) {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!("gc uploads, removed {removed}"),
+            Err(err) => tracing::error!("gc uploads failed: {err:?}"),
+        }
+        if hour % 24 != 0 {
+            continue;
+        }
+        let before = chrono::Utc::now() - chrono::Duration::hours(ATTACHMENT_GC_GRACE_HOURS);
+        match model::attachment::gc_attachments(before) {
+            Ok(removed) => {
+                tracing::info!("gc attachments before {before}, removed {removed} blobs")
+            }
+            Err(err) => tracing::error!("gc attachments failed: {err:?}"),
+        }
+    }
+}
+
+/// publish scheduled posts once they are due, checked every minute.
+async fn publish_scheduled_task() {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        match model::post::publish_due_posts(chrono::Utc::now()) {
+            Ok(0) => {}
+            Ok(published) => tracing::info!("published {published} scheduled posts"),
+            Err(err) => tracing::error!("publish scheduled posts failed: {err:?}"),
+        }
+    }
+}
+
 fn file_log(path: &Path, enable_debug: bool) -> anyhow::Result<impl Drop> {
     let file_path = path.join("logs");
     println!("logs file to: {file_path:?}");
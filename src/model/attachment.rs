@@ -0,0 +1,338 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{db::new_conn, error::ServiceResult};
+
+use super::{media::remove_thumbnails, post::Post};
+
+const DEFAULT_ATTACHMENT_DIR: &str = "attachments";
+
+/// held from storing a blob until its attachment is added, and by the gc while it
+/// removes a blob, so the gc never removes a blob an attachment is being added for.
+static BLOB_LOCK: Mutex<()> = Mutex::new(());
+
+/// a file uploaded to a repo, stored once on disk under its sha256.
+#[derive(Debug)]
+pub struct Attachment {
+    pub repo_id: String,
+    pub sha256: String,
+    pub name: String, // file name of the first upload
+    pub content_type: String,
+    pub size: u64,
+    pub uploader: String,
+    pub created_at: DateTime<Utc>,
+}
+
+pub fn attachment_dir() -> PathBuf {
+    PathBuf::from(
+        crate::SERVER_CONFIG
+            .attachment_dir
+            .as_deref()
+            .unwrap_or(DEFAULT_ATTACHMENT_DIR),
+    )
+}
+
+/// `<dir>/<first two hex digits>/<sha256>`, so no directory gets too large.
+pub fn blob_path(sha256: &str) -> PathBuf {
+    attachment_dir().join(&sha256[..2]).join(sha256)
+}
+
+pub fn is_sha256(s: &str) -> bool {
+    s.len() == 64
+        && s.bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// hex sha256 and size of a file.
+pub fn sha256_file(path: &Path) -> io::Result<(String, u64)> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+/// copy `path` into the blob store as `sha256`, a blob already stored is left as is.
+fn store_blob(path: &Path, sha256: &str) -> io::Result<()> {
+    let target = blob_path(sha256);
+    if !target.exists() {
+        let dir = target.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        // copy next to the target first, so a crash never leaves a partial blob behind
+        let partial = dir.join(format!("{sha256}.partial"));
+        fs::copy(path, &partial)?;
+        fs::rename(&partial, &target)?;
+    }
+    Ok(())
+}
+
+/// store the file at `path`, hashed to `attachment.sha256`, and add it as the attachment.
+/// returns the one already uploaded with the same content instead, if any.
+pub fn add_attachment_file(
+    path: &Path,
+    attachment: Attachment,
+) -> ServiceResult<(Attachment, bool)> {
+    let _lock = BLOB_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    store_blob(path, &attachment.sha256)?;
+    add_attachment(attachment)
+}
+
+/// add the attachment, or return the one already uploaded with the same content. its
+/// `created_at` moves to now then, so gc gives the new upload the full grace period.
+pub fn add_attachment(attachment: Attachment) -> ServiceResult<(Attachment, bool)> {
+    let conn = new_conn()?;
+    if let Some(existing) = query_attachment(&conn, &attachment.repo_id, &attachment.sha256)? {
+        conn.execute(
+            "UPDATE attachment SET created_at = ?1 WHERE repo_id = ?2 AND sha256 = ?3",
+            params![attachment.created_at, attachment.repo_id, attachment.sha256],
+        )?;
+        return Ok((
+            Attachment {
+                created_at: attachment.created_at,
+                ..existing
+            },
+            false,
+        ));
+    }
+    conn.execute(
+        "INSERT INTO attachment (repo_id, sha256, name, content_type, size, uploader, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            attachment.repo_id,
+            attachment.sha256,
+            attachment.name,
+            attachment.content_type,
+            attachment.size,
+            attachment.uploader,
+            attachment.created_at
+        ],
+    )?;
+    Ok((attachment, true))
+}
+
+fn query_attachment(
+    conn: &Connection,
+    repo_id: &str,
+    sha256: &str,
+) -> ServiceResult<Option<Attachment>> {
+    Ok(conn
+        .query_row(
+            "SELECT repo_id, sha256, name, content_type, size, uploader, created_at FROM attachment WHERE repo_id = ?1 AND sha256 = ?2",
+            params![repo_id, sha256],
+            |row| {
+                Ok(Attachment {
+                    repo_id: row.get(0)?,
+                    sha256: row.get(1)?,
+                    name: row.get(2)?,
+                    content_type: row.get(3)?,
+                    size: row.get(4)?,
+                    uploader: row.get(5)?,
+                    created_at: row.get(6)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+pub fn get_attachment(repo_id: &str, sha256: &str) -> ServiceResult<Option<Attachment>> {
+    let conn = new_conn()?;
+    query_attachment(&conn, repo_id, sha256)
+}
+
+pub fn list_attachments_by_repo_id(repo_id: &str) -> ServiceResult<Vec<Attachment>> {
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare("SELECT repo_id, sha256, name, content_type, size, uploader, created_at FROM attachment WHERE repo_id = ?1 ORDER BY created_at DESC")?;
+    let mut rows = stmt.query(params![repo_id])?;
+    let mut attachments = Vec::new();
+    while let Some(row) = rows.next()? {
+        attachments.push(Attachment {
+            repo_id: row.get(0)?,
+            sha256: row.get(1)?,
+            name: row.get(2)?,
+            content_type: row.get(3)?,
+            size: row.get(4)?,
+            uploader: row.get(5)?,
+            created_at: row.get(6)?,
+        });
+    }
+    Ok(attachments)
+}
+
+/// sha256s of the attachments a post links to, as `.../attachment/<sha256>`.
+pub fn attachment_refs(content: &str) -> Vec<String> {
+    const MARKER: &str = "attachment/";
+    let mut refs: Vec<String> = Vec::new();
+    for (start, _) in content.match_indices(MARKER) {
+        let candidate = content
+            .get(start + MARKER.len()..start + MARKER.len() + 64)
+            .unwrap_or_default();
+        if is_sha256(candidate) && !refs.iter().any(|r| r == candidate) {
+            refs.push(candidate.to_owned());
+        }
+    }
+    refs
+}
+
+/// remember which attachments the post links to, so they survive the gc.
+pub(crate) fn set_post_attachments(conn: &Connection, post: &Post) -> ServiceResult<()> {
+    conn.execute(
+        "DELETE FROM post_attachment WHERE post_id = ?1",
+        params![post.id],
+    )?;
+    let mut stmt =
+        conn.prepare("INSERT INTO post_attachment (post_id, repo_id, sha256) VALUES (?1, ?2, ?3)")?;
+    for sha256 in attachment_refs(&post.content) {
+        stmt.execute(params![post.id, post.repo_id, sha256])?;
+    }
+    Ok(())
+}
+
+/// make the attachments the post links to in `from_repo_id` attachments of `to_repo_id` too.
+pub(crate) fn copy_post_attachments(
+    conn: &Connection,
+    post_id: &str,
+    from_repo_id: &str,
+    to_repo_id: &str,
+) -> ServiceResult<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO attachment (repo_id, sha256, name, content_type, size, uploader, created_at)
+        SELECT ?3, sha256, name, content_type, size, uploader, created_at FROM attachment
+        WHERE repo_id = ?2 AND sha256 IN (SELECT sha256 FROM post_attachment WHERE post_id = ?1)",
+        params![post_id, from_repo_id, to_repo_id],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn erase_post_attachments(conn: &Connection, post_id: &str) -> ServiceResult<()> {
+    conn.execute(
+        "DELETE FROM post_attachment WHERE post_id = ?1",
+        params![post_id],
+    )?;
+    Ok(())
+}
+
+/// drop attachments uploaded before `before` that no post links to, and the blobs
+/// no attachment uses anymore. returns the number of removed blobs.
+pub fn gc_attachments(before: DateTime<Utc>) -> ServiceResult<usize> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let orphans = {
+        let mut stmt = tx.prepare(
+            "DELETE FROM attachment WHERE created_at < ?1 AND NOT EXISTS (
+                SELECT 1 FROM post_attachment p WHERE p.repo_id = attachment.repo_id AND p.sha256 = attachment.sha256
+            ) RETURNING sha256",
+        )?;
+        let removed = stmt
+            .query_map(params![before], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut orphans = Vec::new();
+        for sha256 in removed {
+            let used: i64 = tx.query_row(
+                "SELECT COUNT(*) FROM attachment WHERE sha256 = ?1",
+                params![sha256],
+                |row| row.get(0),
+            )?;
+            if used == 0 && !orphans.contains(&sha256) {
+                orphans.push(sha256);
+            }
+        }
+        orphans
+    };
+    tx.commit()?;
+    let mut removed = 0;
+    for sha256 in &orphans {
+        let _lock = BLOB_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        // added again since the transaction
+        let used: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM attachment WHERE sha256 = ?1)",
+            params![sha256],
+            |row| row.get(0),
+        )?;
+        if used {
+            continue;
+        }
+        match fs::remove_file(blob_path(sha256)) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        remove_thumbnails(sha256)?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiAttachmentResponse {
+    pub sha256: String,
+    pub repo_id: String,
+    pub name: String,
+    pub content_type: String,
+    pub size: u64,
+    pub uploader: String,
+    pub created_at: DateTime<Utc>,
+    pub url: String, // relative to the server root, link it from the post content
+}
+
+impl From<Attachment> for OpenApiAttachmentResponse {
+    fn from(value: Attachment) -> Self {
+        Self {
+            url: format!("/repo/{}/attachment/{}", value.repo_id, value.sha256),
+            sha256: value.sha256,
+            repo_id: value.repo_id,
+            name: value.name,
+            content_type: value.content_type,
+            size: value.size,
+            uploader: value.uploader,
+            created_at: value.created_at,
+        }
+    }
+}
+
+impl Scribe for OpenApiAttachmentResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenApiListAttachmentResponse(pub Vec<OpenApiAttachmentResponse>);
+
+impl Scribe for OpenApiListAttachmentResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attachment_refs() {
+        let sha = "a".repeat(64);
+        let content = format!(
+            "![x](/repo/r/attachment/{sha}) [y](attachment/{sha}) attachment/{}",
+            "g".repeat(64)
+        );
+        assert_eq!(attachment_refs(&content), [sha]);
+    }
+}
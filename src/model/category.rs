@@ -0,0 +1,393 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::new_conn,
+    error::{ServiceError, ServiceResult},
+};
+
+use super::{
+    change::{record_change, ChangeKind, ChangeOp},
+    post::query_post,
+    search::index_post,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Category {
+    pub id: String,
+    pub repo_id: String,
+    pub name: String, // unique per repo, case-insensitive
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub sort_order: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+fn find_category_id(conn: &Connection, repo_id: &str, name: &str) -> ServiceResult<Option<String>> {
+    Ok(conn
+        .query_row(
+            "SELECT id FROM category WHERE repo_id = ?1 AND name = ?2",
+            params![repo_id, name],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// the category named `name` in the repo, created at the end of the order if missing.
+pub(crate) fn resolve_category(
+    conn: &Connection,
+    repo_id: &str,
+    name: &str,
+) -> ServiceResult<Category> {
+    if find_category_id(conn, repo_id, name)?.is_none() {
+        conn.execute(
+            "INSERT INTO category (id, repo_id, name, sort_order, created_at, updated_at)
+            SELECT ?1, ?2, ?3, COALESCE(MAX(sort_order), -1) + 1, ?4, ?4 FROM category WHERE repo_id = ?2",
+            params![uuid::Uuid::new_v4().to_string(), repo_id, name, Utc::now()],
+        )?;
+    }
+    let id = find_category_id(conn, repo_id, name)?.unwrap_or_default();
+    query_category(conn, &id)?.ok_or(ServiceError::InternalServerError(format!(
+        "category {name:?} not resolved"
+    )))
+}
+
+/// give posts from before categories existed the category matching their name.
+pub(crate) fn derive_categories(conn: &Connection) -> ServiceResult<usize> {
+    let mut stmt =
+        conn.prepare("SELECT DISTINCT repo_id, category FROM post WHERE category_id IS NULL")?;
+    let pairs = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (repo_id, name) in &pairs {
+        let category = resolve_category(conn, repo_id, name)?;
+        conn.execute(
+            "UPDATE post SET category_id = ?1, category = ?4 WHERE repo_id = ?2 AND category = ?3 AND category_id IS NULL",
+            params![category.id, repo_id, name, category.name],
+        )?;
+    }
+    Ok(pairs.len())
+}
+
+pub(crate) fn query_category(conn: &Connection, id: &str) -> ServiceResult<Option<Category>> {
+    let mut stmt = conn.prepare("SELECT id, repo_id, name, color, icon, sort_order, created_at, updated_at FROM category WHERE id = ?1")?;
+    let mut rows = stmt.query(params![id])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(Category {
+            id: row.get(0)?,
+            repo_id: row.get(1)?,
+            name: row.get(2)?,
+            color: row.get(3)?,
+            icon: row.get(4)?,
+            sort_order: row.get(5)?,
+            created_at: row.get(6)?,
+            updated_at: row.get(7)?,
+        })),
+        None => Ok(None),
+    }
+}
+
+pub fn get_category_by_id(id: &str) -> ServiceResult<Option<Category>> {
+    let conn = new_conn()?;
+    query_category(&conn, id)
+}
+
+pub fn get_category_by_name(repo_id: &str, name: &str) -> ServiceResult<Option<Category>> {
+    let conn = new_conn()?;
+    match find_category_id(&conn, repo_id, name)? {
+        Some(id) => query_category(&conn, &id),
+        None => Ok(None),
+    }
+}
+
+/// a name made of whitespace only is a bad request.
+pub fn validate_category_name(name: &str) -> ServiceResult<()> {
+    if name.trim().is_empty() {
+        return Err(ServiceError::BadRequest(
+            "category name is empty".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// add a category at the end of the order, `sort_order` is ignored.
+pub fn add_category(category: &Category) -> ServiceResult<i64> {
+    validate_category_name(&category.name)?;
+    let conn = new_conn()?;
+    let sort_order = conn.query_row(
+        "INSERT INTO category (id, repo_id, name, color, icon, sort_order, created_at, updated_at)
+        SELECT ?1, ?2, ?3, ?4, ?5, COALESCE(MAX(sort_order), -1) + 1, ?6, ?7 FROM category WHERE repo_id = ?2
+        RETURNING sort_order",
+        params![
+            category.id,
+            category.repo_id,
+            category.name,
+            category.color,
+            category.icon,
+            category.created_at,
+            category.updated_at
+        ],
+        |row| row.get(0),
+    )?;
+    Ok(sort_order)
+}
+
+pub fn get_or_add_category(repo_id: &str, name: &str) -> ServiceResult<Category> {
+    let conn = new_conn()?;
+    resolve_category(&conn, repo_id, name)
+}
+
+pub fn list_categories_by_repo_id(repo_id: &str) -> ServiceResult<Vec<Category>> {
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare("SELECT id, repo_id, name, color, icon, sort_order, created_at, updated_at FROM category WHERE repo_id = ?1 ORDER BY sort_order, name")?;
+    let mut rows = stmt.query(params![repo_id])?;
+    let mut categories = Vec::new();
+    while let Some(row) = rows.next()? {
+        categories.push(Category {
+            id: row.get(0)?,
+            repo_id: row.get(1)?,
+            name: row.get(2)?,
+            color: row.get(3)?,
+            icon: row.get(4)?,
+            sort_order: row.get(5)?,
+            created_at: row.get(6)?,
+            updated_at: row.get(7)?,
+        });
+    }
+    Ok(categories)
+}
+
+fn list_category_post_ids(conn: &Connection, category_id: &str) -> ServiceResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT id FROM post WHERE category_id = ?1")?;
+    let ids = stmt
+        .query_map(params![category_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ids)
+}
+
+/// posts whose category changed: copy the category name, bump their version, and let
+/// the change feed and the search index know about it.
+fn touch_posts(conn: &Connection, post_ids: &[String]) -> ServiceResult<()> {
+    for post_id in post_ids {
+        touch_post(conn, post_id)?;
+    }
+    Ok(())
+}
+
+fn touch_post(conn: &Connection, post_id: &str) -> ServiceResult<()> {
+    conn.execute(
+        "UPDATE post SET category = (SELECT name FROM category WHERE id = post.category_id), version = version + 1 WHERE id = ?1",
+        params![post_id],
+    )?;
+    if let Some(post) = query_post(conn, post_id)? {
+        record_change(
+            conn,
+            &post.repo_id,
+            ChangeKind::Post,
+            &post.id,
+            ChangeOp::Upsert,
+        )?;
+        index_post(conn, &post)?;
+    }
+    Ok(())
+}
+
+/// rename and restyle, a rename is applied to every post in the category.
+pub fn update_category(category: &Category) -> ServiceResult<()> {
+    validate_category_name(&category.name)?;
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let old_name: String = tx.query_row(
+        "SELECT name FROM category WHERE id = ?1",
+        params![category.id],
+        |row| row.get(0),
+    )?;
+    tx.execute(
+        "UPDATE category SET name = ?2, color = ?3, icon = ?4, updated_at = ?5 WHERE id = ?1",
+        params![
+            category.id,
+            category.name,
+            category.color,
+            category.icon,
+            category.updated_at
+        ],
+    )?;
+    if old_name != category.name {
+        let post_ids = list_category_post_ids(&tx, &category.id)?;
+        touch_posts(&tx, &post_ids)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// move every post of `from` into `into` and drop `from`.
+pub fn merge_category(from: &str, into: &str) -> ServiceResult<()> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let post_ids = list_category_post_ids(&tx, from)?;
+    tx.execute(
+        "UPDATE post SET category_id = ?2 WHERE category_id = ?1",
+        params![from, into],
+    )?;
+    tx.execute("DELETE FROM category WHERE id = ?1", params![from])?;
+    touch_posts(&tx, &post_ids)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// delete a category no post uses anymore, returns false if it is still in use.
+pub fn delete_category(id: &str) -> ServiceResult<bool> {
+    let conn = new_conn()?;
+    let deleted = conn.execute(
+        "DELETE FROM category WHERE id = ?1 AND NOT EXISTS (SELECT 1 FROM post WHERE category_id = ?1)",
+        params![id],
+    )?;
+    Ok(deleted > 0)
+}
+
+/// `ids` in their new order, categories left out keep their relative order after them.
+pub fn reorder_categories(repo_id: &str, ids: &[String]) -> ServiceResult<()> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let now = Utc::now();
+    {
+        let mut stmt = tx.prepare(
+            "UPDATE category SET sort_order = ?3, updated_at = ?4 WHERE repo_id = ?1 AND id = ?2",
+        )?;
+        for (order, id) in ids.iter().enumerate() {
+            stmt.execute(params![repo_id, id, order as i64, now])?;
+        }
+    }
+    tx.execute(
+        "UPDATE category SET sort_order = sort_order + ?2 WHERE repo_id = ?1 AND id NOT IN (SELECT value FROM json_each(?3))",
+        params![
+            repo_id,
+            ids.len() as i64,
+            serde_json::to_string(ids).unwrap_or_default()
+        ],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiPushCategoryRequest {
+    pub name: String,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiMergeCategoryRequest {
+    pub into: String, // target category id
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiReorderCategoryRequest {
+    pub ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiGetCategoryResponse {
+    pub id: String,
+    pub repo_id: String,
+    pub name: String,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub sort_order: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<Category> for OpenApiGetCategoryResponse {
+    fn from(value: Category) -> Self {
+        Self {
+            id: value.id,
+            repo_id: value.repo_id,
+            name: value.name,
+            color: value.color,
+            icon: value.icon,
+            sort_order: value.sort_order,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+impl Scribe for OpenApiGetCategoryResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenApiListCategoryResponse(pub Vec<OpenApiGetCategoryResponse>);
+
+impl Scribe for OpenApiListCategoryResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        repo::{add_repo, Repo, RepoStatus},
+        user::{add_user, User},
+    };
+
+    #[test]
+    fn test_category_order() -> anyhow::Result<()> {
+        crate::db::init_db()?;
+        let user = User::new(uuid::Uuid::new_v4().to_string(), "password".into());
+        add_user(&user)?;
+        let repo_id = uuid::Uuid::new_v4().to_string();
+        add_repo(&Repo {
+            id: repo_id.clone(),
+            name: "categories".into(),
+            owner: user.id,
+            description: String::new(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            status: RepoStatus::Normal,
+            public: false,
+            topics: vec![],
+            version: 1,
+            search_tokenizer: None,
+        })?;
+        let notes = get_or_add_category(&repo_id, "Notes")?;
+        let todo = get_or_add_category(&repo_id, "todo")?;
+        assert_eq!(get_or_add_category(&repo_id, "notes")?.id, notes.id);
+        assert_eq!((notes.sort_order, todo.sort_order), (0, 1));
+        let blank = Category {
+            name: " \t".into(),
+            ..get_category_by_id(&notes.id)?.unwrap()
+        };
+        assert!(matches!(
+            update_category(&blank),
+            Err(ServiceError::BadRequest(_))
+        ));
+
+        reorder_categories(&repo_id, std::slice::from_ref(&todo.id))?;
+        let names: Vec<_> = list_categories_by_repo_id(&repo_id)?
+            .into_iter()
+            .map(|category| category.name)
+            .collect();
+        assert_eq!(names, ["todo", "Notes"]);
+
+        merge_category(&todo.id, &notes.id)?;
+        assert!(get_category_by_id(&todo.id)?.is_none());
+        assert!(delete_category(&notes.id)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::new_conn,
+    error::{ServiceError, ServiceResult},
+};
+
+use super::{comment::OpenApiCommentSummaryResponse, post::PostSummary};
+
+#[derive(Debug, Clone, Copy)]
+pub enum ChangeKind {
+    Post,
+    Comment,
+}
+
+impl FromStr for ChangeKind {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "post" => Ok(Self::Post),
+            "comment" => Ok(Self::Comment),
+            _ => Err(ServiceError::InternalServerError(
+                "invalid change kind".to_owned(),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Self::Post => "post",
+            Self::Comment => "comment",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ChangeOp {
+    Upsert,
+    Delete,
+}
+
+impl FromStr for ChangeOp {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "upsert" => Ok(Self::Upsert),
+            "delete" => Ok(Self::Delete),
+            _ => Err(ServiceError::InternalServerError(
+                "invalid change op".to_owned(),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ChangeOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            Self::Upsert => "upsert",
+            Self::Delete => "delete",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// record the latest change of an entity, older entries of the same entity in the repo are
+/// dropped so each repo's feed only ever holds one row per post or comment.
+pub(crate) fn record_change(
+    conn: &Connection,
+    repo_id: &str,
+    kind: ChangeKind,
+    entity_id: &str,
+    op: ChangeOp,
+) -> ServiceResult<()> {
+    conn.execute(
+        "DELETE FROM change_log WHERE kind = ?1 AND entity_id = ?2 AND repo_id = ?3",
+        params![kind.to_string(), entity_id, repo_id],
+    )?;
+    conn.execute(
+        "INSERT INTO change_log (repo_id, kind, entity_id, op, changed_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![repo_id, kind.to_string(), entity_id, op.to_string(), Utc::now()],
+    )?;
+    Ok(())
+}
+
+/// opaque to clients, the hex of the last seen `change_log.seq`.
+pub fn encode_cursor(seq: i64) -> String {
+    format!("{seq:x}")
+}
+
+pub fn decode_cursor(cursor: &str) -> ServiceResult<i64> {
+    i64::from_str_radix(cursor, 16)
+        .map_err(|_| ServiceError::BadRequest(format!("invalid cursor {cursor:?}")))
+}
+
+/// reject a cursor from before deletes that gc has dropped since, the client missed
+/// them and has to resync from the beginning.
+pub fn check_cursor(after: i64) -> ServiceResult<()> {
+    let conn = new_conn()?;
+    let horizon: Option<i64> = conn
+        .query_row("SELECT seq FROM change_gc WHERE id = 1", [], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    match horizon {
+        Some(horizon) if after > 0 && after < horizon => Err(ServiceError::Gone(format!(
+            "cursor {} expired, resync without one",
+            encode_cursor(after)
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug)]
+pub struct Change {
+    pub seq: i64,
+    pub kind: ChangeKind,
+    pub entity_id: String,
+    pub repo_id: String,
+    pub op: ChangeOp,
+    pub changed_at: DateTime<Utc>,
+    pub post: Option<PostSummary>,
+    pub comment: Option<OpenApiCommentSummaryResponse>,
+    pub deleted_by: Option<String>,
+}
+
+const CHANGE_SQL: &str = "
+    SELECT c.seq, c.kind, c.entity_id, c.repo_id, c.op, c.changed_at,
+        p.title, p.category, p.created_at, p.updated_at, p.version,
+        m.post_id, m.updated_at, t.deleted_by
+    FROM change_log c
+    LEFT JOIN post p ON c.kind = 'post' AND c.op = 'upsert' AND p.id = c.entity_id
+        AND (p.state = 'published' OR p.author = ?4)
+    LEFT JOIN comment m ON c.kind = 'comment' AND c.op = 'upsert' AND m.id = c.entity_id
+        AND m.post_id IN (SELECT id FROM post WHERE state = 'published' OR author = ?4)
+    LEFT JOIN tombstone t ON c.op = 'delete' AND t.kind = c.kind AND t.entity_id = c.entity_id
+    WHERE c.seq > ?1";
+
+/// changes of one repo after `after`, in feed order. posts `viewer` cannot see, and their
+/// comments, are reported as deleted.
+pub fn list_repo_changes(
+    repo_id: &str,
+    viewer: &str,
+    after: i64,
+    limit: u32,
+) -> ServiceResult<Vec<Change>> {
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare(&format!(
+        "{CHANGE_SQL} AND c.repo_id = ?2 ORDER BY c.seq LIMIT ?3"
+    ))?;
+    query_changes(&mut stmt, params![after, repo_id, limit, viewer])
+}
+
+/// changes of every repo the user owns or subscribes, after `after`, in feed order.
+pub fn list_user_changes(user_id: &str, after: i64, limit: u32) -> ServiceResult<Vec<Change>> {
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare(&format!(
+        "{CHANGE_SQL} AND c.repo_id IN (
+            SELECT id FROM repo WHERE owner = ?2 AND status = 'normal'
+            UNION SELECT repo_id FROM subscribe WHERE user_id = ?2
+        ) ORDER BY c.seq LIMIT ?3"
+    ))?;
+    query_changes(&mut stmt, params![after, user_id, limit, user_id])
+}
+
+fn query_changes(
+    stmt: &mut rusqlite::Statement,
+    params: &[&dyn rusqlite::ToSql],
+) -> ServiceResult<Vec<Change>> {
+    let mut rows = stmt.query(params)?;
+    let mut changes = Vec::new();
+    while let Some(row) = rows.next()? {
+        let kind = ChangeKind::from_str(&row.get::<_, String>(1)?)?;
+        let entity_id: String = row.get(2)?;
+        let repo_id: String = row.get(3)?;
+        let mut op = ChangeOp::from_str(&row.get::<_, String>(4)?)?;
+        let changed_at = row.get(5)?;
+        let (mut post, mut comment) = (None, None);
+        if let ChangeOp::Upsert = op {
+            match kind {
+                ChangeKind::Post => {
+                    post = row
+                        .get::<_, Option<String>>(6)?
+                        .map(|title| -> rusqlite::Result<PostSummary> {
+                            Ok(PostSummary {
+                                id: entity_id.clone(),
+                                title,
+                                category: row.get(7)?,
+                                created_at: row.get(8)?,
+                                updated_at: row.get(9)?,
+                                version: row.get(10)?,
+                            })
+                        })
+                        .transpose()?;
+                }
+                ChangeKind::Comment => {
+                    comment = row
+                        .get::<_, Option<String>>(11)?
+                        .map(|post_id| -> rusqlite::Result<_> {
+                            Ok(OpenApiCommentSummaryResponse {
+                                id: entity_id.clone(),
+                                post_id,
+                                repo_id: repo_id.clone(),
+                                updated_at: row.get(12)?,
+                            })
+                        })
+                        .transpose()?;
+                }
+            }
+            // the row is gone without a delete being recorded, or hidden from the viewer,
+            // report it as deleted
+            if post.is_none() && comment.is_none() {
+                op = ChangeOp::Delete;
+            }
+        }
+        changes.push(Change {
+            seq: row.get(0)?,
+            kind,
+            entity_id,
+            repo_id,
+            op,
+            changed_at,
+            post,
+            comment,
+            deleted_by: row.get(13)?,
+        });
+    }
+    Ok(changes)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiChangeResponse {
+    pub kind: String, // post | comment
+    pub id: String,
+    pub repo_id: String,
+    pub op: String, // upsert | delete
+    pub changed_at: DateTime<Utc>,
+    pub post: Option<PostSummary>,
+    pub comment: Option<OpenApiCommentSummaryResponse>,
+    pub deleted_by: Option<String>,
+}
+
+impl From<Change> for OpenApiChangeResponse {
+    fn from(value: Change) -> Self {
+        Self {
+            kind: value.kind.to_string(),
+            id: value.entity_id,
+            repo_id: value.repo_id,
+            op: value.op.to_string(),
+            changed_at: value.changed_at,
+            post: value.post,
+            comment: value.comment,
+            deleted_by: value.deleted_by,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiListChangeResponse {
+    pub changes: Vec<OpenApiChangeResponse>,
+    pub next_cursor: String, // pass back as `cursor`, also when there is nothing more yet
+    pub has_more: bool,
+}
+
+impl Scribe for OpenApiListChangeResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
@@ -1,10 +1,20 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
-use rusqlite::params;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, ToSql};
 use salvo::{writing::Json, Scribe};
 use serde::{Deserialize, Serialize};
 
 use crate::{db::new_conn, error::ServiceResult};
 
+use super::{
+    change::{record_change, ChangeKind, ChangeOp},
+    listing::{ListColumns, ListQuery, Page},
+    reaction::{erase_reactions, OpenApiReactionResponse},
+    search::{index_comment, unindex},
+    tombstone::{record_tombstone, Tombstone},
+};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Comment {
     pub id: String,
@@ -18,8 +28,9 @@ pub struct Comment {
 }
 
 pub fn add_comment(comment: &Comment) -> ServiceResult<()> {
-    let conn = new_conn()?;
-    conn.execute(
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    tx.execute(
         "INSERT INTO comment (id, post_id, repo_id, content, created_at, updated_at, author, parent_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
         params![
             comment.id,
@@ -32,6 +43,15 @@ pub fn add_comment(comment: &Comment) -> ServiceResult<()> {
             comment.parent_id
         ],
     )?;
+    record_change(
+        &tx,
+        &comment.repo_id,
+        ChangeKind::Comment,
+        &comment.id,
+        ChangeOp::Upsert,
+    )?;
+    index_comment(&tx, comment)?;
+    tx.commit()?;
     Ok(())
 }
 
@@ -55,13 +75,28 @@ pub fn get_comment_by_id(id: &str) -> ServiceResult<Option<Comment>> {
     }
 }
 
-pub fn list_comments_by_post_id(post_id: &str) -> ServiceResult<Vec<Comment>> {
+const COMMENT_LIST_COLUMNS: ListColumns = ListColumns {
+    id: "id",
+    created_at: "created_at",
+    updated_at: "updated_at",
+    title: None,
+    author: Some("author"),
+    category: None,
+};
+
+pub fn list_comments_by_post_id(post_id: &str, query: &ListQuery) -> ServiceResult<Page<Comment>> {
     let conn = new_conn()?;
-    let mut stmt = conn.prepare("SELECT id, post_id, repo_id, content, created_at, updated_at, author, parent_id FROM comment WHERE post_id = ?1")?;
-    let mut rows = stmt.query(params![post_id])?;
+    let (list_sql, list_params) = query.sql(&COMMENT_LIST_COLUMNS, 2)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, post_id, repo_id, content, created_at, updated_at, author, parent_id, {} FROM comment WHERE post_id = ?1{list_sql}",
+        query.sort_column(&COMMENT_LIST_COLUMNS)?
+    ))?;
+    let mut values: Vec<Box<dyn ToSql>> = vec![Box::new(post_id.to_owned())];
+    values.extend(list_params);
+    let mut rows = stmt.query(params_from_iter(values.iter()))?;
     let mut comments = Vec::new();
     while let Some(row) = rows.next()? {
-        comments.push(Comment {
+        let comment = Comment {
             id: row.get(0)?,
             post_id: row.get(1)?,
             repo_id: row.get(2)?,
@@ -70,20 +105,122 @@ pub fn list_comments_by_post_id(post_id: &str) -> ServiceResult<Vec<Comment>> {
             updated_at: row.get(5)?,
             author: row.get(6)?,
             parent_id: row.get(7)?,
-        });
+        };
+        comments.push((comment, query.cursor(row, 0, 8)?));
     }
-    Ok(comments)
+    Ok(query.page(comments))
 }
 
-pub fn delete_comment_by_id(id: &str) -> ServiceResult<()> {
+/// the comments of each of the posts in creation order, in one query.
+pub fn list_comments_by_post_ids(
+    post_ids: &[String],
+) -> ServiceResult<HashMap<String, Vec<Comment>>> {
     let conn = new_conn()?;
-    conn.execute("DELETE FROM comment WHERE id = ?1", params![id])?;
+    let mut stmt = conn.prepare(
+        "SELECT id, post_id, repo_id, content, created_at, updated_at, author, parent_id FROM comment
+        WHERE post_id IN (SELECT value FROM json_each(?1)) ORDER BY created_at, id",
+    )?;
+    let mut rows = stmt.query(params![serde_json::json!(post_ids).to_string()])?;
+    let mut comments: HashMap<String, Vec<Comment>> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let comment = Comment {
+            id: row.get(0)?,
+            post_id: row.get(1)?,
+            repo_id: row.get(2)?,
+            content: row.get(3)?,
+            created_at: row.get(4)?,
+            updated_at: row.get(5)?,
+            author: row.get(6)?,
+            parent_id: row.get(7)?,
+        };
+        comments
+            .entry(comment.post_id.clone())
+            .or_default()
+            .push(comment);
+    }
+    Ok(comments)
+}
+
+/// move the comments of a post along with it, they are deleted from the old repo's feed.
+pub(crate) fn move_post_comments(
+    conn: &Connection,
+    post_id: &str,
+    from_repo_id: &str,
+    to_repo_id: &str,
+) -> ServiceResult<()> {
+    let mut stmt = conn.prepare(
+        "UPDATE comment SET repo_id = ?2 WHERE post_id = ?1
+        RETURNING id, post_id, repo_id, content, created_at, updated_at, author, parent_id",
+    )?;
+    let comments = stmt
+        .query_map(params![post_id, to_repo_id], |row| {
+            Ok(Comment {
+                id: row.get(0)?,
+                post_id: row.get(1)?,
+                repo_id: row.get(2)?,
+                content: row.get(3)?,
+                created_at: row.get(4)?,
+                updated_at: row.get(5)?,
+                author: row.get(6)?,
+                parent_id: row.get(7)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for comment in &comments {
+        record_change(
+            conn,
+            from_repo_id,
+            ChangeKind::Comment,
+            &comment.id,
+            ChangeOp::Delete,
+        )?;
+        record_change(
+            conn,
+            to_repo_id,
+            ChangeKind::Comment,
+            &comment.id,
+            ChangeOp::Upsert,
+        )?;
+        index_comment(conn, comment)?;
+    }
+    Ok(())
+}
+
+/// delete the comment, leaving a tombstone signed by `deleted_by`.
+pub fn delete_comment_by_id(id: &str, deleted_by: &str) -> ServiceResult<()> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let repo_id: Option<String> = tx
+        .query_row(
+            "SELECT repo_id FROM comment WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    tx.execute("DELETE FROM comment WHERE id = ?1", params![id])?;
+    erase_reactions(&tx, ChangeKind::Comment, id)?;
+    unindex(&tx, ChangeKind::Comment, id)?;
+    if let Some(repo_id) = repo_id {
+        record_change(&tx, &repo_id, ChangeKind::Comment, id, ChangeOp::Delete)?;
+        record_tombstone(
+            &tx,
+            &Tombstone {
+                kind: ChangeKind::Comment,
+                entity_id: id.to_owned(),
+                repo_id,
+                deleted_at: Utc::now(),
+                deleted_by: deleted_by.to_owned(),
+            },
+        )?;
+    }
+    tx.commit()?;
     Ok(())
 }
 
 pub fn update_comment(comment: &Comment) -> ServiceResult<()> {
-    let conn = new_conn()?;
-    conn.execute(
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    tx.execute(
         "UPDATE comment SET post_id = ?2, repo_id = ?3, content = ?4, updated_at = ?5, author = ?6, parent_id = ?7 WHERE id = ?1",
         params![
             comment.id,
@@ -95,6 +232,15 @@ pub fn update_comment(comment: &Comment) -> ServiceResult<()> {
             comment.parent_id
         ],
     )?;
+    record_change(
+        &tx,
+        &comment.repo_id,
+        ChangeKind::Comment,
+        &comment.id,
+        ChangeOp::Upsert,
+    )?;
+    index_comment(&tx, comment)?;
+    tx.commit()?;
     Ok(())
 }
 
@@ -117,6 +263,8 @@ pub struct OpenApiGetCommentResponse {
     pub updated_at: DateTime<Utc>,
     pub author: String,
     pub parent_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reactions: Option<Vec<OpenApiReactionResponse>>,
 }
 
 impl Scribe for OpenApiGetCommentResponse {
@@ -136,6 +284,7 @@ impl From<Comment> for OpenApiGetCommentResponse {
             updated_at: value.updated_at,
             author: value.author,
             parent_id: value.parent_id,
+            reactions: None,
         }
     }
 }
@@ -166,10 +315,30 @@ impl Scribe for OpenApiCommentSummaryResponse {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct OpenApiListCommentResponse(pub Vec<OpenApiGetCommentResponse>);
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiListCommentResponse {
+    pub comments: Vec<OpenApiGetCommentResponse>,
+    pub next_cursor: Option<String>, // pass back as `cursor` for the next page, none on the last one
+    #[serde(skip)]
+    pub paged: bool, // rendered as a bare array when not, like before paging
+}
+
+impl From<Page<Comment>> for OpenApiListCommentResponse {
+    fn from(page: Page<Comment>) -> Self {
+        Self {
+            comments: page.items.into_iter().map(Into::into).collect(),
+            next_cursor: page.next_cursor,
+            paged: page.paged,
+        }
+    }
+}
 
 impl Scribe for OpenApiListCommentResponse {
     fn render(self, res: &mut salvo::Response) {
-        res.render(Json(&self));
+        if self.paged {
+            res.render(Json(&self));
+        } else {
+            res.render(Json(&self.comments));
+        }
     }
 }
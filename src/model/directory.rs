@@ -0,0 +1,155 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::new_conn,
+    error::{ServiceError, ServiceResult},
+    model::repo::{list_repo_topics, parse_tokenizer, Repo, RepoStatus},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum DirectorySort {
+    Subscribers,
+    Activity,
+}
+
+impl FromStr for DirectorySort {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "subscribers" => Ok(Self::Subscribers),
+            "activity" => Ok(Self::Activity),
+            _ => Err(ServiceError::BadRequest(format!("invalid sort {s:?}"))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DirectoryQuery {
+    pub keyword: Option<String>, // matches name, description or topic
+    pub topic: Option<String>,   // exact topic
+    pub sort: DirectorySort,
+    pub page: u32,
+    pub limit: u32,
+}
+
+#[derive(Debug)]
+pub struct DirectoryEntry {
+    pub repo: Repo,
+    pub subscribers: i64,
+    pub last_active_at: DateTime<Utc>,
+}
+
+/// the `MAX()` picks the later of the repo's own update and its latest post.
+const DIRECTORY_SQL: &str = "
+    SELECT r.id, r.name, r.owner, r.description, r.created_at, r.updated_at, r.status, r.public, r.version, r.search_tokenizer,
+        (SELECT COUNT(*) FROM subscribe s WHERE s.repo_id = r.id) AS subscribers,
+        MAX(r.updated_at, COALESCE((SELECT MAX(p.updated_at) FROM post p WHERE p.repo_id = r.id), r.updated_at)) AS last_active_at
+    FROM repo r
+    WHERE r.status = 'normal' AND r.public = 1
+        AND (?1 IS NULL OR r.name LIKE ?1 ESCAPE '\\' OR r.description LIKE ?1 ESCAPE '\\'
+            OR EXISTS (SELECT 1 FROM repo_topic t WHERE t.repo_id = r.id AND t.topic LIKE ?1 ESCAPE '\\'))
+        AND (?2 IS NULL OR EXISTS (SELECT 1 FROM repo_topic t WHERE t.repo_id = r.id AND t.topic = ?2))";
+
+/// `text` matched literally by `LIKE ... ESCAPE '\'`.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// list public repos matching the query, returns the page and the total count.
+pub fn search_public_repos(query: &DirectoryQuery) -> ServiceResult<(Vec<DirectoryEntry>, i64)> {
+    let conn = new_conn()?;
+    let keyword = query
+        .keyword
+        .as_ref()
+        .map(|k| format!("%{}%", escape_like(k)));
+    let topic = query.topic.as_ref().map(|t| t.trim().to_lowercase());
+
+    let total: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM ({DIRECTORY_SQL})"),
+        params![keyword, topic],
+        |row| row.get(0),
+    )?;
+
+    let order_by = match query.sort {
+        DirectorySort::Subscribers => "subscribers DESC, last_active_at DESC",
+        DirectorySort::Activity => "last_active_at DESC, subscribers DESC",
+    };
+    let mut stmt = conn.prepare(&format!(
+        "{DIRECTORY_SQL} ORDER BY {order_by}, r.id LIMIT ?3 OFFSET ?4"
+    ))?;
+    let offset = query.page.saturating_sub(1) as i64 * query.limit as i64;
+    let mut rows = stmt.query(params![keyword, topic, query.limit, offset])?;
+    let mut entries = Vec::new();
+    while let Some(row) = rows.next()? {
+        let repo_id: String = row.get(0)?;
+        entries.push(DirectoryEntry {
+            repo: Repo {
+                topics: list_repo_topics(&conn, &repo_id)?,
+                id: repo_id,
+                name: row.get(1)?,
+                owner: row.get(2)?,
+                description: row.get(3)?,
+                created_at: row.get(4)?,
+                updated_at: row.get(5)?,
+                status: RepoStatus::from_str(&row.get::<_, String>(6)?)?,
+                public: row.get(7)?,
+                version: row.get(8)?,
+                search_tokenizer: parse_tokenizer(row.get(9)?)?,
+            },
+            subscribers: row.get(10)?,
+            last_active_at: row.get(11)?,
+        });
+    }
+    Ok((entries, total))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiDirectoryRepoResponse {
+    pub id: String,
+    pub name: String,
+    pub owner: String,
+    pub description: String,
+    pub topics: Vec<String>,
+    pub subscribers: i64,
+    pub created_at: DateTime<Utc>,
+    pub last_active_at: DateTime<Utc>,
+}
+
+impl From<DirectoryEntry> for OpenApiDirectoryRepoResponse {
+    fn from(value: DirectoryEntry) -> Self {
+        Self {
+            id: value.repo.id,
+            name: value.repo.name,
+            owner: value.repo.owner,
+            description: value.repo.description,
+            topics: value.repo.topics,
+            subscribers: value.subscribers,
+            created_at: value.repo.created_at,
+            last_active_at: value.last_active_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiDirectoryResponse {
+    pub repos: Vec<OpenApiDirectoryRepoResponse>,
+    pub page: u32,
+    pub limit: u32,
+    pub total: i64,
+}
+
+impl Scribe for OpenApiDirectoryResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
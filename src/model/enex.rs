@@ -0,0 +1,625 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use md5::{Digest, Md5};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use crate::error::{ServiceError, ServiceResult};
+
+use super::{
+    export::path_segment,
+    import::{derived_id, push_imported, store_attachment, ImportStatus, OpenApiImportResult},
+    post::OpenApiPushPostRequest,
+};
+
+/// the html entities the enml doctype declares that show up in notes, beyond xml's own.
+fn resolve_entity(entity: &str) -> Option<&'static str> {
+    match entity {
+        "lt" => Some("<"),
+        "gt" => Some(">"),
+        "amp" => Some("&"),
+        "apos" => Some("'"),
+        "quot" => Some("\""),
+        "nbsp" => Some("\u{a0}"),
+        "ensp" | "emsp" | "thinsp" => Some(" "),
+        "ndash" => Some("–"),
+        "mdash" => Some("—"),
+        "hellip" => Some("…"),
+        "lsquo" => Some("‘"),
+        "rsquo" => Some("’"),
+        "ldquo" => Some("“"),
+        "rdquo" => Some("”"),
+        "laquo" => Some("«"),
+        "raquo" => Some("»"),
+        "bull" => Some("•"),
+        "middot" => Some("·"),
+        "copy" => Some("©"),
+        "reg" => Some("®"),
+        "trade" => Some("™"),
+        "deg" => Some("°"),
+        "times" => Some("×"),
+        "euro" => Some("€"),
+        _ => None,
+    }
+}
+
+fn attribute(start: &BytesStart, name: &str) -> Option<String> {
+    start
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|attr| attr.unescape_value_with(resolve_entity).ok())
+        .map(|value| value.into_owned())
+}
+
+/// an attachment made from a resource of the note, by the md5 `en-media` refers to it with.
+#[derive(Debug, Clone)]
+pub struct Media {
+    pub sha256: String,
+    pub name: String,
+    pub mime: String,
+}
+
+/// what to write when an element closes.
+enum Close {
+    Nothing,
+    Text(&'static str),
+    Link(String),
+    Block,
+    Line,
+    Heading,
+    List,
+    Item,
+    Pre,
+    Quote,
+    Row,
+    Cell,
+}
+
+/// markdown written while walking the enml.
+#[derive(Default)]
+struct Markdown {
+    out: String,
+    lists: Vec<Option<u32>>, // the next number of ordered lists
+    pre: usize,
+    quote: usize,
+    rows: usize, // rows of the current table written so far
+    cells: usize,
+    in_cell: usize, // blocks and breaks inside cells would end the row
+}
+
+impl Markdown {
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    fn line(&mut self) {
+        if !self.at_line_start() {
+            self.out.push('\n');
+        }
+    }
+
+    fn block(&mut self) {
+        self.line();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    /// write `s`, starting new lines inside quotes with their marker.
+    fn push(&mut self, s: &str) {
+        for (i, part) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.out.push('\n');
+            }
+            if !part.is_empty() && self.quote > 0 && self.at_line_start() {
+                self.out.push_str(&"> ".repeat(self.quote));
+            }
+            self.out.push_str(part);
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.pre > 0 {
+            self.push(text);
+            return;
+        }
+        let mut collapsed = String::with_capacity(text.len());
+        for c in text.chars() {
+            if c.is_whitespace() && c != '\u{a0}' {
+                if !collapsed.ends_with(' ') {
+                    collapsed.push(' ');
+                }
+            } else {
+                collapsed.push(c);
+            }
+        }
+        let collapsed = if self.at_line_start() || self.out.ends_with(' ') {
+            collapsed.trim_start()
+        } else {
+            &collapsed
+        };
+        self.push(collapsed);
+    }
+
+    fn open(&mut self, start: &BytesStart, media: &HashMap<String, Media>, repo_id: &str) -> Close {
+        let name = String::from_utf8_lossy(start.local_name().as_ref()).to_lowercase();
+        match name.as_str() {
+            "div" if attribute(start, "style").is_some_and(|s| s.contains("-en-codeblock")) => {
+                self.block();
+                self.push("```\n");
+                self.pre += 1;
+                Close::Pre
+            }
+            "pre" => {
+                self.block();
+                self.push("```\n");
+                self.pre += 1;
+                Close::Pre
+            }
+            "p" | "div" | "br" if self.in_cell > 0 => {
+                if !self.out.ends_with(' ') {
+                    self.push(" ");
+                }
+                Close::Nothing
+            }
+            "p" | "div" | "en-note" | "center" | "section" | "article" => {
+                if self.pre > 0 {
+                    self.line();
+                    Close::Line
+                } else {
+                    self.block();
+                    Close::Block
+                }
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse().unwrap_or(1);
+                self.block();
+                self.push(&format!("{} ", "#".repeat(level)));
+                Close::Heading
+            }
+            "br" => {
+                self.push("\n");
+                Close::Nothing
+            }
+            "hr" => {
+                self.block();
+                self.push("---");
+                self.block();
+                Close::Nothing
+            }
+            "b" | "strong" => {
+                self.push("**");
+                Close::Text("**")
+            }
+            "i" | "em" => {
+                self.push("_");
+                Close::Text("_")
+            }
+            "s" | "strike" | "del" => {
+                self.push("~~");
+                Close::Text("~~")
+            }
+            "code" if self.pre == 0 => {
+                self.push("`");
+                Close::Text("`")
+            }
+            "a" => match attribute(start, "href") {
+                Some(href) if !href.is_empty() => {
+                    self.push("[");
+                    Close::Link(href)
+                }
+                _ => Close::Nothing,
+            },
+            "ul" | "ol" => {
+                if self.lists.is_empty() {
+                    self.block();
+                } else {
+                    self.line();
+                }
+                self.lists.push((name == "ol").then_some(1));
+                Close::List
+            }
+            "li" => {
+                self.line();
+                let depth = self.lists.len().max(1) - 1;
+                let marker = match self.lists.last_mut() {
+                    Some(Some(next)) => {
+                        *next += 1;
+                        format!("{}. ", *next - 1)
+                    }
+                    _ => "- ".to_owned(),
+                };
+                self.push(&format!("{}{marker}", "  ".repeat(depth)));
+                Close::Item
+            }
+            "blockquote" => {
+                self.block();
+                self.quote += 1;
+                Close::Quote
+            }
+            "table" => {
+                self.block();
+                self.rows = 0;
+                Close::Block
+            }
+            "tr" => {
+                self.line();
+                self.cells = 0;
+                self.push("|");
+                Close::Row
+            }
+            "td" | "th" => {
+                self.push(" ");
+                self.in_cell += 1;
+                Close::Cell
+            }
+            "en-todo" => {
+                let checked = attribute(start, "checked").is_some_and(|c| c == "true");
+                if self.at_line_start() && self.lists.is_empty() {
+                    self.push("- ");
+                }
+                self.push(if checked { "[x] " } else { "[ ] " });
+                Close::Nothing
+            }
+            "en-media" => {
+                if let Some(media) = attribute(start, "hash").and_then(|hash| media.get(&hash)) {
+                    let url = format!("/repo/{repo_id}/attachment/{}", media.sha256);
+                    let bang = if media.mime.starts_with("image/") {
+                        "!"
+                    } else {
+                        ""
+                    };
+                    self.push(&format!("{bang}[{}]({url})", media.name));
+                }
+                Close::Nothing
+            }
+            "img" => {
+                if let Some(src) = attribute(start, "src").filter(|src| src.starts_with("http")) {
+                    let alt = attribute(start, "alt").unwrap_or_default();
+                    self.push(&format!("![{alt}]({src})"));
+                }
+                Close::Nothing
+            }
+            _ => Close::Nothing,
+        }
+    }
+
+    fn close(&mut self, close: Close) {
+        match close {
+            Close::Nothing => {}
+            Close::Text(s) => self.push(s),
+            Close::Link(href) => self.push(&format!("]({href})")),
+            Close::Block | Close::Heading => self.block(),
+            Close::Line | Close::Item => self.line(),
+            Close::List => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.block();
+                }
+            }
+            Close::Pre => {
+                self.pre -= 1;
+                self.line();
+                self.push("```");
+                self.block();
+            }
+            Close::Quote => {
+                self.quote -= 1;
+                self.block();
+            }
+            Close::Row => {
+                self.line();
+                if self.rows == 0 {
+                    self.push(&format!("|{}\n", " --- |".repeat(self.cells.max(1))));
+                }
+                self.rows += 1;
+            }
+            Close::Cell => {
+                self.in_cell -= 1;
+                self.cells += 1;
+                self.push(" |");
+            }
+        }
+    }
+}
+
+/// markdown of an enml note, `en-media` pointing at the attachments in `media`.
+/// anything that does not parse ends the conversion, keeping what was read.
+pub fn enml_to_markdown(enml: &str, media: &HashMap<String, Media>, repo_id: &str) -> String {
+    let mut reader = Reader::from_str(enml);
+    reader.config_mut().check_end_names = false;
+    let mut markdown = Markdown::default();
+    let mut open: Vec<Close> = Vec::new();
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(start)) => {
+                let close = markdown.open(&start, media, repo_id);
+                open.push(close);
+            }
+            Ok(Event::Empty(start)) => {
+                let close = markdown.open(&start, media, repo_id);
+                markdown.close(close);
+            }
+            Ok(Event::End(_)) => {
+                if let Some(close) = open.pop() {
+                    markdown.close(close);
+                }
+            }
+            Ok(Event::Text(text)) => {
+                if let Ok(text) = text.unescape_with(resolve_entity) {
+                    markdown.text(&text);
+                }
+            }
+            Ok(Event::CData(text)) => markdown.text(&String::from_utf8_lossy(&text)),
+            Ok(Event::Eof) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+    let out = markdown.out.trim().replace('\u{a0}', " ");
+    let mut collapsed = String::with_capacity(out.len());
+    for line in out.split('\n') {
+        let line = line.trim_end();
+        if line.is_empty() && collapsed.ends_with("\n\n") {
+            continue;
+        }
+        collapsed.push_str(line);
+        collapsed.push('\n');
+    }
+    collapsed
+}
+
+#[derive(Debug, Default)]
+struct Resource {
+    data: String, // base64, without whitespace
+    /// the data went past the limit of `read_notes` and was dropped.
+    oversized: bool,
+    mime: String,
+    name: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Note {
+    title: String,
+    content: String,
+    created: Option<DateTime<Utc>>,
+    updated: Option<DateTime<Utc>>,
+    tags: Vec<String>,
+    resources: Vec<Resource>,
+}
+
+fn parse_enex_time(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value.trim(), "%Y%m%dT%H%M%SZ")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+/// read the notes of an enex file one at a time, calling `on_note` with each. resource
+/// data past `max_data_len` base64 chars is dropped, the resource marked oversized.
+fn read_notes(
+    path: &Path,
+    max_data_len: usize,
+    mut on_note: impl FnMut(Note) -> ServiceResult<()>,
+) -> ServiceResult<()> {
+    let mut reader = Reader::from_reader(BufReader::new(fs::File::open(path)?));
+    let invalid = |err: quick_xml::Error| ServiceError::BadRequest(format!("invalid enex: {err}"));
+    let mut buf = Vec::new();
+    let mut elements: Vec<String> = Vec::new();
+    let mut note: Option<Note> = None;
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(invalid)?;
+        let text = match &event {
+            Event::Start(start) => {
+                let name = String::from_utf8_lossy(start.local_name().as_ref()).into_owned();
+                match name.as_str() {
+                    "note" => note = Some(Note::default()),
+                    "resource" => {
+                        if let Some(note) = note.as_mut() {
+                            note.resources.push(Resource::default());
+                        }
+                    }
+                    _ => {}
+                }
+                elements.push(name);
+                None
+            }
+            Event::End(_) => {
+                if elements.pop().as_deref() == Some("note") {
+                    if let Some(note) = note.take() {
+                        on_note(note)?;
+                    }
+                }
+                None
+            }
+            Event::Text(text) => Some(
+                text.unescape_with(resolve_entity)
+                    .map_err(invalid)?
+                    .into_owned(),
+            ),
+            Event::CData(text) => Some(String::from_utf8_lossy(text).into_owned()),
+            Event::Eof => break,
+            _ => None,
+        };
+        if let (Some(text), Some(note)) = (text, note.as_mut()) {
+            let parent = elements.len().checked_sub(2).map(|i| elements[i].as_str());
+            match (parent, elements.last().map(String::as_str)) {
+                (Some("note"), Some("title")) => note.title.push_str(&text),
+                (Some("note"), Some("content")) => note.content.push_str(&text),
+                (Some("note"), Some("created")) => note.created = parse_enex_time(&text),
+                (Some("note"), Some("updated")) => note.updated = parse_enex_time(&text),
+                (Some("note"), Some("tag")) => note.tags.push(text.trim().to_owned()),
+                (Some("resource"), Some("data")) => {
+                    if let Some(resource) = note.resources.last_mut() {
+                        if !resource.oversized {
+                            resource
+                                .data
+                                .extend(text.chars().filter(|c| !c.is_whitespace()));
+                            if resource.data.len() > max_data_len {
+                                resource.data = String::new();
+                                resource.oversized = true;
+                            }
+                        }
+                    }
+                }
+                (Some("resource"), Some("mime")) => {
+                    if let Some(resource) = note.resources.last_mut() {
+                        resource.mime = text.trim().to_owned();
+                    }
+                }
+                (Some("resource-attributes"), Some("file-name")) => {
+                    if let Some(resource) = note.resources.last_mut() {
+                        resource.name = Some(text.trim().to_owned());
+                    }
+                }
+                _ => {}
+            }
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+/// import the notes of an enex export as posts of `category`. resources become
+/// attachments, the note's markdown links to them where its enml showed them.
+/// re-importing the same export updates the posts, a note is known by its category,
+/// title and creation time.
+#[allow(clippy::too_many_arguments)]
+pub fn import_enex(
+    repo_id: &str,
+    author: &str,
+    enex: &Path,
+    category: &str,
+    work_dir: &Path,
+    resurrect: bool,
+    max_attachment_size: u64,
+    results: &mut Vec<OpenApiImportResult>,
+) -> ServiceResult<()> {
+    let mut items = Vec::new();
+    let max_data_len = usize::try_from(max_attachment_size.div_ceil(3) * 4).unwrap_or(usize::MAX);
+    read_notes(enex, max_data_len, |note| {
+        let title = match note.title.trim() {
+            "" => "untitled".to_owned(),
+            title => title.to_owned(),
+        };
+        let path = format!("{category}/{}", path_segment(&title, "untitled"));
+        let mut media = HashMap::new();
+        for (index, resource) in note.resources.into_iter().enumerate() {
+            let name = resource.name.unwrap_or_else(|| format!("resource-{index}"));
+            if resource.oversized {
+                results.push(OpenApiImportResult::new(
+                    format!("{path}/{name}"),
+                    None,
+                    ImportStatus::Invalid,
+                    Some(format!("resource larger than {max_attachment_size} bytes")),
+                ));
+                continue;
+            }
+            let Ok(data) = STANDARD.decode(&resource.data) else {
+                results.push(OpenApiImportResult::new(
+                    format!("{path}/{name}"),
+                    None,
+                    ImportStatus::Invalid,
+                    Some("resource is not base64".to_owned()),
+                ));
+                continue;
+            };
+            let hash = format!("{:x}", Md5::digest(&data));
+            let file: PathBuf = work_dir.join(&hash);
+            fs::create_dir_all(work_dir)?;
+            fs::write(&file, &data)?;
+            let stored =
+                store_attachment(repo_id, author, &file, name.clone(), max_attachment_size);
+            let _ = fs::remove_file(&file);
+            match stored {
+                Ok(sha256) => {
+                    results.push(OpenApiImportResult::new(
+                        format!("{path}/{name}"),
+                        Some(sha256.clone()),
+                        ImportStatus::Attached,
+                        None,
+                    ));
+                    media.insert(
+                        hash,
+                        Media {
+                            sha256,
+                            name,
+                            mime: resource.mime,
+                        },
+                    );
+                }
+                Err(ServiceError::BadRequest(err)) => results.push(OpenApiImportResult::new(
+                    format!("{path}/{name}"),
+                    None,
+                    ImportStatus::Invalid,
+                    Some(err),
+                )),
+                Err(err) => return Err(err),
+            }
+        }
+        let created = note.created.unwrap_or_else(Utc::now);
+        let id = derived_id(
+            repo_id,
+            &format!("enex:{category}/{title}/{}", created.timestamp()),
+        );
+        items.push((
+            path,
+            OpenApiPushPostRequest {
+                id,
+                category: category.to_owned(),
+                title,
+                content: enml_to_markdown(&note.content, &media, repo_id),
+                created_at: created,
+                updated_at: note.updated.unwrap_or(created),
+                author: author.to_owned(),
+                repo_id: repo_id.to_owned(),
+                expected_version: None,
+                resurrect,
+                category_id: None,
+                tags: Some(note.tags),
+                state: None,
+                publish_at: None,
+            },
+        ));
+        Ok(())
+    })?;
+    push_imported(repo_id, items, results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enml_to_markdown() {
+        let media = HashMap::from([(
+            "abc".to_owned(),
+            Media {
+                sha256: "s".to_owned(),
+                name: "cat.png".to_owned(),
+                mime: "image/png".to_owned(),
+            },
+        )]);
+        let enml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE en-note SYSTEM "http://xml.evernote.com/pub/enml2.dtd">
+<en-note><h2>Plan</h2><div>Buy <b>milk</b>&nbsp;and <a href="http://x.y">bread</a></div>
+<div><br/></div><ul><li>one<ul><li>nested</li></ul></li><li>two</li></ul>
+<div><en-todo checked="true"/>done</div><en-media hash="abc" type="image/png"/>
+<div style="-en-codeblock:true">let a = 1;<br/>let b = 2;</div>
+<table><tr><td><div>a</div></td><td>b</td></tr><tr><td>c</td><td>d</td></tr></table></en-note>"#;
+        assert_eq!(
+            enml_to_markdown(enml, &media, "r"),
+            "## Plan\n\nBuy **milk** and [bread](http://x.y)\n\n- one\n  - nested\n- two\n\n\
+            - [x] done\n\n![cat.png](/repo/r/attachment/s)\n\n```\nlet a = 1;\nlet b = 2;\n```\n\n\
+            | a | b |\n| --- | --- |\n| c | d |\n"
+        );
+    }
+}
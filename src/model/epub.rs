@@ -0,0 +1,268 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Seek, Write},
+    path::Path,
+};
+
+use chrono::Utc;
+use tracing::warn;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::error::{ServiceError, ServiceResult};
+
+use super::{
+    attachment::{attachment_refs, blob_path, list_attachments_by_repo_id},
+    export::{attachment_entry, encode_link, entry_options, relink_attachments},
+    import::derived_id,
+    listing::{ListQuery, ListSort},
+    markdown::{escape_html, render_xhtml},
+    post::list_posts_by_repo_id,
+    repo::get_repo_by_id,
+    user::get_user_by_id,
+};
+
+/// image types e-readers have to support, others stay links to the server.
+const IMAGE_TYPES: [&str; 5] = [
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/svg+xml",
+    "image/webp",
+];
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>
+"#;
+
+const STYLE_CSS: &str = r#"body { line-height: 1.5; }
+h1 { page-break-before: always; }
+img { max-width: 100%; }
+pre { white-space: pre-wrap; font-size: .85em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #999; padding: .2em .4em; }
+"#;
+
+/// what goes into the book: posts of the repo, or of one category, in the order of
+/// `sort`. `lang` is the language the readers are told the book is in.
+#[derive(Debug)]
+pub struct EpubOptions {
+    pub category: Option<String>, // id or name
+    pub sort: ListSort,
+    pub desc: bool,
+    pub lang: String,
+}
+
+fn xhtml_page(lang: &str, title: &str, body: &str) -> String {
+    let lang = escape_html(lang);
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n\
+        <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{lang}\" lang=\"{lang}\">\n\
+        <head>\n<meta charset=\"UTF-8\"/>\n<title>{title}</title>\n\
+        <link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/>\n</head>\n\
+        <body>\n{body}</body>\n</html>\n"
+    )
+}
+
+/// write the posts `viewer` can see as an epub 3 book to `writer`, a chapter per post
+/// with the images they show inside. the title, description and author come from the
+/// repo and its owner. returns the number of chapters.
+pub fn export_epub<W: Write + Seek>(
+    repo_id: &str,
+    viewer: &str,
+    options: &EpubOptions,
+    writer: W,
+) -> ServiceResult<(W, usize)> {
+    let Some(repo) = get_repo_by_id(repo_id)? else {
+        return Err(ServiceError::NotFound("repo not found".to_owned()));
+    };
+    let creator = get_user_by_id(&repo.owner)?.map_or(repo.owner.clone(), |owner| owner.name);
+    let query = ListQuery {
+        sort: options.sort,
+        desc: options.desc,
+        category: options.category.clone(),
+        ..ListQuery::default()
+    };
+    let posts = list_posts_by_repo_id(repo_id, viewer, None, &query)?.items;
+    if posts.is_empty() {
+        return Err(ServiceError::NotFound("no posts to export".to_owned()));
+    }
+    let title = match &options.category {
+        Some(_) => format!("{} - {}", repo.name, posts[0].category),
+        None => repo.name.clone(),
+    };
+
+    // images the posts show, embedded in the book
+    let mut images: HashMap<String, (String, String)> = HashMap::new(); // sha256 to (name, type)
+    let attachments = list_attachments_by_repo_id(repo_id)?;
+    for sha256 in posts.iter().flat_map(|post| attachment_refs(&post.content)) {
+        if let Some(attachment) = attachments.iter().find(|a| a.sha256 == sha256) {
+            if IMAGE_TYPES.contains(&attachment.content_type.as_str()) {
+                images.insert(
+                    sha256,
+                    (attachment.name.clone(), attachment.content_type.clone()),
+                );
+            }
+        }
+    }
+    images.retain(|sha256, _| blob_path(sha256).exists());
+    let names: HashMap<String, String> = images
+        .iter()
+        .map(|(sha256, (name, _))| (sha256.clone(), name.clone()))
+        .collect();
+
+    let mut zip = ZipWriter::new(writer);
+    let now = Utc::now();
+    // uncompressed and first, so readers can tell what the file is
+    zip.start_file("mimetype", entry_options(now, CompressionMethod::Stored))?;
+    zip.write_all(b"application/epub+zip")?;
+    let deflated = entry_options(now, CompressionMethod::Deflated);
+    zip.start_file("META-INF/container.xml", deflated)?;
+    zip.write_all(CONTAINER_XML.as_bytes())?;
+    zip.start_file("OEBPS/style.css", deflated)?;
+    zip.write_all(STYLE_CSS.as_bytes())?;
+
+    let mut manifest = String::new();
+    let mut spine = String::new();
+    let mut toc = String::new();
+    for (i, post) in posts.iter().enumerate() {
+        let file = format!("chapter-{:04}.xhtml", i + 1);
+        let heading = escape_html(&post.title);
+        let body = render_xhtml(&relink_attachments(&post.content, repo_id, &names, ""));
+        let remote = if body.contains("src=\"http") {
+            " properties=\"remote-resources\""
+        } else {
+            ""
+        };
+        zip.start_file(format!("OEBPS/{file}"), deflated)?;
+        zip.write_all(
+            xhtml_page(
+                &options.lang,
+                &heading,
+                &format!("<section epub:type=\"chapter\">\n<h1>{heading}</h1>\n{body}</section>\n"),
+            )
+            .as_bytes(),
+        )?;
+        manifest.push_str(&format!(
+            "<item id=\"c{i}\" href=\"{file}\" media-type=\"application/xhtml+xml\"{remote}/>\n"
+        ));
+        spine.push_str(&format!("<itemref idref=\"c{i}\"/>\n"));
+        toc.push_str(&format!("<li><a href=\"{file}\">{heading}</a></li>\n"));
+    }
+
+    zip.start_file("OEBPS/nav.xhtml", deflated)?;
+    zip.write_all(
+        xhtml_page(
+            &options.lang,
+            &escape_html(&title),
+            &format!(
+                "<nav epub:type=\"toc\" id=\"toc\">\n<h1>{}</h1>\n<ol>\n{toc}</ol>\n</nav>\n",
+                escape_html(&title)
+            ),
+        )
+        .as_bytes(),
+    )?;
+
+    let mut images: Vec<_> = images.into_iter().collect();
+    images.sort();
+    for (i, (sha256, (name, content_type))) in images.iter().enumerate() {
+        let entry = attachment_entry(sha256, name);
+        let mut blob = match fs::File::open(blob_path(sha256)) {
+            Ok(blob) => blob,
+            Err(err) => {
+                warn!("skip image {sha256} in epub: {err}");
+                continue;
+            }
+        };
+        zip.start_file(
+            format!("OEBPS/{entry}"),
+            entry_options(now, CompressionMethod::Stored),
+        )?;
+        io::copy(&mut blob, &mut zip)?;
+        manifest.push_str(&format!(
+            "<item id=\"i{i}\" href=\"{}\" media-type=\"{content_type}\"/>\n",
+            encode_link(&entry)
+        ));
+    }
+
+    let mut metadata = format!(
+        "<dc:identifier id=\"book-id\">urn:uuid:{}</dc:identifier>\n\
+        <dc:title>{}</dc:title>\n<dc:language>{}</dc:language>\n<dc:creator>{}</dc:creator>\n",
+        derived_id(
+            repo_id,
+            &format!("epub:{}", options.category.as_deref().unwrap_or_default())
+        ),
+        escape_html(&title),
+        escape_html(&options.lang),
+        escape_html(&creator),
+    );
+    if !repo.description.is_empty() {
+        metadata.push_str(&format!(
+            "<dc:description>{}</dc:description>\n",
+            escape_html(&repo.description)
+        ));
+    }
+    metadata.push_str(&format!(
+        "<meta property=\"dcterms:modified\">{}</meta>\n",
+        now.format("%Y-%m-%dT%H:%M:%SZ")
+    ));
+    zip.start_file("OEBPS/content.opf", deflated)?;
+    zip.write_all(
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\">\n\
+            <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{metadata}</metadata>\n\
+            <manifest>\n<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+            <item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n{manifest}</manifest>\n\
+            <spine>\n{spine}</spine>\n</package>\n"
+        )
+        .as_bytes(),
+    )?;
+    Ok((zip.finish()?, posts.len()))
+}
+
+/// write the book into `path`, removing what was written if it fails.
+pub fn export_epub_to_file(
+    repo_id: &str,
+    viewer: &str,
+    options: &EpubOptions,
+    path: &Path,
+) -> ServiceResult<usize> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = fs::File::create(path)?;
+    let result = export_epub(repo_id, viewer, options, io::BufWriter::new(file))
+        .and_then(|(mut writer, chapters)| Ok(writer.flush().map(|_| chapters)?));
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chapter_is_xml() {
+        let body = render_xhtml(
+            "a<br>b <div onclick=x>\n\n![i](x.png) [l](javascript:alert(1))\n\n- [x] done\n\n---\n\n```rust\nlet a = 1 < 2;\n```\n\u{1}",
+        );
+        assert!(body.contains("&lt;br&gt;"));
+        assert!(body.contains("href=\"#\""));
+        let page = xhtml_page("en", "t", &body);
+        let mut reader = quick_xml::Reader::from_str(&page);
+        loop {
+            match reader.read_event() {
+                Ok(quick_xml::events::Event::Eof) => break,
+                Ok(_) => {}
+                Err(err) => panic!("{err}: {page}"),
+            }
+        }
+    }
+}
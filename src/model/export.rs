@@ -0,0 +1,262 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use rusqlite::params;
+use tracing::warn;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{db::new_conn, error::ServiceResult};
+
+use super::{
+    attachment::{attachment_dir, attachment_refs, blob_path, list_attachments_by_repo_id},
+    comment::{list_comments_by_post_id, OpenApiGetCommentResponse},
+    listing::ListQuery,
+    post::{post_from_row, Post, POST_COLUMNS},
+};
+
+/// folder of the archive holding the attachments, next to the category folders.
+pub const ATTACHMENT_FOLDER: &str = "attachments";
+
+/// where archives are written before being sent.
+pub fn export_path(id: &str) -> PathBuf {
+    attachment_dir().join("exports").join(id)
+}
+
+/// a file or folder name from `name`, without path separators or characters
+/// some file systems reject. `fallback` when nothing is left.
+pub fn path_segment(name: &str, fallback: &str) -> String {
+    let segment: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '-',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .take(100)
+        .collect();
+    let segment = segment.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if segment.is_empty() {
+        fallback.to_owned()
+    } else {
+        segment.to_owned()
+    }
+}
+
+/// path of an attachment in the archive, `attachments/<sha256>/<name>`.
+pub fn attachment_entry(sha256: &str, name: &str) -> String {
+    format!(
+        "{ATTACHMENT_FOLDER}/{sha256}/{}",
+        path_segment(name, "attachment")
+    )
+}
+
+/// percent encode what would end a markdown link target.
+pub(crate) fn encode_link(path: &str) -> String {
+    let mut encoded = String::new();
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+/// point links to attachments of the repo at their copies in the archive, `root` leading
+/// from the linking file to the top of it. thumbnails and other sub paths are left as
+/// they are.
+pub(crate) fn relink_attachments(
+    content: &str,
+    repo_id: &str,
+    names: &HashMap<String, String>,
+    root: &str,
+) -> String {
+    let marker = format!("/repo/{repo_id}/attachment/");
+    let mut relinked = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find(&marker) {
+        let after = &rest[start + marker.len()..];
+        let sha256 = after.get(..64).unwrap_or_default();
+        match names.get(sha256) {
+            Some(name) if !after[64..].starts_with('/') => {
+                relinked.push_str(&rest[..start]);
+                relinked.push_str(root);
+                relinked.push_str(&encode_link(&attachment_entry(sha256, name)));
+                rest = &after[64..];
+            }
+            _ => {
+                relinked.push_str(&rest[..start + marker.len()]);
+                rest = after;
+            }
+        }
+    }
+    relinked.push_str(rest);
+    relinked
+}
+
+/// yaml front matter of the post, strings written as json which yaml reads as well.
+pub fn front_matter(post: &Post) -> String {
+    let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+    let mut yaml = String::from("---\n");
+    yaml.push_str(&format!("id: {}\n", quote(&post.id)));
+    yaml.push_str(&format!("title: {}\n", quote(&post.title)));
+    yaml.push_str(&format!("category: {}\n", quote(&post.category)));
+    yaml.push_str(&format!("author: {}\n", quote(&post.author)));
+    yaml.push_str(&format!("created_at: {}\n", post.created_at.to_rfc3339()));
+    yaml.push_str(&format!("updated_at: {}\n", post.updated_at.to_rfc3339()));
+    yaml.push_str(&format!(
+        "tags: {}\n",
+        serde_json::to_string(&post.tags).unwrap_or_default()
+    ));
+    yaml.push_str(&format!("state: {}\n", post.state));
+    if let Some(publish_at) = post.publish_at {
+        yaml.push_str(&format!("publish_at: {}\n", publish_at.to_rfc3339()));
+    }
+    yaml.push_str("---\n");
+    yaml
+}
+
+pub(crate) fn entry_options(
+    modified: DateTime<Utc>,
+    method: CompressionMethod,
+) -> SimpleFileOptions {
+    let options = SimpleFileOptions::default().compression_method(method);
+    match zip::DateTime::from_date_and_time(
+        modified.year().clamp(1980, 2107) as u16,
+        modified.month() as u8,
+        modified.day() as u8,
+        modified.hour() as u8,
+        modified.minute() as u8,
+        modified.second() as u8,
+    ) {
+        Ok(time) => options.last_modified_time(time),
+        Err(_) => options,
+    }
+}
+
+/// write the posts of the repo `viewer` can see as a zip of markdown files to `writer`:
+/// `<category>/<title>.md` with yaml front matter, the attachments those posts link to
+/// under `attachments/`, and with `comments` a `<title>.comments.json` next to each post.
+/// posts are read one at a time and attachments copied from disk, so the repo never
+/// has to fit in memory.
+pub fn export_repo<W: Write + Seek>(
+    repo_id: &str,
+    viewer: &str,
+    comments: bool,
+    writer: W,
+) -> ServiceResult<W> {
+    let mut zip = ZipWriter::new(writer);
+    let attachments = list_attachments_by_repo_id(repo_id)?;
+    let names: HashMap<String, String> = attachments
+        .iter()
+        .map(|attachment| (attachment.sha256.clone(), attachment.name.clone()))
+        .collect();
+
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {POST_COLUMNS} FROM post WHERE repo_id = ?1 AND (state = 'published' OR author = ?2) ORDER BY created_at, id"
+    ))?;
+    let mut rows = stmt.query(params![repo_id, viewer])?;
+    let mut used: HashSet<String> = HashSet::new();
+    // a draft of someone else is left out, and so are its attachments
+    let mut linked: HashSet<String> = HashSet::new();
+    while let Some(row) = rows.next()? {
+        let post = post_from_row(&conn, row)?;
+        linked.extend(attachment_refs(&post.content));
+        let folder = path_segment(&post.category, "uncategorized");
+        let mut stem = format!("{folder}/{}", path_segment(&post.title, "untitled"));
+        if !used.insert(stem.to_lowercase()) {
+            stem = format!("{stem}-{}", path_segment(&post.id, "post"));
+            used.insert(stem.to_lowercase());
+        }
+        zip.start_file(
+            format!("{stem}.md"),
+            entry_options(post.updated_at, CompressionMethod::Deflated),
+        )?;
+        zip.write_all(front_matter(&post).as_bytes())?;
+        zip.write_all(relink_attachments(&post.content, repo_id, &names, "../").as_bytes())?;
+        if comments {
+            let comments: Vec<OpenApiGetCommentResponse> =
+                list_comments_by_post_id(&post.id, &ListQuery::default())?
+                    .items
+                    .into_iter()
+                    .map(Into::into)
+                    .collect();
+            if !comments.is_empty() {
+                zip.start_file(
+                    format!("{stem}.comments.json"),
+                    entry_options(post.updated_at, CompressionMethod::Deflated),
+                )?;
+                serde_json::to_writer_pretty(&mut zip, &comments).map_err(io::Error::other)?;
+            }
+        }
+    }
+
+    for attachment in attachments {
+        if !linked.contains(&attachment.sha256) {
+            continue;
+        }
+        let path = blob_path(&attachment.sha256);
+        let mut blob = match fs::File::open(&path) {
+            Ok(blob) => blob,
+            Err(err) => {
+                warn!("skip attachment {} in export: {err}", attachment.sha256);
+                continue;
+            }
+        };
+        zip.start_file(
+            attachment_entry(&attachment.sha256, &attachment.name),
+            entry_options(attachment.created_at, CompressionMethod::Stored)
+                .large_file(attachment.size >= u32::MAX as u64),
+        )?;
+        io::copy(&mut blob, &mut zip)?;
+    }
+    Ok(zip.finish()?)
+}
+
+/// write the export into `path`, removing what was written if it fails.
+pub fn export_repo_to_file(
+    repo_id: &str,
+    viewer: &str,
+    comments: bool,
+    path: &Path,
+) -> ServiceResult<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = fs::File::create(path)?;
+    let result = export_repo(repo_id, viewer, comments, io::BufWriter::new(file))
+        .and_then(|mut writer| Ok(writer.flush()?));
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relink_attachments() {
+        let sha = "a".repeat(64);
+        let names = HashMap::from([(sha.clone(), "my photo.png".to_owned())]);
+        let content = format!(
+            "![x](/repo/r/attachment/{sha}) ![t](/repo/r/attachment/{sha}/thumbnail/256) /repo/q/attachment/{sha}"
+        );
+        assert_eq!(
+            relink_attachments(&content, "r", &names, "../"),
+            format!(
+                "![x](../attachments/{sha}/my%20photo.png) ![t](/repo/r/attachment/{sha}/thumbnail/256) /repo/q/attachment/{sha}"
+            )
+        );
+        assert_eq!(path_segment(" ../a/b: c? ", "x"), "-a-b- c-");
+        assert_eq!(path_segment("..", "x"), "x");
+    }
+}
@@ -0,0 +1,744 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::ZipArchive;
+
+use crate::error::{ServiceError, ServiceResult};
+
+use super::{
+    attachment::{add_attachment_file, attachment_dir, sha256_file, Attachment},
+    post::{get_post_by_id, push_posts, BatchPushStatus, OpenApiPushPostRequest, PostState},
+    tag::normalize_tags,
+};
+
+pub const MAX_IMPORT_ENTRIES: usize = 10_000;
+/// most an archive may unpack to across its entries.
+pub const MAX_UNPACKED_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+const MAX_MARKDOWN_BYTES: u64 = 4 * 1024 * 1024;
+
+/// a file to import, at `path` in the imported directory.
+#[derive(Debug)]
+pub struct ImportEntry {
+    pub path: String, // as given by the client, checked by `entry_path`
+    pub file: PathBuf,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// where archives are unpacked while importing.
+pub fn import_dir(id: &str) -> PathBuf {
+    attachment_dir().join("imports").join(id)
+}
+
+/// a relative `/` separated path from a client supplied one, none if it leaves the
+/// imported directory. hidden files and folders are dropped too.
+pub fn entry_path(path: &str) -> Option<String> {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part if part.starts_with('.') || part == "__MACOSX" => return None,
+            part => parts.push(part),
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+fn zip_time(time: zip::DateTime) -> Option<DateTime<Utc>> {
+    NaiveDate::from_ymd_opt(time.year() as i32, time.month() as u32, time.day() as u32)?
+        .and_hms_opt(
+            time.hour() as u32,
+            time.minute() as u32,
+            time.second() as u32,
+        )
+        .map(|time| time.and_utc())
+}
+
+/// copy an entry to `target`, cut off after `max_entry_size` + 1 bytes, enough for the
+/// import to tell it is too large. `unpacked` counts the bytes of the archive so far,
+/// an archive unpacking to more than `max_total_size` is rejected.
+fn unpack_entry(
+    entry: &mut impl Read,
+    target: &Path,
+    max_entry_size: u64,
+    max_total_size: u64,
+    unpacked: &mut u64,
+) -> ServiceResult<()> {
+    let limit = (max_entry_size + 1).min((max_total_size - *unpacked).saturating_add(1));
+    let mut file = fs::File::create(target)?;
+    *unpacked += io::copy(&mut entry.take(limit), &mut file)
+        .map_err(|err| ServiceError::BadRequest(format!("cannot unpack archive entry: {err}")))?;
+    if *unpacked > max_total_size {
+        return Err(ServiceError::BadRequest(format!(
+            "archive unpacks to more than {max_total_size} bytes"
+        )));
+    }
+    Ok(())
+}
+
+/// unpack the files of the zip at `archive` into `dir`, see `unpack_entry` for the
+/// limits. `dir` is removed again if unpacking fails.
+pub fn unpack_archive(
+    archive: &Path,
+    dir: &Path,
+    max_entry_size: u64,
+    max_total_size: u64,
+) -> ServiceResult<Vec<ImportEntry>> {
+    let result = read_archive(archive, dir, max_entry_size, max_total_size);
+    if result.is_err() {
+        let _ = fs::remove_dir_all(dir);
+    }
+    result
+}
+
+fn read_archive(
+    archive: &Path,
+    dir: &Path,
+    max_entry_size: u64,
+    max_total_size: u64,
+) -> ServiceResult<Vec<ImportEntry>> {
+    let mut zip = ZipArchive::new(fs::File::open(archive)?)
+        .map_err(|err| ServiceError::BadRequest(format!("invalid zip archive: {err}")))?;
+    if zip.len() > MAX_IMPORT_ENTRIES {
+        return Err(ServiceError::BadRequest(format!(
+            "archive has more than {MAX_IMPORT_ENTRIES} entries"
+        )));
+    }
+    let mut entries = Vec::new();
+    let mut unpacked = 0;
+    for index in 0..zip.len() {
+        let mut file = zip.by_index(index)?;
+        if file.is_dir() {
+            continue;
+        }
+        let path = file.name().to_owned();
+        let target = dir.join(index.to_string());
+        fs::create_dir_all(dir)?;
+        unpack_entry(
+            &mut file,
+            &target,
+            max_entry_size,
+            max_total_size,
+            &mut unpacked,
+        )?;
+        entries.push(ImportEntry {
+            path,
+            file: target,
+            modified: file.last_modified().and_then(zip_time),
+        });
+    }
+    Ok(entries)
+}
+
+/// unpack the files of the tar at `archive` into `dir`, like `unpack_archive`.
+pub fn unpack_tar(
+    archive: &Path,
+    dir: &Path,
+    max_entry_size: u64,
+    max_total_size: u64,
+) -> ServiceResult<Vec<ImportEntry>> {
+    let result = read_tar(archive, dir, max_entry_size, max_total_size);
+    if result.is_err() {
+        let _ = fs::remove_dir_all(dir);
+    }
+    result
+}
+
+fn read_tar(
+    archive: &Path,
+    dir: &Path,
+    max_entry_size: u64,
+    max_total_size: u64,
+) -> ServiceResult<Vec<ImportEntry>> {
+    let invalid = |err: io::Error| ServiceError::BadRequest(format!("invalid tar archive: {err}"));
+    let mut tar = tar::Archive::new(fs::File::open(archive)?);
+    let mut entries = Vec::new();
+    let mut unpacked = 0;
+    for (index, file) in tar.entries().map_err(invalid)?.enumerate() {
+        let mut file = file.map_err(invalid)?;
+        if !file.header().entry_type().is_file() {
+            continue;
+        }
+        if entries.len() >= MAX_IMPORT_ENTRIES {
+            return Err(ServiceError::BadRequest(format!(
+                "archive has more than {MAX_IMPORT_ENTRIES} entries"
+            )));
+        }
+        let path = file.path().map_err(invalid)?.to_string_lossy().into_owned();
+        let modified = file
+            .header()
+            .mtime()
+            .ok()
+            .and_then(|mtime| DateTime::from_timestamp(mtime as i64, 0));
+        let target = dir.join(index.to_string());
+        fs::create_dir_all(dir)?;
+        unpack_entry(
+            &mut file,
+            &target,
+            max_entry_size,
+            max_total_size,
+            &mut unpacked,
+        )?;
+        entries.push(ImportEntry {
+            path,
+            file: target,
+            modified,
+        });
+    }
+    Ok(entries)
+}
+
+/// `key: value` pairs of the yaml front matter, and the markdown after it. scalars,
+/// quoted strings, `[a, b]` and `- a` lists are understood, a scalar reads as a list
+/// of one.
+pub fn parse_front_matter(text: &str) -> (HashMap<String, Vec<String>>, &str) {
+    let mut fields: HashMap<String, Vec<String>> = HashMap::new();
+    let Some(rest) = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    else {
+        return (fields, text);
+    };
+    let mut offset = 0;
+    let mut last_key: Option<String> = None;
+    for line in rest.split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim_end();
+        if line == "---" || line == "..." {
+            return (fields, &rest[offset..]);
+        }
+        if let Some(item) = line.trim_start().strip_prefix("- ") {
+            if let Some(values) = last_key.as_ref().and_then(|key| fields.get_mut(key)) {
+                values.push(unquote(item));
+            }
+        } else if let Some((key, value)) = line.split_once(':') {
+            let key = key.trim().to_lowercase();
+            let value = value.trim();
+            let values = match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+                Some(list) => list
+                    .split(',')
+                    .map(unquote)
+                    .filter(|v| !v.is_empty())
+                    .collect(),
+                None if value.is_empty() => vec![],
+                None => vec![unquote(value)],
+            };
+            fields.insert(key.clone(), values);
+            last_key = Some(key);
+        }
+    }
+    // no closing line, so it was not front matter
+    (HashMap::new(), text)
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        serde_json::from_str(value).unwrap_or_else(|_| value[1..value.len() - 1].to_owned())
+    } else if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        value[1..value.len() - 1].replace("''", "'")
+    } else {
+        value.to_owned()
+    }
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+                .ok()
+                .map(|time| time.and_utc())
+        })
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|time| time.and_utc())
+        })
+}
+
+/// the same id every time a file at `path` is imported into the repo.
+pub(crate) fn derived_id(repo_id: &str, path: &str) -> String {
+    let digest = Sha256::digest(format!("{repo_id}:{path}"));
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    uuid::Builder::from_sha1_bytes(bytes)
+        .into_uuid()
+        .to_string()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// point relative markdown links to imported files at the attachments they became.
+/// `dir` is the folder of the markdown file, `files` maps imported paths to sha256s.
+fn relink_files(
+    content: &str,
+    repo_id: &str,
+    dir: &str,
+    files: &HashMap<String, String>,
+) -> String {
+    let mut relinked = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("](") {
+        relinked.push_str(&rest[..start + 2]);
+        rest = &rest[start + 2..];
+        let (target, close) = match rest.strip_prefix('<') {
+            Some(inner) => match inner.find('>') {
+                Some(end) => (&inner[..end], end + 2),
+                None => continue,
+            },
+            None => {
+                let end = rest
+                    .find(|c: char| c == ')' || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                (&rest[..end], end)
+            }
+        };
+        let sha256 = (!target.contains(':') && !target.starts_with(['/', '#']))
+            .then(|| entry_path(&format!("{dir}/{}", percent_decode(target))))
+            .flatten()
+            .and_then(|path| files.get(&path));
+        match sha256 {
+            Some(sha256) => relinked.push_str(&format!("/repo/{repo_id}/attachment/{sha256}")),
+            None => relinked.push_str(&rest[..close]),
+        }
+        rest = &rest[close..];
+    }
+    relinked.push_str(rest);
+    relinked
+}
+
+/// the post a markdown file describes. front matter wins, then the folder for the
+/// category, the file name for the title, and the file time for the timestamps.
+fn markdown_post(
+    repo_id: &str,
+    author: &str,
+    entry: &ImportEntry,
+    files: &HashMap<String, String>,
+    resurrect: bool,
+) -> ServiceResult<OpenApiPushPostRequest> {
+    if fs::metadata(&entry.file)?.len() > MAX_MARKDOWN_BYTES {
+        return Err(ServiceError::BadRequest(format!(
+            "markdown larger than {MAX_MARKDOWN_BYTES} bytes"
+        )));
+    }
+    let text = String::from_utf8(fs::read(&entry.file)?)
+        .map_err(|_| ServiceError::BadRequest("markdown is not utf-8".to_owned()))?;
+    let (fields, body) = parse_front_matter(&text);
+    let field = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| fields.get(*key).and_then(|values| values.first()))
+            .cloned()
+    };
+    let (dir, file_name) = entry.path.rsplit_once('/').unwrap_or(("", &entry.path));
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem);
+    let time = |keys: &[&str]| match field(keys) {
+        Some(value) => parse_time(&value)
+            .ok_or_else(|| ServiceError::BadRequest(format!("invalid time {value:?}"))),
+        None => Ok(entry.modified.unwrap_or_else(Utc::now)),
+    };
+    let state = field(&["state"])
+        .map(|state| {
+            serde_json::from_value::<PostState>(serde_json::Value::String(state.clone()))
+                .map_err(|_| ServiceError::BadRequest(format!("invalid state {state:?}")))
+        })
+        .transpose()?;
+    let publish_at = field(&["publish_at"])
+        .map(|value| {
+            parse_time(&value)
+                .ok_or_else(|| ServiceError::BadRequest(format!("invalid time {value:?}")))
+        })
+        .transpose()?;
+    Ok(OpenApiPushPostRequest {
+        id: field(&["id"]).unwrap_or_else(|| derived_id(repo_id, &entry.path)),
+        category: field(&["category"]).unwrap_or_else(|| dir.to_owned()),
+        title: field(&["title"]).unwrap_or_else(|| stem.to_owned()),
+        content: relink_files(body, repo_id, dir, files),
+        created_at: time(&["created_at", "created", "date"])?,
+        updated_at: time(&["updated_at", "updated", "lastmod", "modified"])?,
+        author: author.to_owned(),
+        repo_id: repo_id.to_owned(),
+        expected_version: None,
+        resurrect,
+        category_id: None,
+        tags: fields.get("tags").cloned(),
+        state,
+        publish_at,
+    })
+}
+
+/// whether pushing `req` would leave the post as it is.
+fn is_unchanged(req: &OpenApiPushPostRequest) -> ServiceResult<bool> {
+    let Some(post) = get_post_by_id(&req.id)? else {
+        return Ok(false);
+    };
+    let mut tags = normalize_tags(req.tags.clone().unwrap_or_default())?;
+    tags.sort();
+    let mut current = post.tags.clone();
+    current.sort();
+    Ok(post.repo_id == req.repo_id
+        && post.title == req.title
+        && post.category == req.category
+        && post.content == req.content
+        && current == tags
+        && req.state.is_none_or(|state| state == post.state)
+        && (req.publish_at.is_none() || req.publish_at == post.publish_at))
+}
+
+/// store the file as an attachment of the repo named `name`, returns its sha256.
+/// a file larger than `max_size` is a bad request.
+pub(crate) fn store_attachment(
+    repo_id: &str,
+    author: &str,
+    file: &Path,
+    name: String,
+    max_size: u64,
+) -> ServiceResult<String> {
+    if fs::metadata(file)?.len() > max_size {
+        return Err(ServiceError::BadRequest(format!(
+            "attachment larger than {max_size} bytes"
+        )));
+    }
+    let (sha256, size) = sha256_file(file)?;
+    add_attachment_file(
+        file,
+        Attachment {
+            repo_id: repo_id.to_owned(),
+            sha256: sha256.clone(),
+            content_type: mime_infer::from_path(&name)
+                .first_or_octet_stream()
+                .to_string(),
+            name,
+            size,
+            uploader: author.to_owned(),
+            created_at: Utc::now(),
+        },
+    )?;
+    Ok(sha256)
+}
+
+/// push the posts read from the files at their paths, adding a result for each.
+/// posts that would stay as they are are not written again.
+pub(crate) fn push_imported(
+    repo_id: &str,
+    items: Vec<(String, OpenApiPushPostRequest)>,
+    results: &mut Vec<OpenApiImportResult>,
+) -> ServiceResult<()> {
+    let mut pushed_paths = Vec::new();
+    let mut pushed = Vec::new();
+    for (path, req) in items {
+        if is_unchanged(&req)? {
+            results.push(OpenApiImportResult::new(
+                path,
+                Some(req.id),
+                ImportStatus::Unchanged,
+                None,
+            ));
+        } else {
+            pushed_paths.push(path);
+            pushed.push(req);
+        }
+    }
+    let pushed = push_posts(repo_id, pushed, false)?;
+    for (path, result) in pushed_paths.into_iter().zip(pushed.results) {
+        let status = match result.status {
+            BatchPushStatus::Created => ImportStatus::Created,
+            BatchPushStatus::Updated => ImportStatus::Updated,
+            BatchPushStatus::Conflict => ImportStatus::Conflict,
+            BatchPushStatus::Forbidden => ImportStatus::Forbidden,
+            BatchPushStatus::Invalid | BatchPushStatus::Aborted => ImportStatus::Invalid,
+        };
+        results.push(OpenApiImportResult::new(
+            path,
+            Some(result.id),
+            status,
+            result.error,
+        ));
+    }
+    Ok(())
+}
+
+/// import markdown files as posts of the repo and other files as its attachments.
+/// each post keeps the id of its front matter or gets one from its path, so importing
+/// the same files again updates the posts instead of adding copies, and leaves the
+/// unchanged ones alone.
+pub fn import_entries(
+    repo_id: &str,
+    author: &str,
+    entries: Vec<ImportEntry>,
+    resurrect: bool,
+    max_attachment_size: u64,
+) -> ServiceResult<OpenApiImportResponse> {
+    let mut results = Vec::with_capacity(entries.len());
+    let mut files: HashMap<String, String> = HashMap::new();
+    let mut markdown = Vec::new();
+    for mut entry in entries {
+        match entry_path(&entry.path) {
+            Some(path) => entry.path = path,
+            None => {
+                results.push(OpenApiImportResult::new(
+                    entry.path,
+                    None,
+                    ImportStatus::Skipped,
+                    Some("hidden or outside the imported directory".to_owned()),
+                ));
+                continue;
+            }
+        }
+        let extension = entry
+            .path
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase());
+        if matches!(extension.as_deref(), Some("md" | "markdown")) {
+            markdown.push(entry);
+            continue;
+        }
+        if entry.path.ends_with(".comments.json") {
+            results.push(OpenApiImportResult::new(
+                entry.path,
+                None,
+                ImportStatus::Skipped,
+                None,
+            ));
+            continue;
+        }
+        let name = entry
+            .path
+            .rsplit_once('/')
+            .map_or(entry.path.as_str(), |(_, name)| name)
+            .to_owned();
+        let sha256 = match store_attachment(repo_id, author, &entry.file, name, max_attachment_size)
+        {
+            Ok(sha256) => sha256,
+            Err(ServiceError::BadRequest(err)) => {
+                results.push(OpenApiImportResult::new(
+                    entry.path,
+                    None,
+                    ImportStatus::Invalid,
+                    Some(err),
+                ));
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        files.insert(entry.path.clone(), sha256.clone());
+        results.push(OpenApiImportResult::new(
+            entry.path,
+            Some(sha256),
+            ImportStatus::Attached,
+            None,
+        ));
+    }
+
+    let mut items = Vec::new();
+    for entry in markdown {
+        match markdown_post(repo_id, author, &entry, &files, resurrect) {
+            Ok(req) => items.push((entry.path, req)),
+            Err(ServiceError::BadRequest(err)) => {
+                results.push(OpenApiImportResult::new(
+                    entry.path,
+                    None,
+                    ImportStatus::Invalid,
+                    Some(err),
+                ));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    push_imported(repo_id, items, &mut results)?;
+    results.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(OpenApiImportResponse { results })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportStatus {
+    Created,
+    Updated,
+    Unchanged,
+    Attached,  // stored as an attachment of the repo
+    Skipped,   // comments of an export, not imported
+    Conflict,  // deleted and not resurrected
+    Forbidden, // a post of another repo
+    Invalid,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiImportResult {
+    pub path: String,
+    pub id: Option<String>, // post id, or sha256 of an attachment
+    pub status: ImportStatus,
+    pub error: Option<String>,
+}
+
+impl OpenApiImportResult {
+    pub(crate) fn new(
+        path: String,
+        id: Option<String>,
+        status: ImportStatus,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            path,
+            id,
+            status,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiImportResponse {
+    pub results: Vec<OpenApiImportResult>, // by path
+}
+
+impl Scribe for OpenApiImportResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_front_matter() {
+        let text = "---\ntitle: \"a: b\"\ntags: [x, 'y']\naliases:\n  - one\n  - two\n---\nbody\n";
+        let (fields, body) = parse_front_matter(text);
+        assert_eq!(fields["title"], ["a: b"]);
+        assert_eq!(fields["tags"], ["x", "y"]);
+        assert_eq!(fields["aliases"], ["one", "two"]);
+        assert_eq!(body, "body\n");
+        let (fields, body) = parse_front_matter("---\nno end");
+        assert!(fields.is_empty());
+        assert_eq!(body, "---\nno end");
+
+        let files = HashMap::from([("img/a b.png".to_owned(), "s".to_owned())]);
+        assert_eq!(
+            relink_files(
+                "![](../img/a%20b.png) [x](<../img/a b.png> \"t\") [y](http://z)",
+                "r",
+                "notes",
+                &files
+            ),
+            "![](/repo/r/attachment/s) [x](/repo/r/attachment/s \"t\") [y](http://z)"
+        );
+        assert_eq!(entry_path("a/../../b"), None);
+        assert_eq!(entry_path("./a//b\\c.md"), Some("a/b/c.md".to_owned()));
+    }
+
+    #[test]
+    fn test_unpack_limits() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir)?;
+        let archive = dir.join("a.zip");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&archive)?);
+        for name in ["a.md", "b.md", "c.md"] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())?;
+            io::Write::write_all(&mut zip, &[b'x'; 10])?;
+        }
+        zip.finish()?;
+
+        let unpacked = dir.join("unpacked");
+        assert_eq!(unpack_archive(&archive, &unpacked, 100, 30)?.len(), 3);
+        let err = unpack_archive(&archive, &unpacked, 100, 25).unwrap_err();
+        assert!(matches!(err, ServiceError::BadRequest(_)), "{err}");
+        assert!(!unpacked.exists());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_again() -> anyhow::Result<()> {
+        use crate::model::{
+            repo::{add_repo, Repo, RepoStatus},
+            user::{add_user, User},
+        };
+
+        crate::db::init_db()?;
+        let user = User::new(uuid::Uuid::new_v4().to_string(), "password".into());
+        add_user(&user)?;
+        let repo_id = uuid::Uuid::new_v4().to_string();
+        add_repo(&Repo {
+            id: repo_id.clone(),
+            name: "notes".into(),
+            owner: user.id.clone(),
+            description: String::new(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            status: RepoStatus::Normal,
+            public: false,
+            topics: vec![],
+            version: 1,
+            search_tokenizer: None,
+        })?;
+        let dir = std::env::temp_dir().join(&repo_id);
+        fs::create_dir_all(&dir)?;
+        let file = dir.join("a.md");
+        fs::write(&file, "---\ntags: [rust]\n---\nhello")?;
+        let entries = || {
+            vec![ImportEntry {
+                path: "dev/a.md".into(),
+                file: file.clone(),
+                modified: None,
+            }]
+        };
+        let statuses = |response: OpenApiImportResponse| -> Vec<ImportStatus> {
+            response
+                .results
+                .iter()
+                .map(|result| result.status)
+                .collect()
+        };
+        let first = import_entries(&repo_id, &user.id, entries(), false, 1024)?;
+        let id = first.results[0].id.clone().unwrap_or_default();
+        assert_eq!(statuses(first), [ImportStatus::Created]);
+        let post = get_post_by_id(&id)?.expect("imported");
+        assert_eq!((post.title.as_str(), post.category.as_str()), ("a", "dev"));
+        assert_eq!(post.tags, ["rust"]);
+        let again = import_entries(&repo_id, &user.id, entries(), false, 1024)?;
+        assert_eq!(statuses(again), [ImportStatus::Unchanged]);
+        fs::write(&file, "hello again")?;
+        let changed = import_entries(&repo_id, &user.id, entries(), false, 1024)?;
+        assert_eq!(statuses(changed), [ImportStatus::Updated]);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::error::{ServiceError, ServiceResult};
+
+use super::{
+    export::path_segment,
+    import::{
+        derived_id, entry_path, push_imported, store_attachment, ImportEntry, ImportStatus,
+        OpenApiImportResult,
+    },
+    post::OpenApiPushPostRequest,
+};
+
+const NOTE: &str = "1";
+const FOLDER: &str = "2";
+const RESOURCE: &str = "4";
+const TAG: &str = "5";
+const NOTE_TAG: &str = "6";
+
+/// an item of a raw export: the title and body joplin writes first, then its
+/// `key: value` properties after the last blank line.
+#[derive(Debug, Default)]
+pub struct JoplinItem {
+    pub title: String,
+    pub body: String,
+    pub props: HashMap<String, String>,
+}
+
+impl JoplinItem {
+    fn prop(&self, key: &str) -> &str {
+        self.props.get(key).map_or("", String::as_str)
+    }
+
+    fn time(&self, key: &str) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(self.prop(key))
+            .ok()
+            .map(|time| time.with_timezone(&Utc))
+    }
+}
+
+fn is_prop_line(line: &str) -> bool {
+    line.split_once(':').is_some_and(|(key, _)| {
+        !key.is_empty()
+            && key
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+    })
+}
+
+pub fn parse_item(text: &str) -> JoplinItem {
+    let lines: Vec<&str> = text.trim_end().lines().collect();
+    let mut split = lines.len();
+    while split > 0 && is_prop_line(lines[split - 1]) {
+        split -= 1;
+    }
+    let props = lines[split..]
+        .iter()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.to_owned(), value.trim().to_owned()))
+        .collect();
+    let mut head = lines[..split].iter();
+    let title = head.next().map_or("", |title| title.trim()).to_owned();
+    let body = head.copied().collect::<Vec<_>>().join("\n");
+    JoplinItem {
+        title,
+        body: body.trim_matches('\n').to_owned(),
+        props,
+    }
+}
+
+/// `:/<resource id>` links in joplin markdown pointed at the attachments they became.
+fn relink_resources(body: &str, repo_id: &str, resources: &HashMap<String, String>) -> String {
+    let mut relinked = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(start) = rest.find(":/") {
+        relinked.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let id = after.get(..32).unwrap_or_default();
+        match resources.get(id) {
+            Some(sha256) => {
+                relinked.push_str(&format!("/repo/{repo_id}/attachment/{sha256}"));
+                rest = &after[32..];
+            }
+            None => {
+                relinked.push_str(":/");
+                rest = after;
+            }
+        }
+    }
+    relinked.push_str(rest);
+    relinked
+}
+
+/// the notebook `id` and its parents as a category, `parent/child`.
+fn folder_path<'a>(items: &'a HashMap<String, JoplinItem>, mut id: &'a str) -> String {
+    let mut names = Vec::new();
+    while let Some(folder) = items.get(id).filter(|item| item.prop("type_") == FOLDER) {
+        if names.len() > 32 {
+            break;
+        }
+        names.push(folder.title.clone());
+        id = folder.prop("parent_id");
+    }
+    names.reverse();
+    names.join("/")
+}
+
+/// import a joplin raw export, or the files of a jex archive. notebooks become
+/// categories, nested ones joined by `/`, tags and resources come along. a note is
+/// known by its joplin id, so importing again updates the posts. encrypted and
+/// trashed notes are skipped.
+pub fn import_joplin(
+    repo_id: &str,
+    author: &str,
+    entries: Vec<ImportEntry>,
+    resurrect: bool,
+    max_attachment_size: u64,
+    results: &mut Vec<OpenApiImportResult>,
+) -> ServiceResult<()> {
+    let mut items: HashMap<String, JoplinItem> = HashMap::new();
+    let mut files: HashMap<String, PathBuf> = HashMap::new(); // resource id to its file
+    for entry in entries {
+        let Some(path) = entry_path(&entry.path) else {
+            continue;
+        };
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", &path));
+        let (stem, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+        if dir == "resources" || dir.ends_with("/resources") {
+            files.insert(stem.to_owned(), entry.file);
+        } else if extension == "md" {
+            let text = String::from_utf8_lossy(&fs::read(&entry.file)?).into_owned();
+            let item = parse_item(&text);
+            let id = match item.prop("id") {
+                "" => stem.to_owned(),
+                id => id.to_owned(),
+            };
+            items.insert(id, item);
+        }
+    }
+    if !items.values().any(|item| item.prop("type_") == NOTE) {
+        return Err(ServiceError::BadRequest("no joplin notes found".to_owned()));
+    }
+
+    let mut tags: HashMap<&str, Vec<String>> = HashMap::new();
+    for item in items.values().filter(|item| item.prop("type_") == NOTE_TAG) {
+        if let Some(tag) = items
+            .get(item.prop("tag_id"))
+            .filter(|tag| tag.prop("type_") == TAG)
+        {
+            tags.entry(item.prop("note_id"))
+                .or_default()
+                .push(tag.title.clone());
+        }
+    }
+
+    let mut resources: HashMap<String, String> = HashMap::new(); // resource id to sha256
+    for (id, item) in items
+        .iter()
+        .filter(|(_, item)| item.prop("type_") == RESOURCE)
+    {
+        let name = match item.prop("filename") {
+            "" => match item.prop("file_extension") {
+                "" => item.title.clone(),
+                extension if item.title.ends_with(&format!(".{extension}")) => {
+                    path_segment(&item.title, id)
+                }
+                extension => format!("{}.{extension}", path_segment(&item.title, id)),
+            },
+            filename => filename.to_owned(),
+        };
+        let path = format!("resources/{name}");
+        if item.prop("encryption_applied") == "1" {
+            results.push(OpenApiImportResult::new(
+                path,
+                None,
+                ImportStatus::Skipped,
+                Some("encrypted".to_owned()),
+            ));
+            continue;
+        }
+        let Some(file) = files.get(id.as_str()) else {
+            results.push(OpenApiImportResult::new(
+                path,
+                None,
+                ImportStatus::Invalid,
+                Some("resource file missing".to_owned()),
+            ));
+            continue;
+        };
+        match store_attachment(repo_id, author, file, name, max_attachment_size) {
+            Ok(sha256) => {
+                resources.insert(id.clone(), sha256.clone());
+                results.push(OpenApiImportResult::new(
+                    path,
+                    Some(sha256),
+                    ImportStatus::Attached,
+                    None,
+                ));
+            }
+            Err(ServiceError::BadRequest(err)) => results.push(OpenApiImportResult::new(
+                path,
+                None,
+                ImportStatus::Invalid,
+                Some(err),
+            )),
+            Err(err) => return Err(err),
+        }
+    }
+
+    let mut pushed = Vec::new();
+    for (id, item) in items.iter().filter(|(_, item)| item.prop("type_") == NOTE) {
+        let category = folder_path(&items, item.prop("parent_id"));
+        let title = match item.title.as_str() {
+            "" => "untitled".to_owned(),
+            title => title.to_owned(),
+        };
+        let path = format!("{category}/{}", path_segment(&title, "untitled"));
+        let trashed = !matches!(item.prop("deleted_time"), "" | "0");
+        if trashed || item.prop("encryption_applied") == "1" {
+            results.push(OpenApiImportResult::new(
+                path,
+                None,
+                ImportStatus::Skipped,
+                Some(if trashed { "in the trash" } else { "encrypted" }.to_owned()),
+            ));
+            continue;
+        }
+        let created = item.time("user_created_time").or(item.time("created_time"));
+        let updated = item.time("user_updated_time").or(item.time("updated_time"));
+        let created = created.or(updated).unwrap_or_else(Utc::now);
+        pushed.push((
+            path,
+            OpenApiPushPostRequest {
+                id: derived_id(repo_id, &format!("joplin:{id}")),
+                category,
+                title,
+                content: relink_resources(&item.body, repo_id, &resources),
+                created_at: created,
+                updated_at: updated.unwrap_or(created),
+                author: author.to_owned(),
+                repo_id: repo_id.to_owned(),
+                expected_version: None,
+                resurrect,
+                category_id: None,
+                tags: Some(tags.remove(id.as_str()).unwrap_or_default()),
+                state: None,
+                publish_at: None,
+            },
+        ));
+    }
+    pushed.sort_by(|a, b| a.0.cmp(&b.0));
+    push_imported(repo_id, pushed, results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_item() {
+        let note = parse_item(
+            "Groceries\n\nmilk\n\n![x](:/0123456789abcdef0123456789abcdef)\n\nid: 11111111111111111111111111111111\nparent_id: 22222222222222222222222222222222\ncreated_time: 2020-01-02T03:04:05.000Z\ntype_: 1",
+        );
+        assert_eq!(note.title, "Groceries");
+        assert_eq!(
+            note.body,
+            "milk\n\n![x](:/0123456789abcdef0123456789abcdef)"
+        );
+        assert_eq!(note.prop("type_"), NOTE);
+        assert_eq!(
+            note.time("created_time").map(|time| time.timestamp()),
+            Some(1577934245)
+        );
+        let resources = HashMap::from([(
+            "0123456789abcdef0123456789abcdef".to_owned(),
+            "s".to_owned(),
+        )]);
+        assert_eq!(
+            relink_resources(&note.body, "r", &resources),
+            "milk\n\n![x](/repo/r/attachment/s)"
+        );
+
+        let folder = parse_item("Work\n\nid: 22222222222222222222222222222222\ntype_: 2\n");
+        assert_eq!((folder.title.as_str(), folder.body.as_str()), ("Work", ""));
+    }
+}
@@ -0,0 +1,246 @@
+use rusqlite::{params, Connection};
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+
+use crate::{db::new_conn, error::ServiceResult};
+
+use super::post::Post;
+
+/// links of a repo with the post each one points to, null when broken. a target matches
+/// a post id first, then a title ignoring case, the oldest post winning among equal titles.
+const RESOLVED_LINKS_SQL: &str = "SELECT l.post_id, l.target, COALESCE(
+    (SELECT p.id FROM post p WHERE p.repo_id = l.repo_id AND p.id = l.target),
+    (SELECT p.id FROM post p WHERE p.repo_id = l.repo_id AND p.title = l.target COLLATE NOCASE
+    ORDER BY p.created_at, p.id LIMIT 1)
+) AS target_id FROM post_link l WHERE l.repo_id = ?1";
+
+/// targets of the `[[target]]` and `[[target|label]]` links in the content, each once.
+/// a target is a post id or a post title.
+pub fn link_targets(content: &str) -> Vec<String> {
+    let mut targets: Vec<String> = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("[[") {
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find("]]") else {
+            break;
+        };
+        let inner = &rest[..end];
+        // `[[a [[b]]` links to b
+        if let Some(nested) = inner.rfind("[[") {
+            rest = &rest[nested..];
+            continue;
+        }
+        rest = &rest[end + 2..];
+        if inner.contains('\n') {
+            continue;
+        }
+        let target = inner.split('|').next().unwrap_or_default().trim();
+        if !target.is_empty()
+            && !targets
+                .iter()
+                .any(|t| t.to_lowercase() == target.to_lowercase())
+        {
+            targets.push(target.to_owned());
+        }
+    }
+    targets
+}
+
+/// remember what the post links to, resolved when read so links to posts
+/// created or renamed later follow along.
+pub(crate) fn set_post_links(conn: &Connection, post: &Post) -> ServiceResult<()> {
+    erase_post_links(conn, &post.id)?;
+    let mut stmt =
+        conn.prepare("INSERT INTO post_link (post_id, repo_id, target) VALUES (?1, ?2, ?3)")?;
+    for target in link_targets(&post.content) {
+        stmt.execute(params![post.id, post.repo_id, target])?;
+    }
+    Ok(())
+}
+
+pub(crate) fn erase_post_links(conn: &Connection, post_id: &str) -> ServiceResult<()> {
+    conn.execute("DELETE FROM post_link WHERE post_id = ?1", params![post_id])?;
+    Ok(())
+}
+
+/// parse the links of every post, for posts saved before links were stored.
+pub(crate) fn relink_posts(conn: &Connection) -> ServiceResult<()> {
+    let mut stmt = conn.prepare("SELECT id, repo_id, content FROM post")?;
+    let posts = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut insert = conn.prepare(
+        "INSERT OR IGNORE INTO post_link (post_id, repo_id, target) VALUES (?1, ?2, ?3)",
+    )?;
+    for (post_id, repo_id, content) in posts {
+        for target in link_targets(&content) {
+            insert.execute(params![post_id, repo_id, target])?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkedPost {
+    pub id: String,
+    pub title: String,
+}
+
+/// posts of the repo `viewer` can see that link to the post.
+pub fn list_backlinks(
+    repo_id: &str,
+    post_id: &str,
+    viewer: &str,
+) -> ServiceResult<Vec<LinkedPost>> {
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT DISTINCT s.id, s.title FROM ({RESOLVED_LINKS_SQL}) r JOIN post s ON s.id = r.post_id
+        WHERE r.target_id = ?2 AND s.id != ?2 AND (s.state = 'published' OR s.author = ?3)
+        ORDER BY s.title COLLATE NOCASE, s.id"
+    ))?;
+    let posts = stmt
+        .query_map(params![repo_id, post_id, viewer], |row| {
+            Ok(LinkedPost {
+                id: row.get(0)?,
+                title: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(posts)
+}
+
+#[derive(Debug, Default)]
+pub struct LinkGraph {
+    pub nodes: Vec<LinkedPost>,
+    pub edges: Vec<(String, String)>,  // (from, to) post ids
+    pub broken: Vec<(String, String)>, // (from, target) of links matching no post
+}
+
+/// the posts of the repo `viewer` can see and the links between them.
+/// links to posts hidden from the viewer are left out, not reported as broken.
+pub fn get_link_graph(repo_id: &str, viewer: &str) -> ServiceResult<LinkGraph> {
+    let conn = new_conn()?;
+    let mut graph = LinkGraph::default();
+    let mut stmt = conn.prepare(
+        "SELECT id, title FROM post WHERE repo_id = ?1 AND (state = 'published' OR author = ?2)
+        ORDER BY title COLLATE NOCASE, id",
+    )?;
+    graph.nodes = stmt
+        .query_map(params![repo_id, viewer], |row| {
+            Ok(LinkedPost {
+                id: row.get(0)?,
+                title: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT r.post_id, r.target, r.target_id FROM ({RESOLVED_LINKS_SQL}) r
+        JOIN post s ON s.id = r.post_id
+        LEFT JOIN post t ON t.id = r.target_id
+        WHERE (s.state = 'published' OR s.author = ?2)
+        AND (t.id IS NULL OR t.state = 'published' OR t.author = ?2)
+        ORDER BY r.post_id, r.target"
+    ))?;
+    let mut rows = stmt.query(params![repo_id, viewer])?;
+    while let Some(row) = rows.next()? {
+        let from: String = row.get(0)?;
+        match row.get::<_, Option<String>>(2)? {
+            Some(to) => {
+                if !graph.edges.iter().any(|(f, t)| *f == from && *t == to) {
+                    graph.edges.push((from, to));
+                }
+            }
+            None => graph.broken.push((from, row.get(1)?)),
+        }
+    }
+    Ok(graph)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiLinkedPostResponse {
+    pub id: String,
+    pub title: String,
+}
+
+impl From<LinkedPost> for OpenApiLinkedPostResponse {
+    fn from(value: LinkedPost) -> Self {
+        Self {
+            id: value.id,
+            title: value.title,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenApiListBacklinkResponse(pub Vec<OpenApiLinkedPostResponse>);
+
+impl Scribe for OpenApiListBacklinkResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiLinkEdgeResponse {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiBrokenLinkResponse {
+    pub from: String,
+    pub target: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiLinkGraphResponse {
+    pub nodes: Vec<OpenApiLinkedPostResponse>,
+    pub edges: Vec<OpenApiLinkEdgeResponse>,
+    pub broken: Vec<OpenApiBrokenLinkResponse>,
+}
+
+impl From<LinkGraph> for OpenApiLinkGraphResponse {
+    fn from(value: LinkGraph) -> Self {
+        Self {
+            nodes: value.nodes.into_iter().map(Into::into).collect(),
+            edges: value
+                .edges
+                .into_iter()
+                .map(|(from, to)| OpenApiLinkEdgeResponse { from, to })
+                .collect(),
+            broken: value
+                .broken
+                .into_iter()
+                .map(|(from, target)| OpenApiBrokenLinkResponse { from, target })
+                .collect(),
+        }
+    }
+}
+
+impl Scribe for OpenApiLinkGraphResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_targets() {
+        let content = "see [[Rust notes]] and [[rust NOTES|again]], [[ abc-123 ]]\n\
+            [[not\na link]] [[]] [[a [[nested]] [[unclosed";
+        assert_eq!(link_targets(content), ["Rust notes", "abc-123", "nested"]);
+    }
+}
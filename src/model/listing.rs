@@ -0,0 +1,216 @@
+use std::str::FromStr;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use rusqlite::{Row, ToSql};
+
+use crate::error::{ServiceError, ServiceResult};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListSort {
+    #[default]
+    Created,
+    Updated,
+    Title,
+}
+
+impl FromStr for ListSort {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "created" => Ok(Self::Created),
+            "updated" => Ok(Self::Updated),
+            "title" => Ok(Self::Title),
+            _ => Err(ServiceError::BadRequest(format!("invalid sort {s:?}"))),
+        }
+    }
+}
+
+impl std::fmt::Display for ListSort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sort = match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Title => "title",
+        };
+        write!(f, "{}", sort)
+    }
+}
+
+/// position after the last row of a page: its sort key and id, the id breaks ties.
+#[derive(Debug, Clone)]
+pub struct ListCursor {
+    pub sort: ListSort,
+    pub key: String,
+    pub id: String,
+}
+
+impl ListCursor {
+    /// opaque to clients, url safe base64 of the sort, key and id.
+    pub fn encode(&self) -> String {
+        let raw = serde_json::json!([self.sort.to_string(), self.key, self.id]).to_string();
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(cursor: &str) -> ServiceResult<Self> {
+        let invalid = || ServiceError::BadRequest(format!("invalid cursor {cursor:?}"));
+        let raw = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let (sort, key, id): (String, String, String) =
+            serde_json::from_slice(&raw).map_err(|_| invalid())?;
+        Ok(Self {
+            sort: ListSort::from_str(&sort).map_err(|_| invalid())?,
+            key,
+            id,
+        })
+    }
+}
+
+/// sorting, filtering and the page of a list. the default lists everything by creation.
+#[derive(Debug, Default)]
+pub struct ListQuery {
+    pub sort: ListSort,
+    pub desc: bool,
+    pub limit: Option<u32>, // none returns every row
+    pub after: Option<ListCursor>,
+    pub category: Option<String>, // id or name
+    pub author: Option<String>,
+    pub since: Option<DateTime<Utc>>, // `updated_at` when sorted by updated, `created_at` otherwise
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// columns of the listed table the query applies to, `None` where it has no such column.
+pub struct ListColumns {
+    pub id: &'static str,
+    pub created_at: &'static str,
+    pub updated_at: &'static str,
+    pub title: Option<&'static str>,
+    pub author: Option<&'static str>,
+    pub category: Option<(&'static str, &'static str)>, // (id, name)
+}
+
+impl ListQuery {
+    /// the sort key, also to select after the listed columns so `cursor` can read it.
+    pub fn sort_column(&self, columns: &ListColumns) -> ServiceResult<String> {
+        match self.sort {
+            ListSort::Created => Ok(columns.created_at.to_owned()),
+            ListSort::Updated => Ok(columns.updated_at.to_owned()),
+            ListSort::Title => columns
+                .title
+                .map(|title| format!("{title} COLLATE NOCASE"))
+                .ok_or(ServiceError::BadRequest("cannot sort by title".to_owned())),
+        }
+    }
+
+    /// `AND ...` conditions followed by `ORDER BY` and `LIMIT`, to append to a query whose
+    /// own parameters are `?1` to `?{first_param - 1}`. returns the sql and its parameters.
+    /// one more row than the limit is fetched, `page` uses it to tell if more follow.
+    pub fn sql(
+        &self,
+        columns: &ListColumns,
+        first_param: usize,
+    ) -> ServiceResult<(String, Vec<Box<dyn ToSql>>)> {
+        let sort_column = self.sort_column(columns)?;
+        let mut sql = String::new();
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+        if let Some(category) = &self.category {
+            let (id, name) = columns.category.ok_or(ServiceError::BadRequest(
+                "cannot filter by category".to_owned(),
+            ))?;
+            let p = format!("?{}", first_param + params.len());
+            sql.push_str(&format!(" AND ({id} = {p} OR {name} = {p} COLLATE NOCASE)"));
+            params.push(Box::new(category.clone()));
+        }
+        if let Some(author) = &self.author {
+            let column = columns.author.ok_or(ServiceError::BadRequest(
+                "cannot filter by author".to_owned(),
+            ))?;
+            sql.push_str(&format!(" AND {column} = ?{}", first_param + params.len()));
+            params.push(Box::new(author.clone()));
+        }
+        let date_column = match self.sort {
+            ListSort::Updated => columns.updated_at,
+            _ => columns.created_at,
+        };
+        if let Some(since) = self.since {
+            sql.push_str(&format!(
+                " AND {date_column} >= ?{}",
+                first_param + params.len()
+            ));
+            params.push(Box::new(since));
+        }
+        if let Some(until) = self.until {
+            sql.push_str(&format!(
+                " AND {date_column} < ?{}",
+                first_param + params.len()
+            ));
+            params.push(Box::new(until));
+        }
+        let (cmp, order) = if self.desc {
+            ("<", "DESC")
+        } else {
+            (">", "ASC")
+        };
+        if let Some(after) = &self.after {
+            if after.sort != self.sort {
+                return Err(ServiceError::BadRequest(format!(
+                    "cursor is for sort {}",
+                    after.sort
+                )));
+            }
+            let p = first_param + params.len();
+            sql.push_str(&format!(
+                " AND ({sort_column}, {}) {cmp} (?{p}, ?{})",
+                columns.id,
+                p + 1
+            ));
+            params.push(Box::new(after.key.clone()));
+            params.push(Box::new(after.id.clone()));
+        }
+        sql.push_str(&format!(
+            " ORDER BY {sort_column} {order}, {} {order}",
+            columns.id
+        ));
+        if let Some(limit) = self.limit {
+            sql.push_str(&format!(" LIMIT {}", limit as u64 + 1));
+        }
+        Ok((sql, params))
+    }
+
+    /// the cursor of a row, from the id and the sort key at `key_index`.
+    pub fn cursor(
+        &self,
+        row: &Row,
+        id_index: usize,
+        key_index: usize,
+    ) -> ServiceResult<ListCursor> {
+        Ok(ListCursor {
+            sort: self.sort,
+            key: row.get(key_index)?,
+            id: row.get(id_index)?,
+        })
+    }
+
+    /// cut the rows fetched by `sql` down to the limit, with the cursor of the next page.
+    pub fn page<T>(&self, mut rows: Vec<(T, ListCursor)>) -> Page<T> {
+        let mut next_cursor = None;
+        if let Some(limit) = self.limit {
+            if rows.len() > limit as usize {
+                rows.truncate(limit as usize);
+                next_cursor = rows.last().map(|(_, cursor)| cursor.encode());
+            }
+        }
+        Page {
+            items: rows.into_iter().map(|(item, _)| item).collect(),
+            next_cursor,
+            paged: self.limit.is_some(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>, // none on the last page
+    pub paged: bool,                 // a limit was asked for
+}
@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+
+use chrono::Utc;
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use syntect::{
+    html::{ClassStyle, ClassedHTMLGenerator},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+use crate::{db::new_conn, error::ServiceResult};
+
+use super::post::Post;
+
+/// bump when the rendering changes, so cached html is rendered again.
+const RENDERER_VERSION: u32 = 2;
+
+/// highlighted tokens get `hl-` prefixed classes, clients bring their own theme.
+const HIGHLIGHT_CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+/// ids in posts get this prefix so they cannot clash with those of the page showing them,
+/// fragment links (footnotes) get it as well.
+const ID_PREFIX: &str = "user-content-";
+
+lazy_static::lazy_static! {
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref SANITIZER: ammonia::Builder<'static> = {
+        let mut builder = ammonia::Builder::default();
+        builder
+            .add_tags(["input", "span"])
+            .add_tag_attributes("input", ["type", "checked", "disabled"])
+            .add_tag_attributes("span", ["class"])
+            .add_tag_attributes("code", ["class"])
+            .add_tag_attributes("pre", ["class"])
+            .add_tag_attributes("sup", ["class"])
+            .add_tag_attributes("div", ["class", "id"])
+            .id_prefix(Some(ID_PREFIX))
+            .attribute_filter(|element, attribute, value| match (element, attribute) {
+                // task list checkboxes only
+                ("input", "type") if value != "checkbox" => None,
+                ("a", "href") if value.starts_with('#') && !value[1..].starts_with(ID_PREFIX) => {
+                    Some(format!("#{ID_PREFIX}{}", &value[1..]).into())
+                }
+                _ => Some(value.into()),
+            })
+            .link_rel(Some("noopener noreferrer nofollow"))
+            .url_schemes(HashSet::from(["http", "https", "mailto"]));
+        builder
+    };
+}
+
+/// sha256 of the content, prefixed with the renderer version.
+pub fn content_hash(content: &str) -> String {
+    let digest = Sha256::digest(content.as_bytes());
+    let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+    format!("{RENDERER_VERSION}:{hex}")
+}
+
+fn highlight_code(lang: &str, code: &str) -> String {
+    let syntax = SYNTAX_SET
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
+    let mut generator =
+        ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAX_SET, HIGHLIGHT_CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        if generator
+            .parse_html_for_line_which_includes_newline(line)
+            .is_err()
+        {
+            return escape_html(code);
+        }
+    }
+    generator.finalize()
+}
+
+/// escaped for html text and quoted attribute values.
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// parsed markdown with fenced code highlighted. without `raw_html` html written in the
+/// markdown is turned into text.
+fn markdown_events(content: &str, raw_html: bool) -> Vec<Event<'_>> {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_FOOTNOTES;
+    let mut events = Vec::new();
+    let mut code: Option<(String, String)> = None; // (lang, text) of the fenced block being read
+    for event in Parser::new_ext(content, options) {
+        match (event, &mut code) {
+            (Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))), None) => {
+                let lang = lang.split_whitespace().next().unwrap_or_default();
+                code = Some((lang.to_owned(), String::new()));
+            }
+            (Event::Text(text), Some((_, buf))) => buf.push_str(&text),
+            (Event::Html(html) | Event::InlineHtml(html), None) if !raw_html => {
+                events.push(Event::Text(html))
+            }
+            (Event::End(TagEnd::CodeBlock), Some((lang, buf))) => {
+                let class = if lang.is_empty() {
+                    String::new()
+                } else {
+                    format!(" class=\"language-{}\"", escape_html(lang))
+                };
+                events.push(Event::Html(
+                    format!(
+                        "<pre class=\"hl-code\"><code{class}>{}</code></pre>\n",
+                        highlight_code(lang, buf)
+                    )
+                    .into(),
+                ));
+                code = None;
+            }
+            (event, _) => events.push(event),
+        }
+    }
+    events
+}
+
+/// markdown to sanitized html, with GFM tables, task lists, strikethrough, footnotes
+/// and highlighted fenced code.
+pub fn render_markdown(content: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, markdown_events(content, true).into_iter());
+    SANITIZER.clean(&html).to_string()
+}
+
+/// markdown to well-formed xhtml for e-books. html written in the markdown is kept as
+/// text and script links are dropped, there is no sanitizer to parse it.
+pub fn render_xhtml(content: &str) -> String {
+    let content: String = content
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    let events = markdown_events(&content, false)
+        .into_iter()
+        .map(|event| match event {
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) if is_script_url(&dest_url) => Event::Start(Tag::Link {
+                link_type,
+                dest_url: "#".into(),
+                title,
+                id,
+            }),
+            event => event,
+        });
+    let mut xhtml = String::new();
+    pulldown_cmark::html::push_html(&mut xhtml, events);
+    xhtml
+}
+
+fn is_script_url(url: &str) -> bool {
+    let url = url.trim_start().to_lowercase();
+    url.starts_with("javascript:") || url.starts_with("vbscript:")
+}
+
+/// rendered html of the post, from the cache unless its content changed since.
+pub fn get_post_html(post: &Post) -> ServiceResult<String> {
+    let conn = new_conn()?;
+    let hash = content_hash(&post.content);
+    let cached: Option<String> = conn
+        .query_row(
+            "SELECT html FROM rendered_post WHERE post_id = ?1 AND content_hash = ?2",
+            params![post.id, hash],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(html) = cached {
+        return Ok(html);
+    }
+    let html = render_markdown(&post.content);
+    conn.execute(
+        "INSERT OR REPLACE INTO rendered_post (post_id, content_hash, html, rendered_at) VALUES (?1, ?2, ?3, ?4)",
+        params![post.id, hash, html, Utc::now()],
+    )?;
+    Ok(html)
+}
+
+pub(crate) fn erase_rendered_post(conn: &Connection, post_id: &str) -> ServiceResult<()> {
+    conn.execute(
+        "DELETE FROM rendered_post WHERE post_id = ?1",
+        params![post_id],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        let html = render_markdown(
+            "| a | b |\n|---|---|\n| 1 | 2 |\n\n- [x] done\n\n<script>alert(1)</script>\n\n```rust\nfn main() {}\n```\n",
+        );
+        assert!(html.contains("<table>"));
+        assert!(html.contains("type=\"checkbox\""));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("class=\"language-rust\""));
+        assert!(html.contains("hl-"));
+
+        let html = render_markdown("a[^n]\n\n[^n]: note\n\n<div id=\"app\"></div>\n");
+        assert!(html.contains("href=\"#user-content-n\""));
+        assert!(html.contains("id=\"user-content-n\""));
+        assert!(html.contains("id=\"user-content-app\""));
+    }
+}
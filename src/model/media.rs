@@ -0,0 +1,130 @@
+use std::{
+    fs,
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+};
+
+use image::{imageops::FilterType, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+
+use crate::error::{ServiceError, ServiceResult};
+
+use super::attachment::attachment_dir;
+
+/// square sizes every avatar is stored in, the first one is the default.
+pub const AVATAR_SIZES: [u32; 3] = [256, 128, 64];
+/// bounding boxes thumbnails of image attachments can be asked for.
+pub const THUMBNAIL_SIZES: [u32; 3] = [128, 256, 512];
+
+const MAX_IMAGE_DIMENSION: u32 = 12_000;
+const MAX_IMAGE_ALLOC: u64 = 256 * 1024 * 1024;
+
+/// decode an uploaded image, turned upright by its EXIF orientation. metadata is not
+/// carried over, so encoding the result strips it.
+pub fn decode_image(bytes: &[u8]) -> ServiceResult<DynamicImage> {
+    let invalid =
+        |err: &dyn std::fmt::Display| ServiceError::BadRequest(format!("invalid image: {err}"));
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|err| invalid(&err))?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    limits.max_alloc = Some(MAX_IMAGE_ALLOC);
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder().map_err(|err| invalid(&err))?;
+    let orientation = decoder.orientation().map_err(|err| invalid(&err))?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|err| invalid(&err))?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+pub fn encode_png(image: &DynamicImage) -> ServiceResult<Vec<u8>> {
+    let mut bytes = Cursor::new(Vec::new());
+    image
+        .write_to(&mut bytes, ImageFormat::Png)
+        .map_err(|err| ServiceError::InternalServerError(format!("encode png: {err}")))?;
+    Ok(bytes.into_inner())
+}
+
+/// center crop to a square of `size`.
+pub fn square(image: &DynamicImage, size: u32) -> DynamicImage {
+    image.resize_to_fill(size, size, FilterType::Lanczos3)
+}
+
+/// scaled down to fit in `size` x `size`, small images are left as they are.
+pub fn fit(image: &DynamicImage, size: u32) -> DynamicImage {
+    if image.width() <= size && image.height() <= size {
+        image.clone()
+    } else {
+        image.resize(size, size, FilterType::Lanczos3)
+    }
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let partial = path.with_extension("partial");
+    fs::write(&partial, bytes)?;
+    fs::rename(&partial, path)
+}
+
+pub fn avatar_path(user_id: &str, size: u32) -> PathBuf {
+    attachment_dir()
+        .join("avatars")
+        .join(format!("{user_id}-{size}.png"))
+}
+
+/// store the avatar in every size of `AVATAR_SIZES`.
+pub fn store_avatar(user_id: &str, image: &DynamicImage) -> ServiceResult<()> {
+    for size in AVATAR_SIZES {
+        write_atomic(
+            &avatar_path(user_id, size),
+            &encode_png(&square(image, size))?,
+        )?;
+    }
+    Ok(())
+}
+
+pub fn thumbnail_path(sha256: &str, size: u32) -> PathBuf {
+    attachment_dir()
+        .join("thumbnails")
+        .join(format!("{sha256}-{size}.png"))
+}
+
+/// the thumbnail of an image blob, generated on first use.
+pub fn get_or_make_thumbnail(blob: &Path, sha256: &str, size: u32) -> ServiceResult<PathBuf> {
+    let path = thumbnail_path(sha256, size);
+    if !path.exists() {
+        let image = decode_image(&fs::read(blob)?)?;
+        write_atomic(&path, &encode_png(&fit(&image, size))?)?;
+    }
+    Ok(path)
+}
+
+/// drop the thumbnails of a blob that is gone.
+pub fn remove_thumbnails(sha256: &str) -> io::Result<()> {
+    for size in THUMBNAIL_SIZES {
+        match fs::remove_file(thumbnail_path(sha256, size)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square_and_fit() {
+        let image = DynamicImage::new_rgb8(400, 200);
+        let png = encode_png(&image).unwrap();
+        let decoded = decode_image(&png).unwrap();
+        let avatar = square(&decoded, 128);
+        assert_eq!((avatar.width(), avatar.height()), (128, 128));
+        let thumbnail = fit(&decoded, 100);
+        assert_eq!((thumbnail.width(), thumbnail.height()), (100, 50));
+        assert!(decode_image(b"not an image").is_err());
+    }
+}
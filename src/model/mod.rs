@@ -1,6 +1,28 @@
+pub mod attachment;
+pub mod category;
+pub mod change;
 pub mod comment;
+pub mod directory;
+pub mod enex;
+pub mod epub;
+pub mod export;
+pub mod import;
+pub mod joplin;
+pub mod link;
+pub mod listing;
+pub mod markdown;
+pub mod media;
 pub mod post;
+pub mod reaction;
+pub mod read;
+pub mod redirect;
 pub mod repo;
+pub mod revision;
+pub mod search;
+pub mod site;
 pub mod subscribe;
 pub mod sync;
+pub mod tag;
+pub mod tombstone;
+pub mod upload;
 pub mod user;
@@ -1,11 +1,68 @@
+use std::str::FromStr;
+
 use chrono::{DateTime, Utc};
-use rusqlite::params;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row, ToSql};
 use salvo::{writing::Json, Scribe};
 use serde::{Deserialize, Serialize};
 
-use crate::{db::new_conn, error::ServiceResult};
+use tracing::info;
+
+use crate::{
+    db::new_conn,
+    error::{ServiceError, ServiceResult},
+};
+
+use super::{
+    attachment::{copy_post_attachments, erase_post_attachments, set_post_attachments},
+    category::{query_category, resolve_category},
+    change::{record_change, ChangeKind, ChangeOp},
+    comment::{move_post_comments, Comment, OpenApiCommentSummaryResponse},
+    link::{erase_post_links, set_post_links},
+    listing::{ListColumns, ListQuery, Page},
+    markdown::erase_rendered_post,
+    reaction::{erase_reactions, OpenApiReactionResponse},
+    read::erase_post_reads,
+    redirect::{erase_redirects, record_redirect},
+    repo::set_etag,
+    revision::{archive_post, erase_post_revisions},
+    search::{index_post, unindex},
+    tag::{list_post_tags, normalize_tags, set_post_tags},
+    tombstone::{query_tombstone, record_tombstone, remove_tombstone, Tombstone},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostState {
+    Draft,
+    Published,
+    Scheduled, // published by the scheduler once `publish_at` has passed
+}
+
+impl FromStr for PostState {
+    type Err = ServiceError;
 
-use super::comment::{Comment, OpenApiCommentSummaryResponse};
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "draft" => Ok(Self::Draft),
+            "published" => Ok(Self::Published),
+            "scheduled" => Ok(Self::Scheduled),
+            _ => Err(ServiceError::InternalServerError(
+                "invalid post state".to_owned(),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for PostState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match self {
+            Self::Draft => "draft",
+            Self::Published => "published",
+            Self::Scheduled => "scheduled",
+        };
+        write!(f, "{}", state)
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Post {
@@ -17,6 +74,40 @@ pub struct Post {
     pub updated_at: DateTime<Utc>,
     pub author: String,
     pub repo_id: String,
+    pub version: i64, // bumped on every update
+    pub category_id: Option<String>,
+    pub tags: Vec<String>,
+    pub state: PostState,
+    pub publish_at: Option<DateTime<Utc>>,
+}
+
+impl Post {
+    /// drafts and scheduled posts are only shown to their author.
+    pub fn is_visible_to(&self, user_id: &str) -> bool {
+        self.state == PostState::Published || self.author == user_id
+    }
+}
+
+/// the columns `post_from_row` reads, in order.
+pub(crate) const POST_COLUMNS: &str = "id, title, category, content, created_at, updated_at, author, repo_id, version, category_id, state, publish_at";
+
+pub(crate) fn post_from_row(conn: &Connection, row: &Row) -> ServiceResult<Post> {
+    let id: String = row.get(0)?;
+    Ok(Post {
+        title: row.get(1)?,
+        category: row.get(2)?,
+        content: row.get(3)?,
+        created_at: row.get(4)?,
+        updated_at: row.get(5)?,
+        author: row.get(6)?,
+        repo_id: row.get(7)?,
+        version: row.get(8)?,
+        category_id: row.get(9)?,
+        tags: list_post_tags(conn, &id)?,
+        state: PostState::from_str(&row.get::<_, String>(10)?)?,
+        publish_at: row.get(11)?,
+        id,
+    })
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,6 +117,7 @@ pub struct PostSummary {
     pub category: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub version: i64,
 }
 
 impl From<Post> for PostSummary {
@@ -36,14 +128,16 @@ impl From<Post> for PostSummary {
             category: post.category,
             created_at: post.created_at,
             updated_at: post.updated_at,
+            version: post.version,
         }
     }
 }
 
-pub fn add_post(post: &Post) -> ServiceResult<()> {
-    let conn = new_conn()?;
+/// add the post, clearing the tombstone of a resurrected one.
+fn insert_post(conn: &Connection, post: &Post) -> ServiceResult<()> {
+    remove_tombstone(conn, ChangeKind::Post, &post.id)?;
     conn.execute(
-        "INSERT INTO post (id, title, category, content, created_at, updated_at, author, repo_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        "INSERT INTO post (id, title, category, content, created_at, updated_at, author, repo_id, version, category_id, state, publish_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
         params![
             post.id,
             post.title,
@@ -52,78 +146,421 @@ pub fn add_post(post: &Post) -> ServiceResult<()> {
             post.created_at,
             post.updated_at,
             post.author,
-            post.repo_id
+            post.repo_id,
+            post.version,
+            post.category_id,
+            post.state.to_string(),
+            post.publish_at
         ],
     )?;
+    set_post_tags(conn, &post.id, &post.repo_id, &post.tags)?;
+    set_post_attachments(conn, post)?;
+    set_post_links(conn, post)?;
+    record_change(
+        conn,
+        &post.repo_id,
+        ChangeKind::Post,
+        &post.id,
+        ChangeOp::Upsert,
+    )?;
+    index_post(conn, post)?;
     Ok(())
 }
 
-pub fn list_posts_by_repo_id(repo_id: &str) -> ServiceResult<Vec<Post>> {
+const POST_LIST_COLUMNS: ListColumns = ListColumns {
+    id: "id",
+    created_at: "created_at",
+    updated_at: "updated_at",
+    title: Some("title"),
+    author: Some("author"),
+    category: Some(("category_id", "category")),
+};
+
+/// posts of a repo `viewer` can see, only those tagged `tag` if given.
+pub fn list_posts_by_repo_id(
+    repo_id: &str,
+    viewer: &str,
+    tag: Option<&str>,
+    query: &ListQuery,
+) -> ServiceResult<Page<Post>> {
     let conn = new_conn()?;
-    let mut stmt = conn.prepare("SELECT id, title, category, content, created_at, updated_at, author, repo_id FROM post WHERE repo_id = ?1")?;
-    let mut rows = stmt.query(params![repo_id])?;
+    let (list_sql, list_params) = query.sql(&POST_LIST_COLUMNS, 4)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {POST_COLUMNS}, {} FROM post
+        WHERE repo_id = ?1 AND (state = 'published' OR author = ?2)
+            AND (?3 IS NULL OR id IN (SELECT post_id FROM post_tag WHERE tag = ?3)){list_sql}",
+        query.sort_column(&POST_LIST_COLUMNS)?
+    ))?;
+    let tag = tag.map(|tag| tag.trim().to_lowercase());
+    let mut values: Vec<Box<dyn ToSql>> = vec![
+        Box::new(repo_id.to_owned()),
+        Box::new(viewer.to_owned()),
+        Box::new(tag),
+    ];
+    values.extend(list_params);
+    let mut rows = stmt.query(params_from_iter(values.iter()))?;
     let mut posts = Vec::new();
     while let Some(row) = rows.next()? {
-        let post = Post {
-            id: row.get(0)?,
-            title: row.get(1)?,
-            category: row.get(2)?,
-            content: row.get(3)?,
-            created_at: row.get(4)?,
-            updated_at: row.get(5)?,
-            author: row.get(6)?,
-            repo_id: row.get(7)?,
-        };
-        posts.push(post);
+        // the sort key follows the 12 post columns
+        posts.push((post_from_row(&conn, row)?, query.cursor(row, 0, 12)?));
     }
-    Ok(posts)
+    Ok(query.page(posts))
 }
 
 pub fn get_post_by_id(id: &str) -> ServiceResult<Option<Post>> {
     let conn = new_conn()?;
-    let mut stmt = conn.prepare("SELECT id, title, category, content, created_at, updated_at, author, repo_id FROM post WHERE id = ?1")?;
+    query_post(&conn, id)
+}
+
+pub(crate) fn query_post(conn: &Connection, id: &str) -> ServiceResult<Option<Post>> {
+    let mut stmt = conn.prepare(&format!("SELECT {POST_COLUMNS} FROM post WHERE id = ?1"))?;
     let mut rows = stmt.query(params![id])?;
-    let row = rows.next()?;
-    match row {
-        Some(row) => {
-            let post = Post {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                category: row.get(2)?,
-                content: row.get(3)?,
-                created_at: row.get(4)?,
-                updated_at: row.get(5)?,
-                author: row.get(6)?,
-                repo_id: row.get(7)?,
-            };
-            Ok(Some(post))
-        }
+    match rows.next()? {
+        Some(row) => Ok(Some(post_from_row(conn, row)?)),
         None => Ok(None),
     }
 }
 
-pub fn update_post(post: &Post) -> ServiceResult<()> {
+/// update the post, keeping the replaced version in `post_revision`.
+/// with `expected_version` the write only happens if the stored version still equals it.
+/// returns the new version, none if the post is missing or the version has moved on.
+pub fn update_post(post: &Post, expected_version: Option<i64>) -> ServiceResult<Option<i64>> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let version = write_post(&tx, post, expected_version)?;
+    if version.is_some() {
+        tx.commit()?;
+    }
+    Ok(version)
+}
+
+fn write_post(
+    conn: &Connection,
+    post: &Post,
+    expected_version: Option<i64>,
+) -> ServiceResult<Option<i64>> {
+    archive_post(conn, post)?;
+    let version = conn
+        .query_row(
+            "UPDATE post SET title = ?1, category = ?2, content = ?3, updated_at = ?4, repo_id = ?5, category_id = ?8, state = ?9, publish_at = ?10, version = version + 1
+            WHERE id = ?6 AND (?7 IS NULL OR version = ?7) RETURNING version",
+            params![
+                post.title,
+                post.category,
+                post.content,
+                post.updated_at,
+                post.repo_id,
+                post.id,
+                expected_version,
+                post.category_id,
+                post.state.to_string(),
+                post.publish_at
+            ],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if version.is_some() {
+        set_post_tags(conn, &post.id, &post.repo_id, &post.tags)?;
+        set_post_attachments(conn, post)?;
+        set_post_links(conn, post)?;
+        record_change(
+            conn,
+            &post.repo_id,
+            ChangeKind::Post,
+            &post.id,
+            ChangeOp::Upsert,
+        )?;
+        index_post(conn, post)?;
+    }
+    Ok(version)
+}
+
+/// the version conflict error of a post, carrying its current copy.
+pub fn post_version_conflict(post_id: &str) -> ServiceResult<ServiceError> {
     let conn = new_conn()?;
-    conn.execute(
-        "UPDATE post SET title = ?1, category = ?2, content = ?3, updated_at = ?4, repo_id = ?5 WHERE id = ?6",
-        params![
-            post.title,
-            post.category,
-            post.content,
-            post.updated_at,
-            post.repo_id,
-            post.id
-        ],
-    )?;
+    version_conflict(&conn, post_id)
+}
+
+fn version_conflict(conn: &Connection, post_id: &str) -> ServiceResult<ServiceError> {
+    let current = query_post(conn, post_id)?
+        .map(OpenApiGetPostResponse::from)
+        .ok_or(ServiceError::NotFound("post not found".to_owned()))?;
+    info!(
+        "post {post_id} version conflict, current {}",
+        current.version
+    );
+    serde_json::to_value(current)
+        .map(ServiceError::VersionConflict)
+        .map_err(|err| ServiceError::InternalServerError(err.to_string()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+    Created,
+    Updated,
+}
+
+/// check `publish_at` against the state, a scheduled post already due is published right away.
+fn schedule_post(post: &mut Post) -> ServiceResult<()> {
+    match (post.state, post.publish_at) {
+        (PostState::Scheduled, None) => {
+            return Err(ServiceError::BadRequest(
+                "publishAt is required for a scheduled post".to_owned(),
+            ))
+        }
+        (PostState::Scheduled, Some(publish_at)) if publish_at <= Utc::now() => {
+            post.state = PostState::Published;
+        }
+        (PostState::Scheduled, _) => {}
+        (PostState::Draft, _) => post.publish_at = None,
+        (PostState::Published, _) => {}
+    }
     Ok(())
 }
 
-pub fn erase_post(id: &str) -> ServiceResult<()> {
-    let conn = new_conn()?;
-    conn.execute("DELETE FROM post WHERE id = ?1", params![id])?;
+/// add or update the pushed post of `repo_id` on `conn`, the caller owns the transaction.
+/// the category is resolved, and tags and state left out of the push are kept.
+fn apply_push(
+    conn: &Connection,
+    repo_id: &str,
+    req: OpenApiPushPostRequest,
+    expected_version: Option<i64>,
+) -> ServiceResult<(Post, PushOutcome)> {
+    let resurrect = req.resurrect;
+    let keep_tags = req.tags.is_none();
+    let keep_state = req.state.is_none();
+    let mut post: Post = req.into();
+    post.tags = normalize_tags(post.tags)?;
+    if post.repo_id != repo_id {
+        return Err(ServiceError::NotFound("repo_id not match".to_owned()));
+    }
+    let category = match &post.category_id {
+        Some(category_id) => query_category(conn, category_id)?
+            .filter(|category| category.repo_id == repo_id)
+            .ok_or(ServiceError::BadRequest(format!(
+                "category {category_id} not found in repo"
+            )))?,
+        None => resolve_category(conn, repo_id, &post.category)?,
+    };
+    post.category = category.name;
+    post.category_id = Some(category.id);
+    let old_post = query_post(conn, &post.id)?;
+    if let Some(old_post) = &old_post {
+        if old_post.repo_id != repo_id {
+            return Err(ServiceError::Forbidden(format!(
+                "post {} belongs to another repo",
+                post.id
+            )));
+        }
+        if keep_tags {
+            post.tags = old_post.tags.clone();
+        }
+        if keep_state {
+            post.state = old_post.state;
+            post.publish_at = post.publish_at.or(old_post.publish_at);
+        }
+    }
+    schedule_post(&mut post)?;
+    if old_post.is_some() {
+        info!("update post {}", post.id);
+        let Some(version) = write_post(conn, &post, expected_version)? else {
+            return Err(version_conflict(conn, &post.id)?);
+        };
+        post.version = version;
+        return Ok((post, PushOutcome::Updated));
+    }
+    if let Some(tombstone) = query_tombstone(conn, ChangeKind::Post, &post.id)? {
+        if !resurrect {
+            return Err(ServiceError::Conflict(format!(
+                "post {} was deleted at {}, push with resurrect to restore it",
+                post.id, tombstone.deleted_at
+            )));
+        }
+        info!("resurrect post {}", post.id);
+    }
+    info!("add post {}", post.id);
+    insert_post(conn, &post)?;
+    Ok((post, PushOutcome::Created))
+}
+
+/// add or update a pushed post of `repo_id`.
+pub fn push_post(
+    repo_id: &str,
+    req: OpenApiPushPostRequest,
+    expected_version: Option<i64>,
+) -> ServiceResult<(Post, PushOutcome)> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let pushed = apply_push(&tx, repo_id, req, expected_version)?;
+    tx.commit()?;
+    Ok(pushed)
+}
+
+/// push many posts of `repo_id` in one transaction, each in a savepoint of its own so a
+/// rejected one leaves the others applied. with `atomic` any rejection rolls back them all.
+pub fn push_posts(
+    repo_id: &str,
+    items: Vec<OpenApiPushPostRequest>,
+    atomic: bool,
+) -> ServiceResult<OpenApiBatchPushResponse> {
+    let mut conn = new_conn()?;
+    let mut tx = conn.transaction()?;
+    let mut results = Vec::with_capacity(items.len());
+    for item in items {
+        let id = item.id.clone();
+        let expected_version = item.expected_version;
+        let savepoint = tx.savepoint()?;
+        let result = match apply_push(&savepoint, repo_id, item, expected_version) {
+            Ok((post, outcome)) => {
+                savepoint.commit()?;
+                let status = match outcome {
+                    PushOutcome::Created => BatchPushStatus::Created,
+                    PushOutcome::Updated => BatchPushStatus::Updated,
+                };
+                OpenApiBatchPushResult::new(id, status, Some(post.version), None)
+            }
+            Err(ServiceError::VersionConflict(current)) => {
+                let version = current.get("version").and_then(|v| v.as_i64());
+                OpenApiBatchPushResult::new(
+                    id,
+                    BatchPushStatus::Conflict,
+                    version,
+                    Some("version mismatch".to_owned()),
+                )
+            }
+            Err(ServiceError::Conflict(err)) => {
+                OpenApiBatchPushResult::new(id, BatchPushStatus::Conflict, None, Some(err))
+            }
+            Err(ServiceError::Forbidden(err) | ServiceError::NotFound(err)) => {
+                OpenApiBatchPushResult::new(id, BatchPushStatus::Forbidden, None, Some(err))
+            }
+            Err(ServiceError::BadRequest(err)) => {
+                OpenApiBatchPushResult::new(id, BatchPushStatus::Invalid, None, Some(err))
+            }
+            Err(err) => return Err(err),
+        };
+        results.push(result);
+    }
+    let rejected = results.iter().any(|result| !result.status.is_applied());
+    if atomic && rejected {
+        for result in &mut results {
+            if result.status.is_applied() {
+                result.status = BatchPushStatus::Aborted;
+                result.version = None;
+            }
+        }
+        return Ok(OpenApiBatchPushResponse {
+            applied: false,
+            results,
+        });
+    }
+    tx.commit()?;
+    Ok(OpenApiBatchPushResponse {
+        applied: true,
+        results,
+    })
+}
+
+/// move the post with its comments, tags and attachments to `to_repo_id`, leaving a
+/// redirect behind. the category is resolved by name in the new repo, and links to the
+/// old repo's attachments point at the new one. returns none if the version has moved on.
+pub fn move_post(
+    post_id: &str,
+    to_repo_id: &str,
+    expected_version: Option<i64>,
+) -> ServiceResult<Option<Post>> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let Some(mut post) = query_post(&tx, post_id)? else {
+        return Err(ServiceError::NotFound("post not found".to_owned()));
+    };
+    let from_repo_id = std::mem::replace(&mut post.repo_id, to_repo_id.to_owned());
+    let category = resolve_category(&tx, to_repo_id, &post.category)?;
+    post.category = category.name;
+    post.category_id = Some(category.id);
+    post.content = post.content.replace(
+        &format!("/repo/{from_repo_id}/attachment/"),
+        &format!("/repo/{to_repo_id}/attachment/"),
+    );
+    copy_post_attachments(&tx, &post.id, &from_repo_id, to_repo_id)?;
+    let Some(version) = write_post(&tx, &post, expected_version)? else {
+        return Ok(None);
+    };
+    post.version = version;
+    move_post_comments(&tx, &post.id, &from_repo_id, to_repo_id)?;
+    record_change(
+        &tx,
+        &from_repo_id,
+        ChangeKind::Post,
+        &post.id,
+        ChangeOp::Delete,
+    )?;
+    record_redirect(&tx, &post.id, &from_repo_id, to_repo_id)?;
+    tx.commit()?;
+    Ok(Some(post))
+}
+
+/// delete the post, leaving a tombstone signed by `deleted_by`.
+pub fn erase_post(id: &str, deleted_by: &str) -> ServiceResult<()> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let repo_id: Option<String> = tx
+        .query_row(
+            "SELECT repo_id FROM post WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    erase_post_revisions(&tx, id)?;
+    tx.execute("DELETE FROM post_tag WHERE post_id = ?1", params![id])?;
+    erase_rendered_post(&tx, id)?;
+    erase_post_attachments(&tx, id)?;
+    erase_post_links(&tx, id)?;
+    erase_redirects(&tx, id)?;
+    erase_reactions(&tx, ChangeKind::Post, id)?;
+    erase_post_reads(&tx, id)?;
+    tx.execute("DELETE FROM post WHERE id = ?1", params![id])?;
+    unindex(&tx, ChangeKind::Post, id)?;
+    if let Some(repo_id) = repo_id {
+        record_change(&tx, &repo_id, ChangeKind::Post, id, ChangeOp::Delete)?;
+        record_tombstone(
+            &tx,
+            &Tombstone {
+                kind: ChangeKind::Post,
+                entity_id: id.to_owned(),
+                repo_id,
+                deleted_at: Utc::now(),
+                deleted_by: deleted_by.to_owned(),
+            },
+        )?;
+    }
+    tx.commit()?;
     Ok(())
 }
 
+/// publish the scheduled posts whose `publish_at` is not after `now`, returns how many.
+pub fn publish_due_posts(now: DateTime<Utc>) -> ServiceResult<usize> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let published = {
+        let mut stmt = tx.prepare(
+            "UPDATE post SET state = 'published', version = version + 1
+            WHERE state = 'scheduled' AND publish_at <= ?1 RETURNING id, repo_id",
+        )?;
+        let due = stmt
+            .query_map(params![now], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (post_id, repo_id) in &due {
+            record_change(&tx, repo_id, ChangeKind::Post, post_id, ChangeOp::Upsert)?;
+        }
+        due.len()
+    };
+    tx.commit()?;
+    Ok(published)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OpenApiPushPostRequest {
@@ -135,6 +572,13 @@ pub struct OpenApiPushPostRequest {
     pub updated_at: DateTime<Utc>,
     pub author: String,
     pub repo_id: String,
+    pub expected_version: Option<i64>, // same as `If-Match`
+    #[serde(default)]
+    pub resurrect: bool, // push again a post that has been deleted
+    pub category_id: Option<String>,   // takes precedence over `category`
+    pub tags: Option<Vec<String>>,     // none keeps the current tags
+    pub state: Option<PostState>,      // none keeps the current state, new posts are published
+    pub publish_at: Option<DateTime<Utc>>, // required when scheduled
 }
 
 impl From<OpenApiPushPostRequest> for Post {
@@ -148,6 +592,11 @@ impl From<OpenApiPushPostRequest> for Post {
             updated_at: value.updated_at,
             author: value.author,
             repo_id: value.repo_id,
+            version: 1,
+            category_id: value.category_id,
+            tags: value.tags.unwrap_or_default(),
+            state: value.state.unwrap_or(PostState::Published),
+            publish_at: value.publish_at,
         }
     }
 }
@@ -163,6 +612,15 @@ pub struct OpenApiGetPostResponse {
     pub updated_at: DateTime<Utc>,
     pub author: String,
     pub repo_id: String,
+    pub version: i64,
+    pub category_id: Option<String>,
+    pub tags: Vec<String>,
+    pub state: PostState,
+    pub publish_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>, // sanitized rendering of `content`, with `render=html`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reactions: Option<Vec<OpenApiReactionResponse>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -171,8 +629,19 @@ pub struct OpenApiPostSummaryResponse {
     pub id: String,
     pub title: String,
     pub category: String,
+    pub category_id: Option<String>,
     pub updated_at: DateTime<Utc>,
+    pub version: i64,
+    pub tags: Vec<String>,
+    pub state: PostState,
+    pub publish_at: Option<DateTime<Utc>>,
     pub comments: Vec<OpenApiCommentSummaryResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reactions: Option<Vec<OpenApiReactionResponse>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unread: Option<bool>, // changed since the caller read it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unread_comments: Option<u64>,
 }
 
 impl OpenApiPostSummaryResponse {
@@ -181,14 +650,98 @@ impl OpenApiPostSummaryResponse {
             id: post.id,
             title: post.title,
             category: post.category,
+            category_id: post.category_id,
             updated_at: post.updated_at,
+            version: post.version,
+            tags: post.tags,
+            state: post.state,
+            publish_at: post.publish_at,
             comments: comments.into_iter().map(Into::into).collect(),
+            reactions: None,
+            unread: None,
+            unread_comments: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiListPostResponse {
+    pub posts: Vec<OpenApiPostSummaryResponse>,
+    pub next_cursor: Option<String>, // pass back as `cursor` for the next page, none on the last one
+    #[serde(skip)]
+    pub paged: bool, // rendered as a bare array when not, like before paging
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiMovePostRequest {
+    pub repo_id: String,               // where to
+    pub expected_version: Option<i64>, // same as `If-Match`
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiBatchPushRequest {
+    pub posts: Vec<OpenApiPushPostRequest>,
+    #[serde(default)]
+    pub atomic: bool, // apply every post or none of them
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BatchPushStatus {
+    Created,
+    Updated,
+    Conflict,  // version mismatch, or deleted and not resurrected
+    Forbidden, // another repo's post
+    Invalid,
+    Aborted, // would have been applied, rolled back with an atomic batch
+}
+
+impl BatchPushStatus {
+    pub fn is_applied(&self) -> bool {
+        matches!(self, Self::Created | Self::Updated)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiBatchPushResult {
+    pub id: String,
+    pub status: BatchPushStatus,
+    pub version: Option<i64>, // the new one, or the current one on a version conflict
+    pub error: Option<String>,
+}
+
+impl OpenApiBatchPushResult {
+    fn new(
+        id: String,
+        status: BatchPushStatus,
+        version: Option<i64>,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            id,
+            status,
+            version,
+            error,
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct OpenApiListPostResponse(pub Vec<OpenApiPostSummaryResponse>);
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiBatchPushResponse {
+    pub applied: bool, // false when an atomic batch was rolled back
+    pub results: Vec<OpenApiBatchPushResult>, // in request order
+}
+
+impl Scribe for OpenApiBatchPushResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
 
 impl From<Post> for OpenApiGetPostResponse {
     fn from(post: Post) -> Self {
@@ -201,18 +754,286 @@ impl From<Post> for OpenApiGetPostResponse {
             updated_at: post.updated_at,
             author: post.author,
             repo_id: post.repo_id,
+            version: post.version,
+            category_id: post.category_id,
+            tags: post.tags,
+            state: post.state,
+            publish_at: post.publish_at,
+            html: None,
+            reactions: None,
         }
     }
 }
 
 impl Scribe for OpenApiGetPostResponse {
     fn render(self, res: &mut salvo::Response) {
+        set_etag(res, self.version);
         res.render(Json(&self));
     }
 }
 
 impl Scribe for OpenApiListPostResponse {
     fn render(self, res: &mut salvo::Response) {
-        res.render(Json(&self));
+        if self.paged {
+            res.render(Json(&self));
+        } else {
+            res.render(Json(&self.posts));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        listing::{ListCursor, ListSort},
+        repo::{add_repo, Repo, RepoStatus},
+        user::{add_user, User},
+    };
+
+    /// a fresh user and a repo of theirs, returns (user_id, repo_id).
+    fn new_repo() -> anyhow::Result<(String, String)> {
+        crate::db::init_db()?;
+        let user = User::new(uuid::Uuid::new_v4().to_string(), "password".into());
+        add_user(&user)?;
+        let repo_id = new_repo_of(&user.id)?;
+        Ok((user.id, repo_id))
+    }
+
+    fn new_repo_of(owner: &str) -> anyhow::Result<String> {
+        let repo_id = uuid::Uuid::new_v4().to_string();
+        add_repo(&Repo {
+            id: repo_id.clone(),
+            name: "posts".into(),
+            owner: owner.into(),
+            description: String::new(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            status: RepoStatus::Normal,
+            public: false,
+            topics: vec![],
+            version: 1,
+            search_tokenizer: None,
+        })?;
+        Ok(repo_id)
+    }
+
+    fn new_post(author: &str, repo_id: &str, title: &str) -> Post {
+        Post {
+            id: uuid::Uuid::new_v4().to_string(),
+            title: title.into(),
+            category: "notes".into(),
+            content: String::new(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            author: author.into(),
+            repo_id: repo_id.into(),
+            version: 1,
+            category_id: None,
+            tags: vec![],
+            state: PostState::Published,
+            publish_at: None,
+        }
+    }
+
+    #[test]
+    fn test_scheduled_post() -> anyhow::Result<()> {
+        let (user_id, repo_id) = new_repo()?;
+        let publish_at = Utc::now() + chrono::Duration::hours(1);
+        insert_post(
+            &new_conn()?,
+            &Post {
+                state: PostState::Scheduled,
+                publish_at: Some(publish_at),
+                ..new_post(&user_id, &repo_id, "later")
+            },
+        )?;
+        let all = ListQuery::default();
+        assert_eq!(
+            list_posts_by_repo_id(&repo_id, &user_id, None, &all)?
+                .items
+                .len(),
+            1
+        );
+        assert!(list_posts_by_repo_id(&repo_id, "someone else", None, &all)?
+            .items
+            .is_empty());
+
+        publish_due_posts(publish_at)?;
+        let posts = list_posts_by_repo_id(&repo_id, "someone else", None, &all)?.items;
+        assert_eq!(posts.len(), 1);
+        assert_eq!(
+            (posts[0].state, posts[0].version),
+            (PostState::Published, 2)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_posts_page() -> anyhow::Result<()> {
+        let (user_id, repo_id) = new_repo()?;
+        for title in ["b", "C", "a"] {
+            insert_post(&new_conn()?, &new_post(&user_id, &repo_id, title))?;
+        }
+        let mut query = ListQuery {
+            sort: ListSort::Title,
+            desc: true,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = list_posts_by_repo_id(&repo_id, &user_id, None, &query)?;
+        let titles: Vec<_> = page.items.iter().map(|post| post.title.as_str()).collect();
+        assert_eq!(titles, ["C", "b"]);
+
+        query.after = Some(ListCursor::decode(&page.next_cursor.unwrap())?);
+        let page = list_posts_by_repo_id(&repo_id, &user_id, None, &query)?;
+        assert_eq!(page.items[0].title, "a");
+        assert!(page.next_cursor.is_none());
+
+        query.sort = ListSort::Created;
+        assert!(list_posts_by_repo_id(&repo_id, &user_id, None, &query).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_posts_batch() -> anyhow::Result<()> {
+        let (user_id, repo_id) = new_repo()?;
+        let request = |repo_id: &str| OpenApiPushPostRequest {
+            id: uuid::Uuid::new_v4().to_string(),
+            category: "notes".into(),
+            title: "batch".into(),
+            content: String::new(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            author: user_id.clone(),
+            repo_id: repo_id.into(),
+            expected_version: None,
+            resurrect: false,
+            category_id: None,
+            tags: None,
+            state: None,
+            publish_at: None,
+        };
+        let (good, bad) = (request(&repo_id), request("another repo"));
+        let good_id = good.id.clone();
+        let batch = push_posts(&repo_id, vec![good, bad], true)?;
+        assert!(!batch.applied);
+        let statuses: Vec<_> = batch.results.iter().map(|result| result.status).collect();
+        assert_eq!(
+            statuses,
+            [BatchPushStatus::Aborted, BatchPushStatus::Forbidden]
+        );
+        assert!(get_post_by_id(&good_id)?.is_none());
+
+        let good = request(&repo_id);
+        let stale = OpenApiPushPostRequest {
+            id: good.id.clone(),
+            expected_version: Some(7),
+            ..request(&repo_id)
+        };
+        let batch = push_posts(&repo_id, vec![good, stale], false)?;
+        assert!(batch.applied);
+        let results: Vec<_> = batch
+            .results
+            .iter()
+            .map(|result| (result.status, result.version))
+            .collect();
+        assert_eq!(
+            results,
+            [
+                (BatchPushStatus::Created, Some(1)),
+                (BatchPushStatus::Conflict, Some(1))
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_post() -> anyhow::Result<()> {
+        let (user_id, from) = new_repo()?;
+        let to = new_repo_of(&user_id)?;
+        let sha = "b".repeat(64);
+        crate::model::attachment::add_attachment(crate::model::attachment::Attachment {
+            repo_id: from.clone(),
+            sha256: sha.clone(),
+            name: "a.png".into(),
+            content_type: "image/png".into(),
+            size: 1,
+            uploader: user_id.clone(),
+            created_at: Utc::now(),
+        })?;
+        let post = Post {
+            content: format!("![a](/repo/{from}/attachment/{sha})"),
+            ..new_post(&user_id, &from, "moving")
+        };
+        insert_post(&new_conn()?, &post)?;
+        let comment = Comment {
+            id: uuid::Uuid::new_v4().to_string(),
+            post_id: post.id.clone(),
+            repo_id: from.clone(),
+            content: "hi".into(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            author: user_id.clone(),
+            parent_id: None,
+        };
+        crate::model::comment::add_comment(&comment)?;
+
+        assert!(move_post(&post.id, &to, Some(7))?.is_none());
+        let moved = move_post(&post.id, &to, Some(1))?.unwrap();
+        assert_eq!((moved.repo_id.as_str(), moved.version), (to.as_str(), 2));
+        assert_eq!(moved.content, format!("![a](/repo/{to}/attachment/{sha})"));
+        assert!(crate::model::attachment::get_attachment(&to, &sha)?.is_some());
+        let comment = crate::model::comment::get_comment_by_id(&comment.id)?.unwrap();
+        assert_eq!(comment.repo_id, to);
+        assert_eq!(
+            crate::model::redirect::get_redirect(&from, &post.id)?,
+            Some(to.clone())
+        );
+        let ops: Vec<_> = crate::model::change::list_repo_changes(&from, &user_id, 0, 10)?
+            .into_iter()
+            .map(|change| change.op.to_string())
+            .collect();
+        assert_eq!(ops, ["delete", "delete"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_links() -> anyhow::Result<()> {
+        use crate::model::link::{get_link_graph, list_backlinks};
+
+        let (user_id, repo_id) = new_repo()?;
+        let conn = new_conn()?;
+        let rust = new_post(&user_id, &repo_id, "Rust");
+        insert_post(&conn, &rust)?;
+        let notes = Post {
+            content: "[[rust|the language]] and [[Missing]]".into(),
+            ..new_post(&user_id, &repo_id, "Notes")
+        };
+        insert_post(&conn, &notes)?;
+        let by_id = Post {
+            content: format!("[[{}]]", rust.id),
+            ..new_post(&user_id, &repo_id, "By id")
+        };
+        insert_post(&conn, &by_id)?;
+        let backlinks: Vec<_> = list_backlinks(&repo_id, &rust.id, &user_id)?
+            .into_iter()
+            .map(|post| post.id)
+            .collect();
+        assert_eq!(backlinks, [by_id.id.clone(), notes.id.clone()]);
+        let graph = get_link_graph(&repo_id, &user_id)?;
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(graph.edges.len(), 2);
+        assert_eq!(graph.broken, [(notes.id.clone(), "Missing".to_owned())]);
+
+        // a post created later takes up the broken link
+        let missing = new_post(&user_id, &repo_id, "missing");
+        insert_post(&conn, &missing)?;
+        let graph = get_link_graph(&repo_id, &user_id)?;
+        assert!(graph.broken.is_empty());
+        assert!(graph
+            .edges
+            .contains(&(notes.id.clone(), missing.id.clone())));
+        Ok(())
     }
 }
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::new_conn,
+    error::{ServiceError, ServiceResult},
+};
+
+use super::change::ChangeKind;
+
+const MAX_EMOJI_CHARS: usize = 16; // long enough for zwj sequences like families
+
+/// the emoji trimmed, rejected unless it is a single short symbol. sequences joined
+/// with zwj and keycaps like `1️⃣` count as one.
+pub fn normalize_emoji(emoji: &str) -> ServiceResult<String> {
+    let emoji = emoji.trim();
+    let invalid = || ServiceError::BadRequest(format!("invalid emoji {emoji:?}"));
+    if emoji.is_empty()
+        || emoji.chars().count() > MAX_EMOJI_CHARS
+        || emoji.chars().any(|c| c.is_whitespace() || c.is_control())
+        || emoji.is_ascii()
+        || emoji.chars().any(|c| c.is_alphabetic())
+    {
+        return Err(invalid());
+    }
+    Ok(emoji.to_owned())
+}
+
+/// how many reacted to a post or comment with an emoji, and whether the viewer did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: u64,
+    pub reacted: bool,
+}
+
+/// add the reaction of `user_id`, false if it was there already.
+pub fn add_reaction(
+    kind: ChangeKind,
+    target_id: &str,
+    user_id: &str,
+    emoji: &str,
+) -> ServiceResult<bool> {
+    let conn = new_conn()?;
+    let added = conn.execute(
+        "INSERT OR IGNORE INTO reaction (kind, target_id, user_id, emoji, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![kind.to_string(), target_id, user_id, emoji, Utc::now()],
+    )?;
+    Ok(added > 0)
+}
+
+/// remove the reaction of `user_id`, false if there was none.
+pub fn remove_reaction(
+    kind: ChangeKind,
+    target_id: &str,
+    user_id: &str,
+    emoji: &str,
+) -> ServiceResult<bool> {
+    let conn = new_conn()?;
+    let removed = conn.execute(
+        "DELETE FROM reaction WHERE kind = ?1 AND target_id = ?2 AND user_id = ?3 AND emoji = ?4",
+        params![kind.to_string(), target_id, user_id, emoji],
+    )?;
+    Ok(removed > 0)
+}
+
+pub(crate) fn query_reactions(
+    conn: &Connection,
+    kind: ChangeKind,
+    target_ids: &[String],
+    viewer: &str,
+) -> ServiceResult<HashMap<String, Vec<ReactionCount>>> {
+    let mut stmt = conn.prepare(
+        "SELECT target_id, emoji, COUNT(*), MAX(user_id = ?3) FROM reaction
+        WHERE kind = ?1 AND target_id IN (SELECT value FROM json_each(?2))
+        GROUP BY target_id, emoji ORDER BY COUNT(*) DESC, MIN(created_at), emoji",
+    )?;
+    let target_ids = serde_json::json!(target_ids).to_string();
+    let mut rows = stmt.query(params![kind.to_string(), target_ids, viewer])?;
+    let mut reactions: HashMap<String, Vec<ReactionCount>> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        reactions
+            .entry(row.get(0)?)
+            .or_default()
+            .push(ReactionCount {
+                emoji: row.get(1)?,
+                count: row.get(2)?,
+                reacted: row.get(3)?,
+            });
+    }
+    Ok(reactions)
+}
+
+/// the reactions to a post or comment, most given first.
+pub fn list_reactions(
+    kind: ChangeKind,
+    target_id: &str,
+    viewer: &str,
+) -> ServiceResult<Vec<ReactionCount>> {
+    let conn = new_conn()?;
+    let mut reactions = query_reactions(&conn, kind, &[target_id.to_owned()], viewer)?;
+    Ok(reactions.remove(target_id).unwrap_or_default())
+}
+
+/// the reactions to each of the posts or comments, in one query.
+pub fn list_reactions_by_target_ids(
+    kind: ChangeKind,
+    target_ids: &[String],
+    viewer: &str,
+) -> ServiceResult<HashMap<String, Vec<ReactionCount>>> {
+    let conn = new_conn()?;
+    query_reactions(&conn, kind, target_ids, viewer)
+}
+
+pub(crate) fn erase_reactions(
+    conn: &Connection,
+    kind: ChangeKind,
+    target_id: &str,
+) -> ServiceResult<()> {
+    conn.execute(
+        "DELETE FROM reaction WHERE kind = ?1 AND target_id = ?2",
+        params![kind.to_string(), target_id],
+    )?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiReactionResponse {
+    pub emoji: String,
+    pub count: u64,
+    pub reacted: bool, // by the caller
+}
+
+impl From<ReactionCount> for OpenApiReactionResponse {
+    fn from(value: ReactionCount) -> Self {
+        Self {
+            emoji: value.emoji,
+            count: value.count,
+            reacted: value.reacted,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenApiListReactionResponse(pub Vec<OpenApiReactionResponse>);
+
+impl From<Vec<ReactionCount>> for OpenApiListReactionResponse {
+    fn from(value: Vec<ReactionCount>) -> Self {
+        Self(value.into_iter().map(Into::into).collect())
+    }
+}
+
+impl Scribe for OpenApiListReactionResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_emoji() {
+        for emoji in ["👍", " ❤️ ", "1️⃣", "👨‍👩‍👧‍👦", "🇫🇷"] {
+            assert_eq!(normalize_emoji(emoji).unwrap(), emoji.trim());
+        }
+        for emoji in ["", "+1", "👍 👍", "a👍", "是", "\u{200b}\u{0}"] {
+            assert!(normalize_emoji(emoji).is_err(), "{emoji:?}");
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use rusqlite::{params, Connection};
+
+use crate::{db::new_conn, error::ServiceResult};
+
+/// the `change_log.seq` up to which the user `?1` has read the post `p`, by marking it
+/// or its whole repo read. changes are compared by seq rather than time, post times are
+/// set by clients.
+const READ_SEQ_SQL: &str = "MAX(
+    COALESCE((SELECT read_seq FROM post_read WHERE user_id = ?1 AND post_id = p.id), 0),
+    COALESCE((SELECT read_seq FROM repo_read WHERE user_id = ?1 AND repo_id = p.repo_id), 0))";
+
+/// whether the post `p` changed since read, the user's own posts never count.
+fn unread_post_sql() -> String {
+    format!(
+        "p.author != ?1 AND EXISTS (SELECT 1 FROM change_log c
+        WHERE c.kind = 'post' AND c.entity_id = p.id AND c.repo_id = p.repo_id AND c.seq > {READ_SEQ_SQL})"
+    )
+}
+
+/// comments of the post `p` added or edited since read, leaving out the user's own.
+fn unread_comments_sql() -> String {
+    format!(
+        "SELECT COUNT(*) FROM comment m JOIN change_log c
+            ON c.kind = 'comment' AND c.entity_id = m.id AND c.repo_id = m.repo_id
+        WHERE m.post_id = p.id AND m.author != ?1 AND c.seq > {READ_SEQ_SQL}"
+    )
+}
+
+fn current_seq(conn: &Connection) -> ServiceResult<i64> {
+    Ok(
+        conn.query_row("SELECT COALESCE(MAX(seq), 0) FROM change_log", [], |row| {
+            row.get(0)
+        })?,
+    )
+}
+
+/// mark the post and its comments read as they are now.
+pub fn mark_post_read(user_id: &str, post_id: &str) -> ServiceResult<()> {
+    let conn = new_conn()?;
+    conn.execute(
+        "INSERT OR REPLACE INTO post_read (user_id, post_id, read_seq, read_at) VALUES (?1, ?2, ?3, ?4)",
+        params![user_id, post_id, current_seq(&conn)?, Utc::now()],
+    )?;
+    Ok(())
+}
+
+/// mark every post of the repo and their comments read as they are now.
+pub fn mark_repo_read(user_id: &str, repo_id: &str) -> ServiceResult<()> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT OR REPLACE INTO repo_read (user_id, repo_id, read_seq, read_at) VALUES (?1, ?2, ?3, ?4)",
+        params![user_id, repo_id, current_seq(&tx)?, Utc::now()],
+    )?;
+    // superseded by the repo's
+    tx.execute(
+        "DELETE FROM post_read WHERE user_id = ?1 AND post_id IN (SELECT id FROM post WHERE repo_id = ?2)",
+        params![user_id, repo_id],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PostUnread {
+    pub unread: bool,  // new or changed since read
+    pub comments: u64, // comments added or edited since
+}
+
+/// what changed in each of the posts since `user_id` read them, in one query.
+pub fn list_posts_unread(
+    user_id: &str,
+    post_ids: &[String],
+) -> ServiceResult<HashMap<String, PostUnread>> {
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT p.id, {}, ({}) FROM post p WHERE p.id IN (SELECT value FROM json_each(?2))",
+        unread_post_sql(),
+        unread_comments_sql()
+    ))?;
+    let post_ids = serde_json::json!(post_ids).to_string();
+    let mut rows = stmt.query(params![user_id, post_ids])?;
+    let mut unread = HashMap::new();
+    while let Some(row) = rows.next()? {
+        unread.insert(
+            row.get(0)?,
+            PostUnread {
+                unread: row.get(1)?,
+                comments: row.get(2)?,
+            },
+        );
+    }
+    Ok(unread)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RepoUnread {
+    pub posts: u64,
+    pub comments: u64,
+}
+
+/// how many posts `user_id` can see in each of the repos are unread, and how many
+/// comments, in one query. repos without posts are left out.
+pub fn list_repos_unread(
+    user_id: &str,
+    repo_ids: &[String],
+) -> ServiceResult<HashMap<String, RepoUnread>> {
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT p.repo_id, SUM({}), SUM(({})) FROM post p
+        WHERE p.repo_id IN (SELECT value FROM json_each(?2)) AND (p.state = 'published' OR p.author = ?1)
+        GROUP BY p.repo_id",
+        unread_post_sql(),
+        unread_comments_sql()
+    ))?;
+    let repo_ids = serde_json::json!(repo_ids).to_string();
+    let mut rows = stmt.query(params![user_id, repo_ids])?;
+    let mut unread = HashMap::new();
+    while let Some(row) = rows.next()? {
+        unread.insert(
+            row.get(0)?,
+            RepoUnread {
+                posts: row.get(1)?,
+                comments: row.get(2)?,
+            },
+        );
+    }
+    Ok(unread)
+}
+
+pub(crate) fn erase_post_reads(conn: &Connection, post_id: &str) -> ServiceResult<()> {
+    conn.execute("DELETE FROM post_read WHERE post_id = ?1", params![post_id])?;
+    Ok(())
+}
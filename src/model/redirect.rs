@@ -0,0 +1,47 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{db::new_conn, error::ServiceResult};
+
+/// remember that the post left `from_repo_id`. redirects of earlier moves are pointed at
+/// the new repo too, and one back into `to_repo_id` is dropped.
+pub(crate) fn record_redirect(
+    conn: &Connection,
+    post_id: &str,
+    from_repo_id: &str,
+    to_repo_id: &str,
+) -> ServiceResult<()> {
+    conn.execute(
+        "DELETE FROM post_redirect WHERE post_id = ?1 AND from_repo_id = ?2",
+        params![post_id, to_repo_id],
+    )?;
+    conn.execute(
+        "UPDATE post_redirect SET to_repo_id = ?2 WHERE post_id = ?1",
+        params![post_id, to_repo_id],
+    )?;
+    conn.execute(
+        "INSERT OR REPLACE INTO post_redirect (post_id, from_repo_id, to_repo_id, moved_at) VALUES (?1, ?2, ?3, ?4)",
+        params![post_id, from_repo_id, to_repo_id, Utc::now()],
+    )?;
+    Ok(())
+}
+
+/// the repo a post of `repo_id` has been moved to.
+pub fn get_redirect(repo_id: &str, post_id: &str) -> ServiceResult<Option<String>> {
+    let conn = new_conn()?;
+    Ok(conn
+        .query_row(
+            "SELECT to_repo_id FROM post_redirect WHERE post_id = ?1 AND from_repo_id = ?2",
+            params![post_id, repo_id],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+pub(crate) fn erase_redirects(conn: &Connection, post_id: &str) -> ServiceResult<()> {
+    conn.execute(
+        "DELETE FROM post_redirect WHERE post_id = ?1",
+        params![post_id],
+    )?;
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use std::str::FromStr;
 
 use chrono::{DateTime, Utc};
-use rusqlite::params;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, ToSql};
 use salvo::{writing::Json, Scribe};
 use serde::{Deserialize, Serialize};
 
@@ -10,6 +10,11 @@ use crate::{
     error::{ServiceError, ServiceResult},
 };
 
+use super::{
+    listing::{ListColumns, ListQuery, Page},
+    search::Tokenizer,
+};
+
 #[derive(Debug)]
 pub enum RepoStatus {
     Normal,
@@ -55,12 +60,47 @@ pub struct Repo {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub status: RepoStatus,
+    pub public: bool, // listed in the directory
+    pub topics: Vec<String>,
+    pub version: i64,                        // bumped on every update
+    pub search_tokenizer: Option<Tokenizer>, // none for the server default
+}
+
+const MAX_TOPICS: usize = 20;
+const MAX_TOPIC_LEN: usize = 32;
+
+/// `ETag` of a versioned resource, the quoted version number.
+pub fn set_etag(res: &mut salvo::Response, version: i64) {
+    if let Ok(value) = format!("\"{version}\"").parse() {
+        res.headers_mut().insert(salvo::http::header::ETAG, value);
+    }
+}
+
+/// trim, lowercase and dedup topics, rejecting empty or overlong ones.
+pub fn normalize_topics(topics: Vec<String>) -> ServiceResult<Vec<String>> {
+    let mut result: Vec<String> = Vec::new();
+    for topic in topics {
+        let topic = topic.trim().to_lowercase();
+        if topic.is_empty() || topic.chars().count() > MAX_TOPIC_LEN {
+            return Err(ServiceError::BadRequest(format!("invalid topic {topic:?}")));
+        }
+        if !result.contains(&topic) {
+            result.push(topic);
+        }
+    }
+    if result.len() > MAX_TOPICS {
+        return Err(ServiceError::BadRequest(format!(
+            "at most {MAX_TOPICS} topics per repo"
+        )));
+    }
+    Ok(result)
 }
 
 pub fn add_repo(repo: &Repo) -> ServiceResult<()> {
-    let conn = new_conn()?;
-    conn.execute(
-        "INSERT INTO repo (id, name, owner, description, created_at, updated_at, status) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO repo (id, name, owner, description, created_at, updated_at, status, public, version, search_tokenizer) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
         params![
             repo.id,
             repo.name,
@@ -69,30 +109,96 @@ pub fn add_repo(repo: &Repo) -> ServiceResult<()> {
             repo.created_at,
             repo.updated_at,
             repo.status.to_string(),
+            repo.public,
+            repo.version,
+            repo.search_tokenizer.map(|t| t.to_string()),
         ],
     )?;
+    set_repo_topics(&tx, &repo.id, &repo.topics)?;
+    tx.commit()?;
     Ok(())
 }
 
-pub fn update_repo(repo: &Repo) -> ServiceResult<()> {
-    let conn = new_conn()?;
+/// with `expected_version` the write only happens if the stored version still equals it.
+/// returns the new version, none if the repo is missing or the version has moved on.
+pub fn update_repo(repo: &Repo, expected_version: Option<i64>) -> ServiceResult<Option<i64>> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let version = tx
+        .query_row(
+            "UPDATE repo SET name = ?2, description = ?3, updated_at = ?4, status = ?5, public = ?6, search_tokenizer = ?8, version = version + 1
+            WHERE id = ?1 AND (?7 IS NULL OR version = ?7) RETURNING version",
+            params![
+                repo.id,
+                repo.name,
+                repo.description,
+                repo.updated_at,
+                repo.status.to_string(),
+                repo.public,
+                expected_version,
+                repo.search_tokenizer.map(|t| t.to_string()),
+            ],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if version.is_some() {
+        set_repo_topics(&tx, &repo.id, &repo.topics)?;
+        tx.commit()?;
+    }
+    Ok(version)
+}
+
+fn set_repo_topics(conn: &Connection, repo_id: &str, topics: &[String]) -> ServiceResult<()> {
     conn.execute(
-        "UPDATE repo SET name = ?2, description = ?3, updated_at = ?4, status = ?5 WHERE id = ?1",
-        params![
-            repo.id,
-            repo.name,
-            repo.description,
-            repo.updated_at,
-            repo.status.to_string(),
-        ],
+        "DELETE FROM repo_topic WHERE repo_id = ?1",
+        params![repo_id],
     )?;
+    let mut stmt = conn.prepare("INSERT INTO repo_topic (repo_id, topic) VALUES (?1, ?2)")?;
+    for topic in topics {
+        stmt.execute(params![repo_id, topic])?;
+    }
     Ok(())
 }
 
-pub fn list_repos_by_owner_id(owner_id: &str) -> ServiceResult<Vec<Repo>> {
+pub(crate) fn parse_tokenizer(tokenizer: Option<String>) -> ServiceResult<Option<Tokenizer>> {
+    tokenizer.map(|t| Tokenizer::from_str(&t)).transpose()
+}
+
+pub(crate) fn list_repo_topics(conn: &Connection, repo_id: &str) -> ServiceResult<Vec<String>> {
+    let mut stmt =
+        conn.prepare("SELECT topic FROM repo_topic WHERE repo_id = ?1 ORDER BY topic")?;
+    let mut rows = stmt.query(params![repo_id])?;
+    let mut topics = Vec::new();
+    while let Some(row) = rows.next()? {
+        topics.push(row.get(0)?);
+    }
+    Ok(topics)
+}
+
+const REPO_LIST_COLUMNS: ListColumns = ListColumns {
+    id: "id",
+    created_at: "created_at",
+    updated_at: "updated_at",
+    title: Some("name"),
+    author: Some("owner"),
+    category: None,
+};
+
+/// repos that are not deleted and match `condition`, whose parameters are `values`.
+pub(crate) fn list_repos_where(
+    condition: &str,
+    mut values: Vec<Box<dyn ToSql>>,
+    query: &ListQuery,
+) -> ServiceResult<Page<Repo>> {
     let conn = new_conn()?;
-    let mut stmt = conn.prepare("SELECT id, name, owner, description, created_at, updated_at, status FROM repo WHERE owner = ?1")?;
-    let mut rows = stmt.query(params![owner_id])?;
+    let (list_sql, list_params) = query.sql(&REPO_LIST_COLUMNS, values.len() + 1)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, name, owner, description, created_at, updated_at, status, public, version, search_tokenizer, {}
+        FROM repo WHERE status = 'normal' AND {condition}{list_sql}",
+        query.sort_column(&REPO_LIST_COLUMNS)?
+    ))?;
+    values.extend(list_params);
+    let mut rows = stmt.query(params_from_iter(values.iter()))?;
     let mut repos = Vec::new();
     while let Some(row) = rows.next()? {
         let repo = Repo {
@@ -103,17 +209,23 @@ pub fn list_repos_by_owner_id(owner_id: &str) -> ServiceResult<Vec<Repo>> {
             created_at: row.get(4)?,
             updated_at: row.get(5)?,
             status: RepoStatus::from_str(&row.get::<_, String>(6)?)?,
+            public: row.get(7)?,
+            topics: list_repo_topics(&conn, &row.get::<_, String>(0)?)?,
+            version: row.get(8)?,
+            search_tokenizer: parse_tokenizer(row.get(9)?)?,
         };
-        if repo.status.is_normal() {
-            repos.push(repo);
-        }
+        repos.push((repo, query.cursor(row, 0, 10)?));
     }
-    Ok(repos)
+    Ok(query.page(repos))
+}
+
+pub fn list_repos_by_owner_id(owner_id: &str, query: &ListQuery) -> ServiceResult<Page<Repo>> {
+    list_repos_where("owner = ?1", vec![Box::new(owner_id.to_owned())], query)
 }
 
 pub fn get_repo_by_id(repo_id: &str) -> ServiceResult<Option<Repo>> {
     let conn = new_conn()?;
-    let mut stmt = conn.prepare("SELECT id, name, owner, description, created_at, updated_at, status FROM repo WHERE id = ?1")?;
+    let mut stmt = conn.prepare("SELECT id, name, owner, description, created_at, updated_at, status, public, version, search_tokenizer FROM repo WHERE id = ?1")?;
     let mut rows = stmt.query(params![repo_id])?;
     let row = rows.next()?;
     match row {
@@ -126,6 +238,10 @@ pub fn get_repo_by_id(repo_id: &str) -> ServiceResult<Option<Repo>> {
                 created_at: row.get(4)?,
                 updated_at: row.get(5)?,
                 status: RepoStatus::from_str(&row.get::<_, String>(6)?)?,
+                public: row.get(7)?,
+                topics: list_repo_topics(&conn, repo_id)?,
+                version: row.get(8)?,
+                search_tokenizer: parse_tokenizer(row.get(9)?)?,
             };
             Ok(repo.status.is_normal().then_some(repo))
         }
@@ -142,6 +258,10 @@ pub struct OpenApiPushRepoRequest {
     pub description: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub public: Option<bool>,             // none to keep the current value
+    pub topics: Option<Vec<String>>,      // none to keep the current value
+    pub expected_version: Option<i64>,    // same as `If-Match`
+    pub search_tokenizer: Option<String>, // none to keep the current value, `default` for the server one
 }
 
 impl From<OpenApiPushRepoRequest> for Repo {
@@ -154,6 +274,10 @@ impl From<OpenApiPushRepoRequest> for Repo {
             created_at: value.created_at,
             updated_at: value.updated_at,
             status: RepoStatus::Normal,
+            public: value.public.unwrap_or(false),
+            topics: value.topics.unwrap_or_default(),
+            version: 1,
+            search_tokenizer: None,
         }
     }
 }
@@ -167,10 +291,34 @@ pub struct OpenApiGetRepoResponse {
     pub description: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub public: bool,
+    pub topics: Vec<String>,
+    pub version: i64,
+    pub search_tokenizer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unread_posts: Option<u64>, // for subscribers, posts changed since they read them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unread_comments: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct OpenApiListRepoResponse(pub Vec<OpenApiGetRepoResponse>);
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiListRepoResponse {
+    pub repos: Vec<OpenApiGetRepoResponse>,
+    pub next_cursor: Option<String>, // pass back as `cursor` for the next page, none on the last one
+    #[serde(skip)]
+    pub paged: bool, // rendered as a bare array when not, like before paging
+}
+
+impl From<Page<Repo>> for OpenApiListRepoResponse {
+    fn from(page: Page<Repo>) -> Self {
+        Self {
+            repos: page.items.into_iter().map(Into::into).collect(),
+            next_cursor: page.next_cursor,
+            paged: page.paged,
+        }
+    }
+}
 
 impl From<Repo> for OpenApiGetRepoResponse {
     fn from(repo: Repo) -> Self {
@@ -181,18 +329,42 @@ impl From<Repo> for OpenApiGetRepoResponse {
             description: repo.description,
             created_at: repo.created_at,
             updated_at: repo.updated_at,
+            public: repo.public,
+            topics: repo.topics,
+            version: repo.version,
+            search_tokenizer: repo.search_tokenizer.map(|t| t.to_string()),
+            unread_posts: None,
+            unread_comments: None,
         }
     }
 }
 
 impl Scribe for OpenApiGetRepoResponse {
     fn render(self, res: &mut salvo::Response) {
+        set_etag(res, self.version);
         res.render(Json(&self));
     }
 }
 
 impl Scribe for OpenApiListRepoResponse {
     fn render(self, res: &mut salvo::Response) {
-        res.render(Json(&self));
+        if self.paged {
+            res.render(Json(&self));
+        } else {
+            res.render(Json(&self.repos));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_topics() {
+        let topics = normalize_topics(vec![" Rust ".into(), "rust".into(), "笔记".into()]).unwrap();
+        assert_eq!(topics, vec!["rust".to_owned(), "笔记".to_owned()]);
+        assert!(normalize_topics(vec!["  ".into()]).is_err());
+        assert!(normalize_topics(vec!["x".repeat(MAX_TOPIC_LEN + 1)]).is_err());
     }
 }
@@ -0,0 +1,218 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+
+use crate::{db::new_conn, error::ServiceResult};
+
+use super::post::Post;
+
+/// a snapshot of a post as it was before an update.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PostRevision {
+    pub post_id: String,
+    pub revision: i64, // 1-based, increasing per post
+    pub title: String,
+    pub category: String,
+    pub content: String,
+    pub author: String,
+    pub updated_at: DateTime<Utc>,  // when this version was written
+    pub archived_at: DateTime<Utc>, // when it got replaced
+}
+
+/// copy the stored row of `post` into `post_revision` before it gets replaced by `post`.
+/// no-op if the post does not exist yet or nothing revisioned changes.
+pub(crate) fn archive_post(conn: &Connection, post: &Post) -> ServiceResult<()> {
+    conn.execute(
+        "INSERT INTO post_revision (post_id, revision, title, category, content, author, updated_at, archived_at)
+        SELECT id,
+            (SELECT COALESCE(MAX(revision), 0) + 1 FROM post_revision WHERE post_id = ?1),
+            title, category, content, author, updated_at, ?2
+        FROM post WHERE id = ?1 AND NOT (title = ?3 AND category = ?4 AND content = ?5)",
+        params![post.id, Utc::now(), post.title, post.category, post.content],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn erase_post_revisions(conn: &Connection, post_id: &str) -> ServiceResult<()> {
+    conn.execute(
+        "DELETE FROM post_revision WHERE post_id = ?1",
+        params![post_id],
+    )?;
+    Ok(())
+}
+
+pub fn list_revisions_by_post_id(post_id: &str) -> ServiceResult<Vec<PostRevision>> {
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare("SELECT post_id, revision, title, category, content, author, updated_at, archived_at FROM post_revision WHERE post_id = ?1 ORDER BY revision DESC")?;
+    let mut rows = stmt.query(params![post_id])?;
+    let mut revisions = Vec::new();
+    while let Some(row) = rows.next()? {
+        revisions.push(PostRevision {
+            post_id: row.get(0)?,
+            revision: row.get(1)?,
+            title: row.get(2)?,
+            category: row.get(3)?,
+            content: row.get(4)?,
+            author: row.get(5)?,
+            updated_at: row.get(6)?,
+            archived_at: row.get(7)?,
+        });
+    }
+    Ok(revisions)
+}
+
+pub fn get_revision(post_id: &str, revision: i64) -> ServiceResult<Option<PostRevision>> {
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare("SELECT post_id, revision, title, category, content, author, updated_at, archived_at FROM post_revision WHERE post_id = ?1 AND revision = ?2")?;
+    let mut rows = stmt.query(params![post_id, revision])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(PostRevision {
+            post_id: row.get(0)?,
+            revision: row.get(1)?,
+            title: row.get(2)?,
+            category: row.get(3)?,
+            content: row.get(4)?,
+            author: row.get(5)?,
+            updated_at: row.get(6)?,
+            archived_at: row.get(7)?,
+        })),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiDiffLine {
+    pub op: String, // equal | insert | delete
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+    pub text: String,
+}
+
+/// line-level diff of `old` to `new`, line numbers are 1-based.
+pub fn diff_lines(old: &str, new: &str) -> Vec<OpenApiDiffLine> {
+    TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .map(|change| OpenApiDiffLine {
+            op: match change.tag() {
+                ChangeTag::Equal => "equal",
+                ChangeTag::Insert => "insert",
+                ChangeTag::Delete => "delete",
+            }
+            .to_owned(),
+            old_line: change.old_index().map(|i| i + 1),
+            new_line: change.new_index().map(|i| i + 1),
+            text: change.value().trim_end_matches(['\r', '\n']).to_owned(),
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiRevisionSummaryResponse {
+    pub revision: i64,
+    pub title: String,
+    pub category: String,
+    pub author: String,
+    pub updated_at: DateTime<Utc>,
+    pub archived_at: DateTime<Utc>,
+}
+
+impl From<PostRevision> for OpenApiRevisionSummaryResponse {
+    fn from(value: PostRevision) -> Self {
+        Self {
+            revision: value.revision,
+            title: value.title,
+            category: value.category,
+            author: value.author,
+            updated_at: value.updated_at,
+            archived_at: value.archived_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenApiListRevisionResponse(pub Vec<OpenApiRevisionSummaryResponse>);
+
+impl Scribe for OpenApiListRevisionResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiGetRevisionResponse {
+    pub post_id: String,
+    pub revision: i64,
+    pub title: String,
+    pub category: String,
+    pub content: String,
+    pub author: String,
+    pub updated_at: DateTime<Utc>,
+    pub archived_at: DateTime<Utc>,
+}
+
+impl From<PostRevision> for OpenApiGetRevisionResponse {
+    fn from(value: PostRevision) -> Self {
+        Self {
+            post_id: value.post_id,
+            revision: value.revision,
+            title: value.title,
+            category: value.category,
+            content: value.content,
+            author: value.author,
+            updated_at: value.updated_at,
+            archived_at: value.archived_at,
+        }
+    }
+}
+
+impl Scribe for OpenApiGetRevisionResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiRevisionDiffResponse {
+    pub from: Option<i64>, // none for the current head
+    pub to: Option<i64>,   // none for the current head
+    pub old_title: String,
+    pub new_title: String,
+    pub old_category: String,
+    pub new_category: String,
+    pub lines: Vec<OpenApiDiffLine>,
+}
+
+impl Scribe for OpenApiRevisionDiffResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        let lines = diff_lines("a\nb\nc\n", "a\nc\nd\n");
+        let ops: Vec<_> = lines
+            .iter()
+            .map(|l| (l.op.as_str(), l.old_line, l.new_line, l.text.as_str()))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                ("equal", Some(1), Some(1), "a"),
+                ("delete", Some(2), None, "b"),
+                ("equal", Some(3), Some(2), "c"),
+                ("insert", None, Some(3), "d"),
+            ]
+        );
+    }
+}
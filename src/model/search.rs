@@ -0,0 +1,452 @@
+use std::{str::FromStr, sync::OnceLock};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::new_conn,
+    error::{ServiceError, ServiceResult},
+};
+
+use super::{
+    change::ChangeKind,
+    comment::Comment,
+    markdown::escape_html,
+    post::{post_from_row, Post, POST_COLUMNS},
+};
+
+/// how text is split into words before it goes into the index.
+/// the fts5 table itself always uses `unicode61`, `CjkBigram` pre-segments the text for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Tokenizer {
+    /// whitespace and punctuation, a run of CJK characters ends up as one word.
+    #[default]
+    Unicode61,
+    /// like `Unicode61`, with runs of CJK characters split into overlapping bigrams.
+    CjkBigram,
+}
+
+impl FromStr for Tokenizer {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unicode61" => Ok(Self::Unicode61),
+            "cjk_bigram" => Ok(Self::CjkBigram),
+            _ => Err(ServiceError::BadRequest(format!(
+                "invalid search tokenizer {s:?}"
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for Tokenizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tokenizer = match self {
+            Self::Unicode61 => "unicode61",
+            Self::CjkBigram => "cjk_bigram",
+        };
+        write!(f, "{}", tokenizer)
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'   // hiragana, katakana
+        | '\u{3400}'..='\u{4dbf}' // cjk extension a
+        | '\u{4e00}'..='\u{9fff}' // cjk unified ideographs
+        | '\u{ac00}'..='\u{d7af}' // hangul syllables
+        | '\u{f900}'..='\u{faff}' // cjk compatibility ideographs
+        | '\u{20000}'..='\u{2fa1f}')
+}
+
+impl Tokenizer {
+    /// the text as it is stored in the index.
+    pub fn segment(&self, text: &str) -> String {
+        match self {
+            Self::Unicode61 => text.to_owned(),
+            Self::CjkBigram => {
+                let mut result = String::with_capacity(text.len() * 2);
+                let mut run: Vec<char> = Vec::new();
+                for c in text.chars().chain(std::iter::once(' ')) {
+                    if is_cjk(c) {
+                        run.push(c);
+                        continue;
+                    }
+                    if !run.is_empty() {
+                        result.push(' ');
+                        if run.len() == 1 {
+                            result.push(run[0]);
+                            result.push(' ');
+                        }
+                        for pair in run.windows(2) {
+                            result.extend(pair);
+                            result.push(' ');
+                        }
+                        run.clear();
+                    }
+                    result.push(c);
+                }
+                result.pop();
+                result
+            }
+        }
+    }
+
+    /// turn free text into an fts5 query: every whitespace separated word becomes a quoted
+    /// phrase, so user input never hits the query syntax, and all words have to match.
+    pub fn fts_query(&self, text: &str) -> Option<String> {
+        let phrases: Vec<String> = text
+            .split_whitespace()
+            .map(|word| {
+                let phrase = format!("\"{}\"", self.segment(word).trim().replace('"', "\"\""));
+                // a lone CJK character only shows up as the start of a bigram
+                let mut chars = word.chars();
+                match (self, chars.next(), chars.next()) {
+                    (Self::CjkBigram, Some(c), None) if is_cjk(c) => format!("{phrase} *"),
+                    _ => phrase,
+                }
+            })
+            .collect();
+        (!phrases.is_empty()).then(|| phrases.join(" "))
+    }
+}
+
+static DEFAULT_TOKENIZER: OnceLock<Tokenizer> = OnceLock::new();
+
+/// set the server-wide tokenizer from the config, before the database is opened.
+pub fn set_default_tokenizer(tokenizer: Tokenizer) {
+    let _ = DEFAULT_TOKENIZER.set(tokenizer);
+}
+
+/// the server-wide tokenizer, used by repos that do not pick one.
+pub fn default_tokenizer() -> Tokenizer {
+    DEFAULT_TOKENIZER.get().copied().unwrap_or_default()
+}
+
+fn repo_tokenizer(conn: &Connection, repo_id: &str) -> ServiceResult<Tokenizer> {
+    let tokenizer: Option<String> = conn
+        .query_row(
+            "SELECT search_tokenizer FROM repo WHERE id = ?1",
+            params![repo_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    match tokenizer {
+        Some(tokenizer) => tokenizer.parse(),
+        None => Ok(default_tokenizer()),
+    }
+}
+
+pub(crate) fn index_post(conn: &Connection, post: &Post) -> ServiceResult<()> {
+    let tokenizer = repo_tokenizer(conn, &post.repo_id)?;
+    unindex(conn, ChangeKind::Post, &post.id)?;
+    conn.execute(
+        "INSERT INTO search_index (kind, entity_id, repo_id, post_id, title, category, content) VALUES (?1, ?2, ?3, ?2, ?4, ?5, ?6)",
+        params![
+            ChangeKind::Post.to_string(),
+            post.id,
+            post.repo_id,
+            tokenizer.segment(&post.title),
+            tokenizer.segment(&post.category),
+            tokenizer.segment(&post.content)
+        ],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn index_comment(conn: &Connection, comment: &Comment) -> ServiceResult<()> {
+    let tokenizer = repo_tokenizer(conn, &comment.repo_id)?;
+    unindex(conn, ChangeKind::Comment, &comment.id)?;
+    conn.execute(
+        "INSERT INTO search_index (kind, entity_id, repo_id, post_id, title, category, content) VALUES (?1, ?2, ?3, ?4, '', '', ?5)",
+        params![
+            ChangeKind::Comment.to_string(),
+            comment.id,
+            comment.repo_id,
+            comment.post_id,
+            tokenizer.segment(&comment.content)
+        ],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn unindex(conn: &Connection, kind: ChangeKind, entity_id: &str) -> ServiceResult<()> {
+    conn.execute(
+        "DELETE FROM search_index WHERE kind = ?1 AND entity_id = ?2",
+        params![kind.to_string(), entity_id],
+    )?;
+    Ok(())
+}
+
+/// rebuild the index of one repo, or of everything, with the current tokenizer settings.
+/// returns the number of indexed posts and comments.
+pub fn reindex(repo_id: Option<&str>) -> ServiceResult<usize> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "DELETE FROM search_index WHERE ?1 IS NULL OR repo_id = ?1",
+        params![repo_id],
+    )?;
+    let mut count = 0;
+    {
+        let mut stmt = tx.prepare(&format!(
+            "SELECT {POST_COLUMNS} FROM post WHERE ?1 IS NULL OR repo_id = ?1"
+        ))?;
+        let mut rows = stmt.query(params![repo_id])?;
+        while let Some(row) = rows.next()? {
+            let post = post_from_row(&tx, row)?;
+            index_post(&tx, &post)?;
+            count += 1;
+        }
+        let mut stmt = tx.prepare("SELECT id, post_id, repo_id, content, created_at, updated_at, author, parent_id FROM comment WHERE ?1 IS NULL OR repo_id = ?1")?;
+        let mut rows = stmt.query(params![repo_id])?;
+        while let Some(row) = rows.next()? {
+            let comment = Comment {
+                id: row.get(0)?,
+                post_id: row.get(1)?,
+                repo_id: row.get(2)?,
+                content: row.get(3)?,
+                created_at: row.get(4)?,
+                updated_at: row.get(5)?,
+                author: row.get(6)?,
+                parent_id: row.get(7)?,
+            };
+            index_comment(&tx, &comment)?;
+            count += 1;
+        }
+    }
+    tx.commit()?;
+    Ok(count)
+}
+
+/// byte ranges of every occurrence of any of `words` in `text`, ascii case-insensitive.
+fn find_matches(text: &str, words: &[&str]) -> Vec<(usize, usize)> {
+    let mut matches = Vec::new();
+    let mut skip_to = 0;
+    for (i, _) in text.char_indices() {
+        if i < skip_to {
+            continue;
+        }
+        let longest = words
+            .iter()
+            .filter(|word| {
+                text.get(i..i + word.len())
+                    .is_some_and(|s| s.eq_ignore_ascii_case(word))
+            })
+            .map(|word| word.len())
+            .max();
+        if let Some(len) = longest {
+            matches.push((i, i + len));
+            skip_to = i + len;
+        }
+    }
+    matches
+}
+
+/// html escaped `text` with `words` wrapped in `<mark>`.
+pub fn highlight(text: &str, words: &[&str]) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, end) in find_matches(text, words) {
+        result.push_str(&escape_html(&text[last..start]));
+        result.push_str("<mark>");
+        result.push_str(&escape_html(&text[start..end]));
+        result.push_str("</mark>");
+        last = end;
+    }
+    result.push_str(&escape_html(&text[last..]));
+    result
+}
+
+const SNIPPET_CHARS: usize = 64;
+const SNIPPET_LEADING_CHARS: usize = 16;
+
+/// a highlighted window of `text` around the first match.
+pub fn snippet(text: &str, words: &[&str]) -> String {
+    let first = find_matches(text, words).first().map_or(0, |m| m.0);
+    let start_char = text[..first]
+        .chars()
+        .count()
+        .saturating_sub(SNIPPET_LEADING_CHARS);
+    let window: String = text.chars().skip(start_char).take(SNIPPET_CHARS).collect();
+    let mut result = String::new();
+    if start_char > 0 {
+        result.push('…');
+    }
+    result.push_str(&highlight(window.trim(), words));
+    if start_char + SNIPPET_CHARS < text.chars().count() {
+        result.push('…');
+    }
+    result
+}
+
+#[derive(Debug)]
+pub struct SearchHit {
+    pub kind: ChangeKind,
+    pub entity_id: String,
+    pub repo_id: String,
+    pub post_id: String,
+    pub title: String,   // highlighted, of the post for comments
+    pub snippet: String, // highlighted, around the first match in the content
+    pub rank: f64,       // bm25, lower is better
+}
+
+/// title weighs more than category, which weighs more than content.
+/// only repos readable by `?2` whose tokenizer is `?4` are searched, as the query depends on it.
+const SEARCH_SQL: &str = "
+    SELECT search_index.kind, search_index.entity_id, search_index.repo_id, search_index.post_id,
+        bm25(search_index, 0, 0, 0, 0, 10.0, 5.0, 1.0) AS rank,
+        p.title, COALESCE(m.content, p.content)
+    FROM search_index
+    LEFT JOIN post p ON p.id = search_index.post_id
+    LEFT JOIN comment m ON search_index.kind = 'comment' AND m.id = search_index.entity_id
+    WHERE search_index MATCH ?1
+        AND search_index.repo_id IN (
+            SELECT r.id FROM repo r
+            WHERE r.status = 'normal' AND COALESCE(r.search_tokenizer, ?5) = ?4
+                AND (r.owner = ?2 OR r.id IN (SELECT repo_id FROM subscribe WHERE user_id = ?2))
+        )
+        AND (?3 IS NULL OR search_index.repo_id = ?3)
+        AND (p.state = 'published' OR p.author = ?2)";
+
+/// search the repos `user_id` can read, or only `repo_id` of them, returns the page and the total count.
+/// repos may use different tokenizers, so each one is queried on its own and the hits are merged.
+pub fn search(
+    user_id: &str,
+    text: &str,
+    repo_id: Option<&str>,
+    limit: u32,
+    offset: u32,
+) -> ServiceResult<(Vec<SearchHit>, i64)> {
+    let conn = new_conn()?;
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let default = default_tokenizer().to_string();
+    let mut total = 0;
+    let mut hits = Vec::new();
+    for tokenizer in [Tokenizer::Unicode61, Tokenizer::CjkBigram] {
+        let Some(query) = tokenizer.fts_query(text) else {
+            continue;
+        };
+        let tokenizer = tokenizer.to_string();
+        total += conn.query_row(
+            &format!("SELECT COUNT(*) FROM ({SEARCH_SQL})"),
+            params![query, user_id, repo_id, tokenizer, default],
+            |row| row.get::<_, i64>(0),
+        )?;
+        let mut stmt = conn.prepare(&format!("{SEARCH_SQL} ORDER BY rank LIMIT ?6"))?;
+        let mut rows = stmt.query(params![
+            query,
+            user_id,
+            repo_id,
+            tokenizer,
+            default,
+            offset as i64 + limit as i64
+        ])?;
+        while let Some(row) = rows.next()? {
+            let title: Option<String> = row.get(5)?;
+            let content: Option<String> = row.get(6)?;
+            hits.push(SearchHit {
+                kind: ChangeKind::from_str(&row.get::<_, String>(0)?)?,
+                entity_id: row.get(1)?,
+                repo_id: row.get(2)?,
+                post_id: row.get(3)?,
+                rank: row.get(4)?,
+                title: highlight(title.as_deref().unwrap_or_default(), &words),
+                snippet: snippet(content.as_deref().unwrap_or_default(), &words),
+            });
+        }
+    }
+    hits.sort_by(|a, b| a.rank.total_cmp(&b.rank));
+    let hits = hits
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect();
+    Ok((hits, total))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiSearchHitResponse {
+    pub kind: String, // post | comment
+    pub id: String,
+    pub repo_id: String,
+    pub post_id: String,
+    pub title: String,
+    pub snippet: String,
+    pub rank: f64,
+}
+
+impl From<SearchHit> for OpenApiSearchHitResponse {
+    fn from(value: SearchHit) -> Self {
+        Self {
+            kind: value.kind.to_string(),
+            id: value.entity_id,
+            repo_id: value.repo_id,
+            post_id: value.post_id,
+            title: value.title,
+            snippet: value.snippet,
+            rank: value.rank,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiSearchResponse {
+    pub hits: Vec<OpenApiSearchHitResponse>,
+    pub page: u32,
+    pub limit: u32,
+    pub total: i64,
+}
+
+impl Scribe for OpenApiSearchResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!(Tokenizer::Unicode61.fts_query("  "), None);
+        assert_eq!(
+            Tokenizer::Unicode61.fts_query("rust AND \"sqlite"),
+            Some("\"rust\" \"AND\" \"\"\"sqlite\"".to_owned())
+        );
+        assert_eq!(
+            Tokenizer::CjkBigram.fts_query("中文搜索 中"),
+            Some("\"中文 文搜 搜索\" \"中\" *".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_cjk_bigram_segment() {
+        let tokenizer = Tokenizer::CjkBigram;
+        assert_eq!(tokenizer.segment("hello world"), "hello world");
+        assert_eq!(tokenizer.segment("用Rust写服务"), " 用 Rust 写服 服务 ");
+        assert_eq!(tokenizer.segment("中"), " 中 ");
+    }
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!(
+            snippet("learn <Rust> with rust", &["rust"]),
+            "learn &lt;<mark>Rust</mark>&gt; with <mark>rust</mark>"
+        );
+        assert_eq!(
+            snippet("a=\"rust\" b='x'", &["rust"]),
+            "a=&quot;<mark>rust</mark>&quot; b=&#39;x&#39;"
+        );
+        let long = format!("{}中文{}", "字".repeat(80), "字".repeat(80));
+        let result = snippet(&long, &["中文"]);
+        assert!(result.starts_with('…') && result.ends_with('…'));
+        assert!(result.contains("<mark>中文</mark>"));
+    }
+}
@@ -0,0 +1,678 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    io::{self, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::{
+    db::new_conn,
+    error::{ServiceError, ServiceResult},
+};
+
+use super::{
+    attachment::{attachment_refs, blob_path, list_attachments_by_repo_id},
+    category::list_categories_by_repo_id,
+    export::{
+        attachment_entry, entry_options, path_segment, relink_attachments, ATTACHMENT_FOLDER,
+    },
+    markdown::{escape_html, get_post_html},
+    post::{post_from_row, POST_COLUMNS},
+    repo::get_repo_by_id,
+};
+
+/// templates the owner can override, each page is rendered into `layout.html`.
+pub const TEMPLATE_NAMES: [&str; 5] = [
+    "layout.html",
+    "index.html",
+    "post.html",
+    "tag.html",
+    "style.css",
+];
+
+pub const MAX_TEMPLATE_BYTES: usize = 256 * 1024;
+
+/// posts in the rss feed, newest first.
+const FEED_ITEMS: usize = 20;
+
+const DEFAULT_LAYOUT: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{title}}</title>
+<link rel="stylesheet" href="{{root}}style.css">
+<link rel="alternate" type="application/rss+xml" title="{{site_title}}" href="{{root}}feed.xml">
+</head>
+<body>
+<header><a href="{{root}}index.html">{{site_title}}</a></header>
+<main>
+{{body}}
+</main>
+</body>
+</html>
+"#;
+
+const DEFAULT_INDEX: &str = r#"<h1>{{site_title}}</h1>
+<p class="description">{{description}}</p>
+{{categories}}
+<nav class="tags">{{tags}}</nav>
+"#;
+
+const DEFAULT_POST: &str = r#"<article>
+<h1>{{title}}</h1>
+<p class="meta"><time datetime="{{created_at}}">{{date}}</time> · {{category}}</p>
+{{content}}
+<nav class="tags">{{tags}}</nav>
+</article>
+"#;
+
+const DEFAULT_TAG: &str = r#"<h1>#{{tag}}</h1>
+{{posts}}
+"#;
+
+const DEFAULT_STYLE: &str = r#"body { max-width: 46rem; margin: 0 auto; padding: 1rem; font-family: sans-serif; line-height: 1.6; }
+header { margin-bottom: 2rem; font-weight: bold; }
+a { color: #2a5db0; }
+time, .meta, .description { color: #666; }
+.tags a { margin-right: .5rem; }
+img { max-width: 100%; }
+pre { overflow-x: auto; padding: .75rem; background: #f5f5f5; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ddd; padding: .25rem .5rem; }
+"#;
+
+pub fn default_template(name: &str) -> Option<&'static str> {
+    match name {
+        "layout.html" => Some(DEFAULT_LAYOUT),
+        "index.html" => Some(DEFAULT_INDEX),
+        "post.html" => Some(DEFAULT_POST),
+        "tag.html" => Some(DEFAULT_TAG),
+        "style.css" => Some(DEFAULT_STYLE),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct SiteTemplate {
+    pub name: String,
+    pub body: String,
+    pub custom: bool,                      // false for the built-in default
+    pub updated_at: Option<DateTime<Utc>>, // of the override
+}
+
+fn query_site_template(
+    conn: &Connection,
+    repo_id: &str,
+    name: &str,
+) -> ServiceResult<Option<SiteTemplate>> {
+    let Some(default) = default_template(name) else {
+        return Ok(None);
+    };
+    let custom: Option<(String, DateTime<Utc>)> = conn
+        .query_row(
+            "SELECT body, updated_at FROM site_template WHERE repo_id = ?1 AND name = ?2",
+            params![repo_id, name],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    Ok(Some(match custom {
+        Some((body, updated_at)) => SiteTemplate {
+            name: name.to_owned(),
+            body,
+            custom: true,
+            updated_at: Some(updated_at),
+        },
+        None => SiteTemplate {
+            name: name.to_owned(),
+            body: default.to_owned(),
+            custom: false,
+            updated_at: None,
+        },
+    }))
+}
+
+/// the template of the repo, none for an unknown name.
+pub fn get_site_template(repo_id: &str, name: &str) -> ServiceResult<Option<SiteTemplate>> {
+    let conn = new_conn()?;
+    query_site_template(&conn, repo_id, name)
+}
+
+pub fn list_site_templates(repo_id: &str) -> ServiceResult<Vec<SiteTemplate>> {
+    let conn = new_conn()?;
+    let mut templates = Vec::new();
+    for name in TEMPLATE_NAMES {
+        templates.extend(query_site_template(&conn, repo_id, name)?);
+    }
+    Ok(templates)
+}
+
+/// override a template of the repo.
+pub fn set_site_template(repo_id: &str, name: &str, body: &str) -> ServiceResult<SiteTemplate> {
+    if default_template(name).is_none() {
+        return Err(ServiceError::NotFound(format!("no template {name:?}")));
+    }
+    let conn = new_conn()?;
+    let now = Utc::now();
+    conn.execute(
+        "INSERT OR REPLACE INTO site_template (repo_id, name, body, updated_at) VALUES (?1, ?2, ?3, ?4)",
+        params![repo_id, name, body, now],
+    )?;
+    Ok(SiteTemplate {
+        name: name.to_owned(),
+        body: body.to_owned(),
+        custom: true,
+        updated_at: Some(now),
+    })
+}
+
+/// go back to the default template, false when it was not overridden.
+pub fn reset_site_template(repo_id: &str, name: &str) -> ServiceResult<bool> {
+    let conn = new_conn()?;
+    let removed = conn.execute(
+        "DELETE FROM site_template WHERE repo_id = ?1 AND name = ?2",
+        params![repo_id, name],
+    )?;
+    Ok(removed > 0)
+}
+
+/// replace the `{{name}}` placeholders with their values, which are inserted as they
+/// are. unknown placeholders are kept.
+pub fn fill(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after.find("}}").and_then(|end| {
+            let key = after[..end].trim();
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| (end, *value))
+        });
+        match value {
+            Some((end, value)) => {
+                filled.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                filled.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// where the files of a site go.
+pub trait SiteOutput {
+    fn write(&mut self, path: &str, data: &[u8]) -> ServiceResult<()>;
+    fn copy(&mut self, path: &str, from: &Path) -> ServiceResult<()>;
+}
+
+/// files written under a directory.
+pub struct DirOutput(pub PathBuf);
+
+impl DirOutput {
+    fn target(&self, path: &str) -> ServiceResult<PathBuf> {
+        let target = self.0.join(path);
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(target)
+    }
+}
+
+impl SiteOutput for DirOutput {
+    fn write(&mut self, path: &str, data: &[u8]) -> ServiceResult<()> {
+        fs::write(self.target(path)?, data)?;
+        Ok(())
+    }
+
+    fn copy(&mut self, path: &str, from: &Path) -> ServiceResult<()> {
+        fs::copy(from, self.target(path)?)?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> SiteOutput for ZipWriter<W> {
+    fn write(&mut self, path: &str, data: &[u8]) -> ServiceResult<()> {
+        self.start_file(path, entry_options(Utc::now(), CompressionMethod::Deflated))?;
+        self.write_all(data)?;
+        Ok(())
+    }
+
+    fn copy(&mut self, path: &str, from: &Path) -> ServiceResult<()> {
+        let mut file = fs::File::open(from)?;
+        let large = file.metadata()?.len() >= u32::MAX as u64;
+        self.start_file(
+            path,
+            entry_options(Utc::now(), CompressionMethod::Stored).large_file(large),
+        )?;
+        io::copy(&mut file, self)?;
+        Ok(())
+    }
+}
+
+struct SitePost {
+    id: String,
+    title: String,
+    category: String,
+    created_at: DateTime<Utc>,
+    tags: Vec<String>,
+    html: Option<String>, // kept for the posts of the feed
+}
+
+/// the pages of posts and tags, by post id and tag.
+struct SiteFiles {
+    posts: HashMap<String, String>,
+    tags: HashMap<String, String>,
+}
+
+impl SiteFiles {
+    fn post(&self, id: &str) -> &str {
+        self.posts.get(id).map_or("", String::as_str)
+    }
+
+    fn tag(&self, tag: &str) -> &str {
+        self.tags.get(tag).map_or("", String::as_str)
+    }
+}
+
+/// a page under `folder` for each of `keys`. keys whose segments are equal ignoring case
+/// get a `-2`, `-3` suffix in order, so no page overwrites another.
+fn unique_files<'a>(
+    folder: &str,
+    keys: impl IntoIterator<Item = &'a String>,
+    fallback: &str,
+) -> HashMap<String, String> {
+    let mut used: HashSet<String> = HashSet::new();
+    let mut files = HashMap::new();
+    for key in keys {
+        if files.contains_key(key) {
+            continue;
+        }
+        let segment = path_segment(key, fallback);
+        let mut name = segment.clone();
+        let mut n = 1;
+        while !used.insert(name.to_lowercase()) {
+            n += 1;
+            name = format!("{segment}-{n}");
+        }
+        files.insert(key.clone(), format!("{folder}/{name}.html"));
+    }
+    files
+}
+
+fn post_list(posts: &[&SitePost], files: &SiteFiles, root: &str) -> String {
+    let mut html = String::from("<ul class=\"posts\">\n");
+    for post in posts {
+        html.push_str(&format!(
+            "<li><a href=\"{root}{}\">{}</a> <time datetime=\"{}\">{}</time></li>\n",
+            files.post(&post.id),
+            escape_html(&post.title),
+            post.created_at.to_rfc3339(),
+            post.created_at.format("%Y-%m-%d"),
+        ));
+    }
+    html.push_str("</ul>");
+    html
+}
+
+fn tag_links<'a>(
+    tags: impl IntoIterator<Item = &'a String>,
+    files: &SiteFiles,
+    root: &str,
+) -> String {
+    tags.into_iter()
+        .map(|tag| {
+            format!(
+                "<a href=\"{root}{}\">#{}</a>",
+                files.tag(tag),
+                escape_html(tag)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// rss 2.0 of the newest posts. links are absolute with `base_url`, relative otherwise.
+fn rss_feed(
+    title: &str,
+    description: &str,
+    base_url: &str,
+    posts: &[SitePost],
+    files: &SiteFiles,
+    repo_id: &str,
+    names: &HashMap<String, String>,
+) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n<channel>\n",
+    );
+    xml.push_str(&format!("<title>{}</title>\n", escape_html(title)));
+    xml.push_str(&format!(
+        "<link>{}index.html</link>\n",
+        escape_html(base_url)
+    ));
+    xml.push_str(&format!(
+        "<description>{}</description>\n",
+        escape_html(description)
+    ));
+    if let Some(post) = posts.first() {
+        xml.push_str(&format!(
+            "<lastBuildDate>{}</lastBuildDate>\n",
+            post.created_at.to_rfc2822()
+        ));
+    }
+    for post in posts.iter().take(FEED_ITEMS) {
+        let link = format!("{base_url}{}", files.post(&post.id));
+        let html = relink_attachments(
+            post.html.as_deref().unwrap_or_default(),
+            repo_id,
+            names,
+            base_url,
+        );
+        xml.push_str("<item>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape_html(&post.title)));
+        xml.push_str(&format!("<link>{}</link>\n", escape_html(&link)));
+        xml.push_str(&format!(
+            "<guid isPermaLink=\"false\">{}</guid>\n",
+            escape_html(&post.id)
+        ));
+        xml.push_str(&format!(
+            "<pubDate>{}</pubDate>\n",
+            post.created_at.to_rfc2822()
+        ));
+        if !post.category.is_empty() {
+            xml.push_str(&format!(
+                "<category>{}</category>\n",
+                escape_html(&post.category)
+            ));
+        }
+        xml.push_str(&format!(
+            "<description>{}</description>\n",
+            escape_html(&html)
+        ));
+        xml.push_str("</item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+/// render the published posts of the repo as a static site: `index.html` by category,
+/// a page per post under `posts/`, per tag under `tags/`, `feed.xml` and the attachments
+/// the posts link to. drafts and scheduled posts are left out. `base_url` is where the
+/// site will be served from, for the absolute links of the feed. returns the number of
+/// posts rendered.
+pub fn render_site(
+    repo_id: &str,
+    base_url: Option<&str>,
+    output: &mut impl SiteOutput,
+) -> ServiceResult<usize> {
+    let Some(repo) = get_repo_by_id(repo_id)? else {
+        return Err(ServiceError::NotFound("repo not found".to_owned()));
+    };
+    let templates: HashMap<String, String> = list_site_templates(repo_id)?
+        .into_iter()
+        .map(|template| (template.name, template.body))
+        .collect();
+    let template = |name: &str| templates.get(name).map_or("", String::as_str);
+    let site_title = escape_html(&repo.name);
+    let description = escape_html(&repo.description);
+    let page = |title: &str, root: &str, body: &str| {
+        fill(
+            template("layout.html"),
+            &[
+                ("title", title),
+                ("site_title", &site_title),
+                ("description", &description),
+                ("root", root),
+                ("body", body),
+            ],
+        )
+    };
+    let base_url = match base_url.map(|url| url.trim_end_matches('/')) {
+        Some(url) if !url.is_empty() => format!("{url}/"),
+        _ => String::new(),
+    };
+    let names: HashMap<String, String> = list_attachments_by_repo_id(repo_id)?
+        .into_iter()
+        .map(|attachment| (attachment.sha256, attachment.name))
+        .collect();
+
+    // read before rendering, which writes the html cache
+    let published = {
+        let conn = new_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {POST_COLUMNS} FROM post WHERE repo_id = ?1 AND state = 'published' ORDER BY created_at DESC, id"
+        ))?;
+        let mut rows = stmt.query(params![repo_id])?;
+        let mut published = Vec::new();
+        while let Some(row) = rows.next()? {
+            published.push(post_from_row(&conn, row)?);
+        }
+        published
+    };
+    // oldest first, so a new post does not rename the page of an older one
+    let files = SiteFiles {
+        posts: unique_files("posts", published.iter().rev().map(|post| &post.id), "post"),
+        tags: unique_files(
+            "tags",
+            published
+                .iter()
+                .flat_map(|post| &post.tags)
+                .collect::<BTreeSet<_>>(),
+            "tag",
+        ),
+    };
+    let mut posts: Vec<SitePost> = Vec::new();
+    let mut linked: HashSet<String> = HashSet::new();
+    for post in published {
+        let html = get_post_html(&post)?;
+        linked.extend(attachment_refs(&post.content));
+        let title = escape_html(&post.title);
+        let body = fill(
+            template("post.html"),
+            &[
+                ("title", &title),
+                ("category", &escape_html(&post.category)),
+                ("created_at", &post.created_at.to_rfc3339()),
+                ("updated_at", &post.updated_at.to_rfc3339()),
+                ("date", &post.created_at.format("%Y-%m-%d").to_string()),
+                ("tags", &tag_links(&post.tags, &files, "../")),
+                (
+                    "content",
+                    &relink_attachments(&html, repo_id, &names, "../"),
+                ),
+                ("root", "../"),
+            ],
+        );
+        output.write(files.post(&post.id), page(&title, "../", &body).as_bytes())?;
+        posts.push(SitePost {
+            html: (posts.len() < FEED_ITEMS).then_some(html),
+            id: post.id,
+            title: post.title,
+            category: post.category,
+            created_at: post.created_at,
+            tags: post.tags,
+        });
+    }
+
+    // categories in the order of the repo, those without one last by name
+    let order: HashMap<String, i64> = list_categories_by_repo_id(repo_id)?
+        .into_iter()
+        .map(|category| (category.name.to_lowercase(), category.sort_order))
+        .collect();
+    let mut categories: BTreeMap<(i64, String), Vec<&SitePost>> = BTreeMap::new();
+    let mut tags: BTreeMap<&String, Vec<&SitePost>> = BTreeMap::new();
+    for post in &posts {
+        let key = post.category.to_lowercase();
+        let rank = order.get(&key).copied().unwrap_or(i64::MAX);
+        categories.entry((rank, key)).or_default().push(post);
+        for tag in &post.tags {
+            tags.entry(tag).or_default().push(post);
+        }
+    }
+    let mut sections = String::new();
+    for posts in categories.values() {
+        let name = match posts[0].category.as_str() {
+            "" => "uncategorized",
+            name => name,
+        };
+        sections.push_str(&format!(
+            "<section class=\"category\">\n<h2>{}</h2>\n{}\n</section>\n",
+            escape_html(name),
+            post_list(posts, &files, "")
+        ));
+    }
+    let index = fill(
+        template("index.html"),
+        &[
+            ("site_title", &site_title),
+            ("description", &description),
+            ("categories", &sections),
+            ("tags", &tag_links(tags.keys().copied(), &files, "")),
+            ("root", ""),
+        ],
+    );
+    output.write("index.html", page(&site_title, "", &index).as_bytes())?;
+    for (tag, posts) in &tags {
+        let body = fill(
+            template("tag.html"),
+            &[
+                ("tag", &escape_html(tag)),
+                ("posts", &post_list(posts, &files, "../")),
+                ("root", "../"),
+            ],
+        );
+        let title = format!("#{} - {site_title}", escape_html(tag));
+        output.write(files.tag(tag), page(&title, "../", &body).as_bytes())?;
+    }
+    output.write("style.css", template("style.css").as_bytes())?;
+    output.write(
+        "feed.xml",
+        rss_feed(
+            &repo.name,
+            &repo.description,
+            &base_url,
+            &posts,
+            &files,
+            repo_id,
+            &names,
+        )
+        .as_bytes(),
+    )?;
+
+    for sha256 in linked {
+        let Some(name) = names.get(&sha256) else {
+            continue;
+        };
+        let blob = blob_path(&sha256);
+        if blob.exists() {
+            output.copy(&attachment_entry(&sha256, name), &blob)?;
+        }
+    }
+    Ok(posts.len())
+}
+
+/// render the site into a directory. the pages and attachments of an earlier run are
+/// removed first, so posts since deleted or unpublished do not stay online.
+pub fn write_site_dir(repo_id: &str, base_url: Option<&str>, dir: &Path) -> ServiceResult<usize> {
+    for folder in ["posts", "tags", ATTACHMENT_FOLDER] {
+        match fs::remove_dir_all(dir.join(folder)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    render_site(repo_id, base_url, &mut DirOutput(dir.to_owned()))
+}
+
+/// render the site as a zip into `path`, removing what was written if it fails.
+pub fn write_site_zip(repo_id: &str, base_url: Option<&str>, path: &Path) -> ServiceResult<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut zip = ZipWriter::new(io::BufWriter::new(fs::File::create(path)?));
+    let result = render_site(repo_id, base_url, &mut zip)
+        .and_then(|_| Ok(zip.finish()?))
+        .and_then(|mut writer| Ok(writer.flush()?));
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiPutSiteTemplateRequest {
+    pub body: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiSiteTemplateResponse {
+    pub name: String,
+    pub body: String,
+    pub custom: bool,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl From<SiteTemplate> for OpenApiSiteTemplateResponse {
+    fn from(value: SiteTemplate) -> Self {
+        Self {
+            name: value.name,
+            body: value.body,
+            custom: value.custom,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+impl Scribe for OpenApiSiteTemplateResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenApiListSiteTemplateResponse(pub Vec<OpenApiSiteTemplateResponse>);
+
+impl Scribe for OpenApiListSiteTemplateResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill() {
+        let vars = [("title", "a {{body}}"), ("body", "<p>b</p>")];
+        assert_eq!(
+            fill("<h1>{{ title }}</h1>{{body}}{{missing}}{{", &vars),
+            "<h1>a {{body}}</h1><p>b</p>{{missing}}{{"
+        );
+        for name in TEMPLATE_NAMES {
+            assert!(default_template(name).is_some());
+        }
+    }
+
+    #[test]
+    fn test_unique_files() {
+        let keys = ["a/b", "A-b", "a-b-2", "a/b"].map(String::from);
+        let files = unique_files("tags", &keys, "tag");
+        assert_eq!(files.len(), 3);
+        assert_eq!(files["a/b"], "tags/a-b.html");
+        assert_eq!(files["A-b"], "tags/A-b-2.html");
+        assert_eq!(files["a-b-2"], "tags/a-b-2-2.html");
+    }
+}
@@ -2,6 +2,11 @@ use rusqlite::params;
 
 use crate::{db::new_conn, error::ServiceResult};
 
+use super::{
+    listing::{ListQuery, Page},
+    repo::{list_repos_where, Repo},
+};
+
 pub fn add_subscribe(user_id: &str, repo_id: &str) -> ServiceResult<()> {
     let id = uuid::Uuid::new_v4().to_string();
     let conn = new_conn()?;
@@ -20,15 +25,13 @@ pub fn check_subscribe(user_id: &str, repo_id: &str) -> ServiceResult<bool> {
     Ok(count > 0)
 }
 
-pub fn fetch_subscribe(user_id: &str) -> ServiceResult<Vec<String>> {
-    let conn = new_conn()?;
-    let mut stmt = conn.prepare("SELECT repo_id FROM subscribe WHERE user_id = ?1")?;
-    let mut rows = stmt.query(params![user_id])?;
-    let mut repos = Vec::new();
-    while let Some(row) = rows.next()? {
-        repos.push(row.get(0)?);
-    }
-    Ok(repos)
+/// the repos the user subscribes, deleted ones left out.
+pub fn fetch_subscribe(user_id: &str, query: &ListQuery) -> ServiceResult<Page<Repo>> {
+    list_repos_where(
+        "id IN (SELECT repo_id FROM subscribe WHERE user_id = ?1)",
+        vec![Box::new(user_id.to_owned())],
+        query,
+    )
 }
 
 pub fn delete_subscribe(user_id: &str, repo_id: &str) -> ServiceResult<()> {
@@ -51,7 +54,7 @@ pub fn delete_subscribe(user_id: &str, repo_id: &str) -> ServiceResult<()> {
 //         let repo_id = "cae646fe-12c4-42a3-bd92-19f49a22a8b4";
 //         add_subscribe(user_id, repo_id).unwrap();
 //         assert!(check_subscribe(user_id, repo_id).unwrap());
-//         dbg!(&fetch_subscribe(user_id).unwrap());
+//         dbg!(&fetch_subscribe(user_id, &Default::default()).unwrap());
 //         delete_subscribe(user_id, repo_id).unwrap();
 //         assert!(!check_subscribe(user_id, repo_id).unwrap());
 //     }
@@ -0,0 +1,182 @@
+use rusqlite::{params, Connection};
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::new_conn,
+    error::{ServiceError, ServiceResult},
+};
+
+const MAX_TAGS: usize = 32;
+const MAX_TAG_LEN: usize = 64;
+
+/// trim, lowercase and dedup tags, rejecting empty or overlong ones.
+pub fn normalize_tags(tags: Vec<String>) -> ServiceResult<Vec<String>> {
+    let mut result: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || tag.chars().count() > MAX_TAG_LEN {
+            return Err(ServiceError::BadRequest(format!("invalid tag {tag:?}")));
+        }
+        if !result.contains(&tag) {
+            result.push(tag);
+        }
+    }
+    if result.len() > MAX_TAGS {
+        return Err(ServiceError::BadRequest(format!(
+            "at most {MAX_TAGS} tags per post"
+        )));
+    }
+    Ok(result)
+}
+
+pub(crate) fn set_post_tags(
+    conn: &Connection,
+    post_id: &str,
+    repo_id: &str,
+    tags: &[String],
+) -> ServiceResult<()> {
+    conn.execute("DELETE FROM post_tag WHERE post_id = ?1", params![post_id])?;
+    let mut stmt =
+        conn.prepare("INSERT INTO post_tag (post_id, repo_id, tag) VALUES (?1, ?2, ?3)")?;
+    for tag in tags {
+        stmt.execute(params![post_id, repo_id, tag])?;
+    }
+    Ok(())
+}
+
+pub(crate) fn list_post_tags(conn: &Connection, post_id: &str) -> ServiceResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT tag FROM post_tag WHERE post_id = ?1 ORDER BY tag")?;
+    let mut rows = stmt.query(params![post_id])?;
+    let mut tags = Vec::new();
+    while let Some(row) = rows.next()? {
+        tags.push(row.get(0)?);
+    }
+    Ok(tags)
+}
+
+#[derive(Debug)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: i64, // posts carrying the tag
+}
+
+/// tags of a repo starting with `prefix`, most used first. only posts `viewer` can see
+/// count, drafts of others do not.
+pub fn list_tags_by_repo_id(
+    repo_id: &str,
+    viewer: &str,
+    prefix: Option<&str>,
+    limit: u32,
+) -> ServiceResult<Vec<TagCount>> {
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare(
+        "SELECT t.tag, COUNT(*) AS count FROM post_tag t JOIN post p ON p.id = t.post_id
+        WHERE t.repo_id = ?1 AND (p.state = 'published' OR p.author = ?4)
+            AND (?2 IS NULL OR substr(t.tag, 1, length(?2)) = ?2)
+        GROUP BY t.tag ORDER BY count DESC, t.tag LIMIT ?3",
+    )?;
+    let prefix = prefix.map(|prefix| prefix.trim().to_lowercase());
+    let mut rows = stmt.query(params![repo_id, prefix, limit, viewer])?;
+    let mut tags = Vec::new();
+    while let Some(row) = rows.next()? {
+        tags.push(TagCount {
+            tag: row.get(0)?,
+            count: row.get(1)?,
+        });
+    }
+    Ok(tags)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiTagResponse {
+    pub tag: String,
+    pub count: i64,
+}
+
+impl From<TagCount> for OpenApiTagResponse {
+    fn from(value: TagCount) -> Self {
+        Self {
+            tag: value.tag,
+            count: value.count,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenApiListTagResponse(pub Vec<OpenApiTagResponse>);
+
+impl Scribe for OpenApiListTagResponse {
+    fn render(self, res: &mut salvo::Response) {
+        res.render(Json(&self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::model::{
+        post::{push_post, OpenApiPushPostRequest, PostState},
+        repo::{add_repo, Repo, RepoStatus},
+        user::{add_user, User},
+    };
+
+    #[test]
+    fn test_normalize_tags() {
+        let tags = normalize_tags(vec![" Rust ".into(), "rust".into(), "web".into()]).unwrap();
+        assert_eq!(tags, ["rust", "web"]);
+        assert!(normalize_tags(vec!["  ".into()]).is_err());
+    }
+
+    #[test]
+    fn test_draft_tags_hidden() -> anyhow::Result<()> {
+        crate::db::init_db()?;
+        let user = User::new(uuid::Uuid::new_v4().to_string(), "password".into());
+        add_user(&user)?;
+        let repo_id = uuid::Uuid::new_v4().to_string();
+        add_repo(&Repo {
+            id: repo_id.clone(),
+            name: "tags".into(),
+            owner: user.id.clone(),
+            description: String::new(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            status: RepoStatus::Normal,
+            public: false,
+            topics: vec![],
+            version: 1,
+            search_tokenizer: None,
+        })?;
+        for (state, tag) in [(PostState::Published, "rust"), (PostState::Draft, "secret")] {
+            let request = OpenApiPushPostRequest {
+                id: uuid::Uuid::new_v4().to_string(),
+                category: "notes".into(),
+                title: tag.into(),
+                content: String::new(),
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+                author: user.id.clone(),
+                repo_id: repo_id.clone(),
+                expected_version: None,
+                resurrect: false,
+                category_id: None,
+                tags: Some(vec![tag.into()]),
+                state: Some(state),
+                publish_at: None,
+            };
+            push_post(&repo_id, request, None)?;
+        }
+        let tags = |viewer: &str| -> anyhow::Result<Vec<String>> {
+            Ok(list_tags_by_repo_id(&repo_id, viewer, None, 10)?
+                .into_iter()
+                .map(|count| count.tag)
+                .collect())
+        };
+        assert_eq!(tags(&user.id)?, ["rust", "secret"]);
+        assert_eq!(tags("someone else")?, ["rust"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+
+use crate::{db::new_conn, error::ServiceResult};
+
+use super::change::ChangeKind;
+
+/// marks a deleted post or comment, so offline clients do not bring it back.
+#[derive(Debug)]
+pub struct Tombstone {
+    pub kind: ChangeKind,
+    pub entity_id: String,
+    pub repo_id: String,
+    pub deleted_at: DateTime<Utc>,
+    pub deleted_by: String,
+}
+
+pub(crate) fn record_tombstone(conn: &Connection, tombstone: &Tombstone) -> ServiceResult<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO tombstone (kind, entity_id, repo_id, deleted_at, deleted_by) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            tombstone.kind.to_string(),
+            tombstone.entity_id,
+            tombstone.repo_id,
+            tombstone.deleted_at,
+            tombstone.deleted_by
+        ],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn remove_tombstone(
+    conn: &Connection,
+    kind: ChangeKind,
+    entity_id: &str,
+) -> ServiceResult<()> {
+    conn.execute(
+        "DELETE FROM tombstone WHERE kind = ?1 AND entity_id = ?2",
+        params![kind.to_string(), entity_id],
+    )?;
+    Ok(())
+}
+
+pub fn get_tombstone(kind: ChangeKind, entity_id: &str) -> ServiceResult<Option<Tombstone>> {
+    let conn = new_conn()?;
+    query_tombstone(&conn, kind, entity_id)
+}
+
+pub(crate) fn query_tombstone(
+    conn: &Connection,
+    kind: ChangeKind,
+    entity_id: &str,
+) -> ServiceResult<Option<Tombstone>> {
+    let mut stmt = conn.prepare(
+        "SELECT entity_id, repo_id, deleted_at, deleted_by FROM tombstone WHERE kind = ?1 AND entity_id = ?2",
+    )?;
+    let mut rows = stmt.query(params![kind.to_string(), entity_id])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(Tombstone {
+            kind,
+            entity_id: row.get(0)?,
+            repo_id: row.get(1)?,
+            deleted_at: row.get(2)?,
+            deleted_by: row.get(3)?,
+        })),
+        None => Ok(None),
+    }
+}
+
+/// drop tombstones, and the matching deletes in the change feed, older than `before`.
+/// cursors from before the last dropped delete are rejected after, see `check_cursor`.
+pub fn gc_tombstones(before: DateTime<Utc>) -> ServiceResult<usize> {
+    let mut conn = new_conn()?;
+    let tx = conn.transaction()?;
+    let horizon: Option<i64> = tx.query_row(
+        "SELECT MAX(seq) FROM change_log WHERE op = 'delete' AND changed_at < ?1",
+        params![before],
+        |row| row.get(0),
+    )?;
+    if let Some(horizon) = horizon {
+        tx.execute(
+            "INSERT INTO change_gc (id, seq, gc_at) VALUES (1, ?1, ?2)
+            ON CONFLICT (id) DO UPDATE SET seq = MAX(seq, excluded.seq), gc_at = excluded.gc_at",
+            params![horizon, Utc::now()],
+        )?;
+    }
+    let removed = tx.execute(
+        "DELETE FROM tombstone WHERE deleted_at < ?1",
+        params![before],
+    )?;
+    tx.execute(
+        "DELETE FROM change_log WHERE op = 'delete' AND changed_at < ?1",
+        params![before],
+    )?;
+    tx.commit()?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::change::{check_cursor, encode_cursor};
+
+    #[test]
+    fn test_gc_expires_cursors() -> anyhow::Result<()> {
+        crate::db::init_db()?;
+        let long_ago: DateTime<Utc> = "2000-01-01T00:00:00Z".parse()?;
+        let conn = new_conn()?;
+        conn.execute(
+            "INSERT INTO change_log (repo_id, kind, entity_id, op, changed_at) VALUES ('r', 'post', ?1, 'delete', ?2)",
+            params![uuid::Uuid::new_v4().to_string(), long_ago],
+        )?;
+        let seq = conn.last_insert_rowid();
+        gc_tombstones(long_ago + chrono::Duration::days(1))?;
+
+        let err = check_cursor(seq - 1).unwrap_err();
+        assert!(err.to_string().starts_with("410"), "{err}");
+        assert!(err.to_string().contains(&encode_cursor(seq - 1)));
+        check_cursor(seq)?;
+        check_cursor(0)?;
+        Ok(())
+    }
+}
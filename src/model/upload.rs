@@ -0,0 +1,237 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use salvo::{writing::Json, Scribe};
+use serde::{Deserialize, Serialize};
+
+use crate::{db::new_conn, error::ServiceResult};
+
+use super::attachment::{attachment_dir, OpenApiAttachmentResponse};
+
+/// an upload not patched for this long is abandoned.
+pub const UPLOAD_EXPIRY_HOURS: i64 = 24;
+
+/// a resumable upload in progress, its bytes so far are kept in `upload_path`.
+#[derive(Debug)]
+pub struct Upload {
+    pub id: String,
+    pub repo_id: String,
+    pub uploader: String,
+    pub name: String,
+    pub content_type: Option<String>,
+    pub length: u64,
+    pub offset: u64,            // bytes received so far
+    pub sha256: Option<String>, // of the whole file, checked on completion
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Upload {
+    pub fn is_complete(&self) -> bool {
+        self.offset == self.length
+    }
+}
+
+pub fn upload_path(id: &str) -> PathBuf {
+    attachment_dir().join("uploads").join(id)
+}
+
+pub fn add_upload(upload: &Upload) -> ServiceResult<()> {
+    let path = upload_path(&upload.id);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::File::create(&path)?;
+    let conn = new_conn()?;
+    conn.execute(
+        "INSERT INTO upload (id, repo_id, uploader, name, content_type, length, received, sha256, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            upload.id,
+            upload.repo_id,
+            upload.uploader,
+            upload.name,
+            upload.content_type,
+            upload.length,
+            upload.offset,
+            upload.sha256,
+            upload.created_at,
+            upload.expires_at
+        ],
+    )?;
+    Ok(())
+}
+
+fn query_upload(conn: &Connection, id: &str) -> ServiceResult<Option<Upload>> {
+    Ok(conn
+        .query_row(
+            "SELECT id, repo_id, uploader, name, content_type, length, received, sha256, created_at, expires_at FROM upload WHERE id = ?1",
+            params![id],
+            |row| {
+                Ok(Upload {
+                    id: row.get(0)?,
+                    repo_id: row.get(1)?,
+                    uploader: row.get(2)?,
+                    name: row.get(3)?,
+                    content_type: row.get(4)?,
+                    length: row.get(5)?,
+                    offset: row.get(6)?,
+                    sha256: row.get(7)?,
+                    created_at: row.get(8)?,
+                    expires_at: row.get(9)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+pub fn get_upload(id: &str) -> ServiceResult<Option<Upload>> {
+    let conn = new_conn()?;
+    query_upload(&conn, id)
+}
+
+fn write_chunk(id: &str, offset: u64, chunk: &[u8]) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().write(true).open(upload_path(id))?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(chunk)?;
+    file.set_len(offset + chunk.len() as u64)?;
+    file.sync_data()
+}
+
+/// ids of the uploads a patch is writing to right now.
+static WRITING: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// a patch's claim on an upload, released when dropped.
+struct WriteClaim(String);
+
+impl WriteClaim {
+    fn take(id: &str) -> Option<Self> {
+        let mut writing = WRITING.lock().unwrap_or_else(PoisonError::into_inner);
+        writing.insert(id.to_owned()).then(|| Self(id.to_owned()))
+    }
+}
+
+impl Drop for WriteClaim {
+    fn drop(&mut self) {
+        let mut writing = WRITING.lock().unwrap_or_else(PoisonError::into_inner);
+        writing.remove(&self.0);
+    }
+}
+
+/// write `chunk` at `offset` and move the offset past it, pushing the expiry back.
+/// returns none if another patch is writing the upload or moved its offset meanwhile.
+/// blocks on the write and fsync, call it off the async threads.
+pub fn append_upload(upload: &Upload, chunk: &[u8]) -> ServiceResult<Option<Upload>> {
+    // patches of an upload take turns through the claim, no transaction stays open
+    // while the chunk is written
+    let Some(_claim) = WriteClaim::take(&upload.id) else {
+        return Ok(None);
+    };
+    let conn = new_conn()?;
+    match query_upload(&conn, &upload.id)? {
+        Some(current) if current.offset == upload.offset => {}
+        _ => return Ok(None),
+    }
+    if let Err(err) = write_chunk(&upload.id, upload.offset, chunk) {
+        // cut off what got written, the offset stays where it was
+        if let Ok(file) = fs::OpenOptions::new()
+            .write(true)
+            .open(upload_path(&upload.id))
+        {
+            let _ = file.set_len(upload.offset);
+        }
+        return Err(err.into());
+    }
+    let moved = conn.execute(
+        "UPDATE upload SET received = ?3, expires_at = ?4 WHERE id = ?1 AND received = ?2",
+        params![
+            upload.id,
+            upload.offset,
+            upload.offset + chunk.len() as u64,
+            Utc::now() + Duration::hours(UPLOAD_EXPIRY_HOURS)
+        ],
+    )?;
+    if moved == 0 {
+        // aborted while the chunk was written
+        return Ok(None);
+    }
+    query_upload(&conn, &upload.id)
+}
+
+/// forget the upload and its partial file.
+pub fn delete_upload(id: &str) -> ServiceResult<()> {
+    let conn = new_conn()?;
+    conn.execute("DELETE FROM upload WHERE id = ?1", params![id])?;
+    match fs::remove_file(upload_path(id)) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// drop uploads that expired before `now`, returns how many.
+pub fn gc_uploads(now: DateTime<Utc>) -> ServiceResult<usize> {
+    let conn = new_conn()?;
+    let mut stmt = conn.prepare("SELECT id FROM upload WHERE expires_at < ?1")?;
+    let expired = stmt
+        .query_map(params![now], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for id in &expired {
+        delete_upload(id)?;
+    }
+    Ok(expired.len())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiNewUploadRequest {
+    pub name: String,
+    pub content_type: Option<String>,
+    pub length: u64,
+    pub sha256: Option<String>, // hex, of the whole file
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiUploadResponse {
+    pub id: String,
+    pub offset: u64,
+    pub length: u64,
+    pub expires_at: DateTime<Utc>,
+    pub attachment: Option<OpenApiAttachmentResponse>, // set once the upload is complete
+}
+
+impl From<Upload> for OpenApiUploadResponse {
+    fn from(value: Upload) -> Self {
+        Self {
+            id: value.id,
+            offset: value.offset,
+            length: value.length,
+            expires_at: value.expires_at,
+            attachment: None,
+        }
+    }
+}
+
+/// tus style `Upload-Offset` and `Upload-Length` headers.
+pub fn set_upload_headers(res: &mut salvo::Response, offset: u64, length: u64) {
+    if let Ok(offset) = offset.to_string().parse() {
+        res.headers_mut().insert("Upload-Offset", offset);
+    }
+    if let Ok(length) = length.to_string().parse() {
+        res.headers_mut().insert("Upload-Length", length);
+    }
+}
+
+impl Scribe for OpenApiUploadResponse {
+    fn render(self, res: &mut salvo::Response) {
+        set_upload_headers(res, self.offset, self.length);
+        res.render(Json(&self));
+    }
+}
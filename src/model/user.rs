@@ -149,6 +149,7 @@ mod tests {
 
     #[test]
     fn test_user() -> anyhow::Result<()> {
+        crate::db::init_db()?;
         let user = User::new("name".into(), "password".into());
         add_user(&user)?;
         Ok(())
@@ -3,8 +3,13 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     pub log_path: Option<String>,
+    pub db_path: Option<String>,
     pub cert: String,
     pub key: String,
     pub port: Option<u16>,
     pub latest_version: String,
+    pub tombstone_retention_days: Option<u32>,
+    pub search_tokenizer: Option<String>, // unicode61 | cjk_bigram
+    pub attachment_dir: Option<String>,
+    pub max_attachment_mb: Option<u64>,
 }
@@ -0,0 +1,206 @@
+use chrono::Utc;
+use salvo::{
+    fs::NamedFile,
+    handler,
+    http::{header, HeaderValue, StatusCode},
+    Depot, Request, Response, Router,
+};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::{
+        attachment::{
+            add_attachment_file, blob_path, get_attachment, is_sha256, list_attachments_by_repo_id,
+            sha256_file, Attachment, OpenApiAttachmentResponse, OpenApiListAttachmentResponse,
+        },
+        media::{get_or_make_thumbnail, THUMBNAIL_SIZES},
+    },
+    router::utils::{
+        check_owner_or_subscribe, check_repo_owner, get_current_user_id, get_req_path,
+        limit_multipart_body,
+    },
+};
+
+const DEFAULT_MAX_ATTACHMENT_MB: u64 = 50;
+
+pub(super) fn max_attachment_size() -> u64 {
+    crate::SERVER_CONFIG
+        .max_attachment_mb
+        .unwrap_or(DEFAULT_MAX_ATTACHMENT_MB)
+        * 1024
+        * 1024
+}
+
+pub fn router() -> Router {
+    Router::new()
+        .get(list_attachment)
+        .post(upload_attachment)
+        .push(
+            Router::with_path("<sha256>")
+                .get(download_attachment)
+                .push(Router::with_path("thumbnail").get(download_thumbnail)),
+        )
+}
+
+#[handler]
+async fn list_attachment(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListAttachmentResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    info!("list attachment in repo {repo_id}");
+    let attachments = list_attachments_by_repo_id(&repo_id)?;
+    Ok(OpenApiListAttachmentResponse(
+        attachments.into_iter().map(Into::into).collect(),
+    ))
+}
+
+/// multipart upload of the `file` field, uploading the same content again returns
+/// the existing attachment.
+#[handler]
+async fn upload_attachment(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiAttachmentResponse> {
+    let current_user_id = get_current_user_id(depot)?.clone();
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_repo_owner(&repo_id, &current_user_id)?;
+    limit_multipart_body(req, max_attachment_size())?;
+    req.form_data().await?;
+    let Some(file) = req.file("file").await else {
+        return Err(ServiceError::BadRequest(
+            "need multipart field `file`".to_owned(),
+        ));
+    };
+    if file.size() > max_attachment_size() {
+        return Err(ServiceError::BadRequest(format!(
+            "attachment larger than {} bytes",
+            max_attachment_size()
+        )));
+    }
+    let name = file.name().unwrap_or("attachment").to_owned();
+    let content_type = file
+        .content_type()
+        .filter(|mime| *mime != mime::APPLICATION_OCTET_STREAM)
+        .unwrap_or_else(|| mime_infer::from_path(&name).first_or_octet_stream())
+        .to_string();
+    let (sha256, size) = sha256_file(file.path())?;
+    info!("upload attachment {sha256} ({size} bytes) to repo {repo_id}");
+    let (attachment, created) = add_attachment_file(
+        file.path(),
+        Attachment {
+            repo_id,
+            sha256,
+            name,
+            content_type,
+            size,
+            uploader: current_user_id,
+            created_at: Utc::now(),
+        },
+    )?;
+    response.status_code(if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    });
+    Ok(attachment.into())
+}
+
+/// svg can carry scripts, so it is treated like any other non-image file.
+fn is_raster_image(content_type: &mime::Mime) -> bool {
+    content_type.type_() == mime::IMAGE && content_type.subtype() != mime::SVG
+}
+
+/// the attachment content, with range support. only images are shown inline.
+#[handler]
+async fn download_attachment(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<()> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let sha256 = get_req_path(req, "sha256")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    if !is_sha256(&sha256) {
+        return Err(ServiceError::NotFound("attachment not found".to_owned()));
+    }
+    let Some(attachment) = get_attachment(&repo_id, &sha256)? else {
+        return Err(ServiceError::NotFound("attachment not found".to_owned()));
+    };
+    let path = blob_path(&attachment.sha256);
+    if !path.exists() {
+        return Err(ServiceError::InternalServerError(format!(
+            "blob of attachment {sha256} is missing"
+        )));
+    }
+    let content_type = attachment
+        .content_type
+        .parse::<mime::Mime>()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+    let inline = is_raster_image(&content_type);
+    let headers = response.headers_mut();
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("sandbox"),
+    );
+    NamedFile::builder(path)
+        .content_type(content_type)
+        .disposition_type(if inline { "inline" } else { "attachment" })
+        .attached_name(attachment.name)
+        .send(req.headers(), response)
+        .await;
+    Ok(())
+}
+
+/// a png of an image attachment scaled down to fit `size`, one of `THUMBNAIL_SIZES`.
+#[handler]
+async fn download_thumbnail(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<()> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let sha256 = get_req_path(req, "sha256")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    let size = req.query::<u32>("size").unwrap_or(THUMBNAIL_SIZES[1]);
+    if !THUMBNAIL_SIZES.contains(&size) {
+        return Err(ServiceError::BadRequest(format!(
+            "size should be one of {THUMBNAIL_SIZES:?}"
+        )));
+    }
+    let attachment = match is_sha256(&sha256) {
+        true => get_attachment(&repo_id, &sha256)?,
+        false => None,
+    }
+    .ok_or(ServiceError::NotFound("attachment not found".to_owned()))?;
+    let content_type = attachment
+        .content_type
+        .parse::<mime::Mime>()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+    if !is_raster_image(&content_type) {
+        return Err(ServiceError::BadRequest(
+            "only image attachments have thumbnails".to_owned(),
+        ));
+    }
+    let path = tokio::task::spawn_blocking(move || {
+        get_or_make_thumbnail(&blob_path(&sha256), &sha256, size)
+    })
+    .await
+    .map_err(|err| ServiceError::InternalServerError(err.to_string()))??;
+    NamedFile::builder(path)
+        .content_type(mime::IMAGE_PNG)
+        .disposition_type("inline")
+        .send(req.headers(), response)
+        .await;
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use chrono::Utc;
+use salvo::{handler, http::StatusCode, Depot, Request, Response, Router};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::category::{
+        add_category, delete_category, get_category_by_id, get_category_by_name,
+        list_categories_by_repo_id, merge_category, reorder_categories, update_category,
+        validate_category_name, Category, OpenApiGetCategoryResponse, OpenApiListCategoryResponse,
+        OpenApiMergeCategoryRequest, OpenApiPushCategoryRequest, OpenApiReorderCategoryRequest,
+    },
+    router::utils::{
+        check_owner_or_subscribe, check_repo_owner, get_current_user_id, get_req_path,
+    },
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .get(list_category)
+        .post(create_category)
+        .push(Router::with_path("order").put(reorder_category))
+        .push(
+            Router::with_path("<category_id>")
+                .put(edit_category)
+                .delete(remove_category)
+                .push(Router::with_path("merge").post(merge_into_category)),
+        )
+}
+
+fn get_repo_category(repo_id: &str, category_id: &str) -> ServiceResult<Category> {
+    match get_category_by_id(category_id)? {
+        Some(category) if category.repo_id == repo_id => Ok(category),
+        _ => Err(ServiceError::NotFound("category not found".to_owned())),
+    }
+}
+
+fn check_category_name(repo_id: &str, category_id: Option<&str>, name: &str) -> ServiceResult<()> {
+    validate_category_name(name)?;
+    match get_category_by_name(repo_id, name)? {
+        Some(other) if Some(other.id.as_str()) != category_id => {
+            Err(ServiceError::Conflict(format!(
+                "category {:?} already exists, merge into it instead",
+                other.name
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[handler]
+async fn list_category(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListCategoryResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    info!("list category in repo {repo_id}");
+    let categories = list_categories_by_repo_id(&repo_id)?;
+    Ok(OpenApiListCategoryResponse(
+        categories.into_iter().map(Into::into).collect(),
+    ))
+}
+
+#[handler]
+async fn create_category(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiGetCategoryResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_repo_owner(&repo_id, current_user_id)?;
+    let body = req.parse_body::<OpenApiPushCategoryRequest>().await?;
+    let name = body.name.trim().to_owned();
+    check_category_name(&repo_id, None, &name)?;
+    let now = Utc::now();
+    let mut category = Category {
+        id: uuid::Uuid::new_v4().to_string(),
+        repo_id,
+        name,
+        color: body.color,
+        icon: body.icon,
+        sort_order: 0,
+        created_at: now,
+        updated_at: now,
+    };
+    info!("add category {category:?}");
+    category.sort_order = add_category(&category)?;
+    response.status_code(StatusCode::CREATED);
+    Ok(category.into())
+}
+
+/// rename or restyle a category, a rename is applied to all its posts.
+#[handler]
+async fn edit_category(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiGetCategoryResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let category_id = get_req_path(req, "category_id")?;
+    check_repo_owner(&repo_id, current_user_id)?;
+    let old = get_repo_category(&repo_id, &category_id)?;
+    let body = req.parse_body::<OpenApiPushCategoryRequest>().await?;
+    let name = body.name.trim().to_owned();
+    check_category_name(&repo_id, Some(&category_id), &name)?;
+    let category = Category {
+        name,
+        color: body.color,
+        icon: body.icon,
+        updated_at: Utc::now(),
+        ..old
+    };
+    info!("update category {category:?}");
+    update_category(&category)?;
+    Ok(category.into())
+}
+
+#[handler]
+async fn remove_category(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<()> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let category_id = get_req_path(req, "category_id")?;
+    check_repo_owner(&repo_id, current_user_id)?;
+    let category = get_repo_category(&repo_id, &category_id)?;
+    info!("delete category {}", category.id);
+    if !delete_category(&category.id)? {
+        return Err(ServiceError::Conflict(format!(
+            "category {:?} still has posts, merge it instead",
+            category.name
+        )));
+    }
+    response.status_code(StatusCode::NO_CONTENT);
+    Ok(())
+}
+
+/// move the posts of the category into `into` and drop it.
+#[handler]
+async fn merge_into_category(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiGetCategoryResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let category_id = get_req_path(req, "category_id")?;
+    check_repo_owner(&repo_id, current_user_id)?;
+    let body = req.parse_body::<OpenApiMergeCategoryRequest>().await?;
+    let from = get_repo_category(&repo_id, &category_id)?;
+    let into = get_repo_category(&repo_id, &body.into)?;
+    if from.id == into.id {
+        return Err(ServiceError::BadRequest(
+            "cannot merge a category into itself".to_owned(),
+        ));
+    }
+    info!("merge category {} into {}", from.id, into.id);
+    merge_category(&from.id, &into.id)?;
+    Ok(into.into())
+}
+
+#[handler]
+async fn reorder_category(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListCategoryResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_repo_owner(&repo_id, current_user_id)?;
+    let body = req.parse_body::<OpenApiReorderCategoryRequest>().await?;
+    for id in &body.ids {
+        get_repo_category(&repo_id, id)?;
+    }
+    info!("reorder category in repo {repo_id}");
+    reorder_categories(&repo_id, &body.ids)?;
+    let categories = list_categories_by_repo_id(&repo_id)?;
+    Ok(OpenApiListCategoryResponse(
+        categories.into_iter().map(Into::into).collect(),
+    ))
+}
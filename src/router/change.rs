@@ -0,0 +1,79 @@
+use salvo::{handler, Depot, Request, Router};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::change::{
+        check_cursor, decode_cursor, encode_cursor, list_repo_changes, list_user_changes, Change,
+        OpenApiListChangeResponse,
+    },
+    router::utils::{check_owner_or_subscribe, get_current_user_id, get_req_path},
+};
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 500;
+
+/// mounted at `repo/<repo_id>/changes`.
+pub fn repo_router() -> Router {
+    Router::new().get(list_repo_change)
+}
+
+/// mounted at `changes`, across every repo the user owns or subscribes.
+pub fn user_router() -> Router {
+    Router::new().get(list_user_change)
+}
+
+/// `(after, limit)` from the `cursor` and `limit` query, no cursor starts from the beginning.
+/// a cursor older than the kept deletes answers 410, the client has to start over.
+fn parse_page(req: &mut Request) -> ServiceResult<(i64, u32)> {
+    let after = match req.query::<String>("cursor") {
+        Some(cursor) if !cursor.is_empty() => decode_cursor(&cursor)?,
+        _ => 0,
+    };
+    check_cursor(after)?;
+    let limit = req.query::<u32>("limit").unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ServiceError::BadRequest(format!(
+            "limit should be in 1..={MAX_LIMIT}"
+        )));
+    }
+    Ok((after, limit))
+}
+
+/// fetches one more than `limit` to tell whether another page follows.
+fn into_response(mut changes: Vec<Change>, after: i64, limit: u32) -> OpenApiListChangeResponse {
+    let has_more = changes.len() > limit as usize;
+    changes.truncate(limit as usize);
+    let last = changes.last().map_or(after, |change| change.seq);
+    OpenApiListChangeResponse {
+        changes: changes.into_iter().map(Into::into).collect(),
+        next_cursor: encode_cursor(last),
+        has_more,
+    }
+}
+
+#[handler]
+async fn list_repo_change(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListChangeResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    let (after, limit) = parse_page(req)?;
+    info!("list changes of repo {repo_id} after {after}");
+    let changes = list_repo_changes(&repo_id, current_user_id, after, limit + 1)?;
+    Ok(into_response(changes, after, limit))
+}
+
+#[handler]
+async fn list_user_change(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListChangeResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let (after, limit) = parse_page(req)?;
+    info!("list changes of user {current_user_id} after {after}");
+    let changes = list_user_changes(current_user_id, after, limit + 1)?;
+    Ok(into_response(changes, after, limit))
+}
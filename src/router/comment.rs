@@ -3,12 +3,19 @@ use tracing::info;
 
 use crate::{
     error::{ServiceError, ServiceResult},
-    model::comment::{
-        add_comment, delete_comment_by_id, get_comment_by_id, list_comments_by_post_id,
-        update_comment, Comment, OpenApiGetCommentResponse, OpenApiListCommentResponse,
-        OpenApiPushCommentRequest,
+    model::{
+        change::ChangeKind,
+        comment::{
+            add_comment, delete_comment_by_id, get_comment_by_id, list_comments_by_post_id,
+            update_comment, Comment, OpenApiGetCommentResponse, OpenApiListCommentResponse,
+            OpenApiPushCommentRequest,
+        },
+        reaction::{list_reactions, list_reactions_by_target_ids},
+        tombstone::get_tombstone,
+    },
+    router::utils::{
+        check_owner_or_subscribe, get_current_user_id, get_list_query, get_repo_post, get_req_path,
     },
-    router::utils::{check_owner_or_subscribe, get_current_user_id, get_req_path},
 };
 
 pub fn router() -> Router {
@@ -28,12 +35,19 @@ async fn list_comment(
     let repo_id = get_req_path(request, "repo_id")?;
     let post_id = get_req_path(request, "post_id")?;
     check_owner_or_subscribe(&repo_id, current_user_id)?;
+    get_repo_post(request, &repo_id, &post_id, current_user_id)?;
     info!("list comment in post {post_id}");
-    let comments = list_comments_by_post_id(&post_id)?;
+    let query = get_list_query(request)?;
+    let comments = list_comments_by_post_id(&post_id, &query)?;
     // info!("list comment result: {comments:?}");
-    Ok(OpenApiListCommentResponse(
-        comments.into_iter().map(|comment| comment.into()).collect(),
-    ))
+    let mut comments: OpenApiListCommentResponse = comments.into();
+    let ids: Vec<String> = comments.comments.iter().map(|c| c.id.clone()).collect();
+    let mut reactions = list_reactions_by_target_ids(ChangeKind::Comment, &ids, current_user_id)?;
+    for comment in &mut comments.comments {
+        let reactions = reactions.remove(&comment.id).unwrap_or_default();
+        comment.reactions = Some(reactions.into_iter().map(Into::into).collect());
+    }
+    Ok(comments)
 }
 
 #[handler]
@@ -46,6 +60,7 @@ async fn get_comment(
     let post_id = get_req_path(request, "post_id")?;
     let comment_id = get_req_path(request, "comment_id")?;
     check_owner_or_subscribe(&repo_id, current_user_id)?;
+    get_repo_post(request, &repo_id, &post_id, current_user_id)?;
     let comment = get_comment_by_id(&comment_id)?;
     info!("get comment {comment:?}");
     match comment {
@@ -53,7 +68,11 @@ async fn get_comment(
             if comment.post_id != post_id || comment.repo_id != repo_id {
                 return Err(ServiceError::NotFound("comment not found".to_owned()));
             }
-            Ok(comment.into())
+            let reactions = list_reactions(ChangeKind::Comment, &comment.id, current_user_id)?;
+            Ok(OpenApiGetCommentResponse {
+                reactions: Some(reactions.into_iter().map(Into::into).collect()),
+                ..comment.into()
+            })
         }
         None => Err(ServiceError::NotFound("comment not found".to_owned())),
     }
@@ -76,6 +95,9 @@ async fn push_comment(
             // update
             let current_comment = get_comment_by_id(&id)?;
             match current_comment {
+                None if get_tombstone(ChangeKind::Comment, &id)?.is_some() => Err(
+                    ServiceError::Conflict(format!("comment {id} has been deleted")),
+                ),
                 None => Err(ServiceError::NotFound("comment not found".to_owned())),
                 Some(current_comment) => {
                     let updated = Comment {
@@ -96,6 +118,7 @@ async fn push_comment(
         }
         None => {
             // insert
+            get_repo_post(request, &repo_id, &post_id, current_user_id)?;
             let comment = Comment {
                 id: uuid::Uuid::new_v4().to_string(),
                 post_id,
@@ -135,7 +158,7 @@ async fn delete_comment(
         return Err(ServiceError::Forbidden("forbidden".to_owned()));
     }
     info!("do delete comment {comment:?}");
-    delete_comment_by_id(&comment_id)?;
+    delete_comment_by_id(&comment_id, current_user_id)?;
     response.status_code(StatusCode::NO_CONTENT);
 
     Ok(())
@@ -0,0 +1,51 @@
+use std::str::FromStr;
+
+use salvo::{handler, Request, Router};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::directory::{
+        search_public_repos, DirectoryQuery, DirectorySort, OpenApiDirectoryResponse,
+    },
+};
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 100;
+
+pub fn router() -> Router {
+    Router::new().get(list_directory)
+}
+
+/// public repos, filtered by `q` and `topic`, sorted by `sort` = subscribers|activity.
+#[handler]
+async fn list_directory(req: &mut Request) -> ServiceResult<OpenApiDirectoryResponse> {
+    let sort = match req.query::<String>("sort") {
+        Some(sort) => DirectorySort::from_str(&sort)?,
+        None => DirectorySort::Activity,
+    };
+    let page = req.query::<u32>("page").unwrap_or(1).max(1);
+    let limit = req.query::<u32>("limit").unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ServiceError::BadRequest(format!(
+            "limit should be in 1..={MAX_LIMIT}"
+        )));
+    }
+    let query = DirectoryQuery {
+        keyword: req.query::<String>("q").filter(|q| !q.trim().is_empty()),
+        topic: req
+            .query::<String>("topic")
+            .filter(|t| !t.trim().is_empty()),
+        sort,
+        page,
+        limit,
+    };
+    info!("list directory {query:?}");
+    let (entries, total) = search_public_repos(&query)?;
+    Ok(OpenApiDirectoryResponse {
+        repos: entries.into_iter().map(Into::into).collect(),
+        page,
+        limit,
+        total,
+    })
+}
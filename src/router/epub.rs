@@ -0,0 +1,76 @@
+use salvo::{fs::NamedFile, handler, Depot, Request, Response, Router};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::{
+        epub::{export_epub_to_file, EpubOptions},
+        export::{export_path, path_segment},
+        listing::ListSort,
+        repo::get_repo_by_id,
+    },
+    router::utils::{check_owner_or_subscribe, get_current_user_id, get_req_path},
+};
+
+const DEFAULT_LANG: &str = "en";
+
+/// mounted at `repo/<repo_id>/epub`.
+pub fn router() -> Router {
+    Router::new().get(export_epub)
+}
+
+/// the repo as an epub book, or only `category`. chapters follow `sort` =
+/// created|updated|title and `order` = asc|desc, `lang` is the language of the book.
+#[handler]
+async fn export_epub(
+    req: &mut Request,
+    depot: &mut Depot,
+    response: &mut Response,
+) -> ServiceResult<()> {
+    let current_user_id = get_current_user_id(depot)?.clone();
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_owner_or_subscribe(&repo_id, &current_user_id)?;
+    let Some(repo) = get_repo_by_id(&repo_id)? else {
+        return Err(ServiceError::NotFound("repo not found".to_owned()));
+    };
+    let sort = match req.query::<String>("sort") {
+        Some(sort) => sort.parse::<ListSort>()?,
+        None => ListSort::default(),
+    };
+    let desc = match req.query::<String>("order").as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(order) => return Err(ServiceError::BadRequest(format!("invalid order {order:?}"))),
+    };
+    let options = EpubOptions {
+        category: req.query::<String>("category").filter(|c| !c.is_empty()),
+        sort,
+        desc,
+        lang: req
+            .query::<String>("lang")
+            .filter(|lang| !lang.is_empty())
+            .unwrap_or_else(|| DEFAULT_LANG.to_owned()),
+    };
+    info!("export repo {repo_id} as epub, {options:?}");
+    let name = match &options.category {
+        Some(category) => format!("{}-{category}", repo.name),
+        None => repo.name,
+    };
+    let path = export_path(&uuid::Uuid::new_v4().to_string());
+    let spool = path.clone();
+    tokio::task::spawn_blocking(move || {
+        export_epub_to_file(&repo_id, &current_user_id, &options, &spool)
+    })
+    .await
+    .map_err(|err| ServiceError::InternalServerError(err.to_string()))??;
+    let file = NamedFile::builder(&path)
+        .content_type(mime_infer::from_ext("epub").first_or_octet_stream())
+        .attached_name(format!("{}.epub", path_segment(&name, "repo")))
+        .build()
+        .await;
+    // the open file keeps the data around until sent
+    std::fs::remove_file(&path)?;
+    let file = file.map_err(|err| ServiceError::InternalServerError(err.to_string()))?;
+    file.send(req.headers(), response).await;
+    Ok(())
+}
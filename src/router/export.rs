@@ -0,0 +1,51 @@
+use salvo::{fs::NamedFile, handler, Depot, Request, Response, Router};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::{
+        export::{export_path, export_repo_to_file, path_segment},
+        repo::get_repo_by_id,
+    },
+    router::utils::{check_owner_or_subscribe, get_current_user_id, get_req_path},
+};
+
+/// mounted at `repo/<repo_id>/export`.
+pub fn router() -> Router {
+    Router::new().get(export_repo)
+}
+
+/// the repo as a zip of markdown files, `comments=true` adds the comments of each post.
+/// the archive is spooled to disk and sent from there, the file is gone once opened.
+#[handler]
+async fn export_repo(
+    req: &mut Request,
+    depot: &mut Depot,
+    response: &mut Response,
+) -> ServiceResult<()> {
+    let current_user_id = get_current_user_id(depot)?.clone();
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_owner_or_subscribe(&repo_id, &current_user_id)?;
+    let Some(repo) = get_repo_by_id(&repo_id)? else {
+        return Err(ServiceError::NotFound("repo not found".to_owned()));
+    };
+    let comments = req.query::<bool>("comments").unwrap_or(false);
+    info!("export repo {repo_id}, comments {comments}");
+    let path = export_path(&uuid::Uuid::new_v4().to_string());
+    let spool = path.clone();
+    tokio::task::spawn_blocking(move || {
+        export_repo_to_file(&repo_id, &current_user_id, comments, &spool)
+    })
+    .await
+    .map_err(|err| ServiceError::InternalServerError(err.to_string()))??;
+    let file = NamedFile::builder(&path)
+        .content_type(mime_infer::from_ext("zip").first_or_octet_stream())
+        .attached_name(format!("{}.zip", path_segment(&repo.name, "repo")))
+        .build()
+        .await;
+    // the open file keeps the data around until sent
+    std::fs::remove_file(&path)?;
+    let file = file.map_err(|err| ServiceError::InternalServerError(err.to_string()))?;
+    file.send(req.headers(), response).await;
+    Ok(())
+}
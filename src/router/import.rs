@@ -0,0 +1,206 @@
+use std::path::{Path, PathBuf};
+
+use salvo::{handler, Depot, Request, Router};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::{
+        enex::import_enex,
+        export::path_segment,
+        import::{
+            import_dir, import_entries, unpack_archive, unpack_tar, ImportEntry,
+            OpenApiImportResponse, MAX_IMPORT_ENTRIES, MAX_UNPACKED_BYTES,
+        },
+        joplin::import_joplin,
+    },
+    router::{
+        attachment::max_attachment_size,
+        utils::{check_repo_owner, get_current_user_id, get_req_path, limit_multipart_body},
+    },
+};
+
+/// mounted at `repo/<repo_id>/import`.
+pub fn router() -> Router {
+    Router::new()
+        .post(import_repo)
+        .push(Router::with_path("enex").post(import_enex_notes))
+        .push(Router::with_path("joplin").post(import_joplin_notes))
+}
+
+/// the repo and importer of an import, with the uploaded files as `(name, path)`.
+struct ImportRequest {
+    repo_id: String,
+    author: String,
+    resurrect: bool,
+    files: Vec<(String, PathBuf)>,
+}
+
+/// files of the multipart field `file`, the repo owner only. `resurrect=true` imports
+/// deleted posts again instead of reporting a conflict.
+async fn import_request(req: &mut Request, depot: &mut Depot) -> ServiceResult<ImportRequest> {
+    let author = get_current_user_id(depot)?.clone();
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_repo_owner(&repo_id, &author)?;
+    let resurrect = req.query::<bool>("resurrect").unwrap_or(false);
+    // loose files or an archive, either way no more than an import may unpack
+    limit_multipart_body(req, MAX_UNPACKED_BYTES)?;
+    req.form_data().await?;
+    let Some(parts) = req.files("file").await else {
+        return Err(ServiceError::BadRequest(
+            "need multipart field `file`".to_owned(),
+        ));
+    };
+    if parts.len() > MAX_IMPORT_ENTRIES {
+        return Err(ServiceError::BadRequest(format!(
+            "more than {MAX_IMPORT_ENTRIES} files"
+        )));
+    }
+    let files = parts
+        .iter()
+        .map(|part| {
+            (
+                part.name().unwrap_or_default().to_owned(),
+                part.path().clone(),
+            )
+        })
+        .collect();
+    Ok(ImportRequest {
+        repo_id,
+        author,
+        resurrect,
+        files,
+    })
+}
+
+/// the files to import: those of a single zip or tar unpacked into `dir`, or the
+/// uploaded ones named by their relative paths.
+fn upload_entries(
+    files: Vec<(String, PathBuf)>,
+    dir: &Path,
+    max_size: u64,
+) -> ServiceResult<Vec<ImportEntry>> {
+    if let [(name, archive)] = files.as_slice() {
+        let name = name.to_lowercase();
+        if name.ends_with(".zip") {
+            return unpack_archive(archive, dir, max_size, MAX_UNPACKED_BYTES);
+        }
+        if name.ends_with(".jex") || name.ends_with(".tar") {
+            return unpack_tar(archive, dir, max_size, MAX_UNPACKED_BYTES);
+        }
+    }
+    Ok(files
+        .into_iter()
+        .map(|(path, file)| ImportEntry {
+            path,
+            file,
+            modified: None,
+        })
+        .collect())
+}
+
+/// run `import` on a blocking thread with a work directory removed afterwards.
+async fn run_import<F>(import: F) -> ServiceResult<OpenApiImportResponse>
+where
+    F: FnOnce(&Path, u64) -> ServiceResult<OpenApiImportResponse> + Send + 'static,
+{
+    let max_size = max_attachment_size();
+    tokio::task::spawn_blocking(move || {
+        let dir = import_dir(&uuid::Uuid::new_v4().to_string());
+        let result = import(&dir, max_size);
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    })
+    .await
+    .map_err(|err| ServiceError::InternalServerError(err.to_string()))?
+}
+
+/// import markdown into the repo: a zip, or the files of a directory.
+#[handler]
+async fn import_repo(req: &mut Request, depot: &mut Depot) -> ServiceResult<OpenApiImportResponse> {
+    let import = import_request(req, depot).await?;
+    info!(
+        "import {} files into repo {}",
+        import.files.len(),
+        import.repo_id
+    );
+    run_import(move |dir, max_size| {
+        let entries = upload_entries(import.files, dir, max_size)?;
+        import_entries(
+            &import.repo_id,
+            &import.author,
+            entries,
+            import.resurrect,
+            max_size,
+        )
+    })
+    .await
+}
+
+/// import evernote exports, one or more `.enex` files. the notes go to the `category`
+/// given, or one named after their file.
+#[handler]
+async fn import_enex_notes(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiImportResponse> {
+    let category = req.query::<String>("category");
+    let import = import_request(req, depot).await?;
+    info!(
+        "import {} enex files into repo {}",
+        import.files.len(),
+        import.repo_id
+    );
+    run_import(move |dir, max_size| {
+        let mut results = Vec::new();
+        for (name, file) in &import.files {
+            let stem = name.rsplit(['/', '\\']).next().unwrap_or_default();
+            let stem = stem.rsplit_once('.').map_or(stem, |(stem, _)| stem);
+            let category = category
+                .clone()
+                .unwrap_or_else(|| path_segment(stem, "evernote"));
+            import_enex(
+                &import.repo_id,
+                &import.author,
+                file,
+                &category,
+                dir,
+                import.resurrect,
+                max_size,
+                &mut results,
+            )?;
+        }
+        results.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(OpenApiImportResponse { results })
+    })
+    .await
+}
+
+/// import a joplin export: a `.jex`, a zip of a raw export, or the files of one.
+#[handler]
+async fn import_joplin_notes(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiImportResponse> {
+    let import = import_request(req, depot).await?;
+    info!(
+        "import {} joplin files into repo {}",
+        import.files.len(),
+        import.repo_id
+    );
+    run_import(move |dir, max_size| {
+        let entries = upload_entries(import.files, dir, max_size)?;
+        let mut results = Vec::new();
+        import_joplin(
+            &import.repo_id,
+            &import.author,
+            entries,
+            import.resurrect,
+            max_size,
+            &mut results,
+        )?;
+        results.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(OpenApiImportResponse { results })
+    })
+    .await
+}
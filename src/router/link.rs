@@ -0,0 +1,51 @@
+use salvo::{handler, Depot, Request, Router};
+use tracing::info;
+
+use crate::{
+    error::ServiceResult,
+    model::link::{
+        get_link_graph, list_backlinks, OpenApiLinkGraphResponse, OpenApiListBacklinkResponse,
+    },
+    router::utils::{check_owner_or_subscribe, get_current_user_id, get_repo_post, get_req_path},
+};
+
+/// mounted at `repo/<repo_id>/post/<post_id>/backlinks`.
+pub fn backlink_router() -> Router {
+    Router::new().get(list_backlink)
+}
+
+/// mounted at `repo/<repo_id>/graph`.
+pub fn graph_router() -> Router {
+    Router::new().get(get_graph)
+}
+
+/// posts of the repo linking to the post with `[[title]]` or `[[id]]`.
+#[handler]
+async fn list_backlink(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListBacklinkResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let post_id = get_req_path(req, "post_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    let post = get_repo_post(req, &repo_id, &post_id, current_user_id)?;
+    info!("list backlinks of post {post_id}");
+    let posts = list_backlinks(&repo_id, &post.id, current_user_id)?;
+    Ok(OpenApiListBacklinkResponse(
+        posts.into_iter().map(Into::into).collect(),
+    ))
+}
+
+/// every post of the repo with the links between them, and the links matching no post.
+#[handler]
+async fn get_graph(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiLinkGraphResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    info!("get link graph of repo {repo_id}");
+    Ok(get_link_graph(&repo_id, current_user_id)?.into())
+}
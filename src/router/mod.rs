@@ -1,9 +1,24 @@
 use salvo::{basic_auth::BasicAuth, handler, http::StatusCode, Response, Router};
 
+mod attachment;
+mod category;
+mod change;
 mod comment;
+mod directory;
+mod epub;
+mod export;
+mod import;
+mod link;
 mod post;
+mod reaction;
+mod read;
 mod repo;
+mod revision;
+mod search;
+mod site;
 mod subscribe;
+mod tag;
+mod upload;
 mod user;
 mod utils;
 mod version;
@@ -11,9 +26,34 @@ mod version;
 pub fn router() -> Router {
     let function_router = Router::with_hoop(BasicAuth::new(user::UserValidator))
         .push(Router::with_path("repo").push(repo::router()))
+        .push(Router::with_path("repo/<repo_id>/attachment").push(attachment::router()))
+        .push(Router::with_path("repo/<repo_id>/category").push(category::router()))
+        .push(Router::with_path("repo/<repo_id>/changes").push(change::repo_router()))
+        .push(Router::with_path("repo/<repo_id>/epub").push(epub::router()))
+        .push(Router::with_path("repo/<repo_id>/export").push(export::router()))
+        .push(Router::with_path("repo/<repo_id>/graph").push(link::graph_router()))
+        .push(Router::with_path("repo/<repo_id>/import").push(import::router()))
         .push(Router::with_path("repo/<repo_id>/post").push(post::router()))
+        .push(
+            Router::with_path("repo/<repo_id>/post/<post_id>/backlinks")
+                .push(link::backlink_router()),
+        )
         .push(Router::with_path("repo/<repo_id>/post/<post_id>/comment").push(comment::router()))
+        .push(
+            Router::with_path("repo/<repo_id>/post/<post_id>/comment/<comment_id>/reaction")
+                .push(reaction::router()),
+        )
+        .push(Router::with_path("repo/<repo_id>/post/<post_id>/reaction").push(reaction::router()))
+        .push(Router::with_path("repo/<repo_id>/post/<post_id>/read").push(read::post_router()))
+        .push(Router::with_path("repo/<repo_id>/post/<post_id>/revision").push(revision::router()))
+        .push(Router::with_path("repo/<repo_id>/read").push(read::repo_router()))
+        .push(Router::with_path("repo/<repo_id>/site").push(site::router()))
+        .push(Router::with_path("repo/<repo_id>/tag").push(tag::router()))
+        .push(Router::with_path("repo/<repo_id>/upload").push(upload::router()))
+        .push(Router::with_path("changes").push(change::user_router()))
+        .push(Router::with_path("search").push(search::router()))
         .push(Router::with_path("subscribe").push(subscribe::router()))
+        .push(Router::with_path("directory").push(directory::router()))
         .push(Router::with_path("version").push(version::router()));
     let user_router = Router::with_path("user").push(user::router());
     let health_router = Router::with_path("health").get(health);
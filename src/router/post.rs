@@ -1,28 +1,44 @@
-use salvo::{handler, http::StatusCode, Depot, Request, Response, Router};
+use salvo::{handler, http::StatusCode, writing::Text, Depot, Request, Response, Router};
 use tracing::info;
 
 use crate::{
     error::{ServiceError, ServiceResult},
     model::{
-        comment::list_comments_by_post_id,
+        change::ChangeKind,
+        comment::list_comments_by_post_ids,
+        markdown::get_post_html,
         post::{
-            add_post, erase_post, get_post_by_id, list_posts_by_repo_id, update_post,
-            OpenApiGetPostResponse, OpenApiListPostResponse, OpenApiPostSummaryResponse,
-            OpenApiPushPostRequest, Post,
+            erase_post, list_posts_by_repo_id, move_post, post_version_conflict,
+            push_post as push_post_to_repo, push_posts, OpenApiBatchPushRequest,
+            OpenApiBatchPushResponse, OpenApiGetPostResponse, OpenApiListPostResponse,
+            OpenApiMovePostRequest, OpenApiPostSummaryResponse, OpenApiPushPostRequest,
+            PushOutcome,
         },
-        repo::get_repo_by_id,
+        reaction::{list_reactions, list_reactions_by_target_ids},
+        read::list_posts_unread,
+        repo::{get_repo_by_id, set_etag},
     },
     router::utils::{
-        check_owner_or_subscribe, check_repo_owner, get_current_user_id, get_req_path,
+        check_owner_or_subscribe, check_repo_owner, get_current_user_id, get_expected_version,
+        get_list_query, get_repo_post, get_req_path,
     },
 };
 
+const MAX_BATCH_POSTS: usize = 1000;
+const MAX_BATCH_BYTES: usize = 64 * 1024 * 1024;
+
 pub fn router() -> Router {
-    Router::new().get(list_post).post(push_post).push(
-        Router::with_path("<post_id>")
-            .get(get_post)
-            .delete(delete_post),
-    )
+    Router::new()
+        .get(list_post)
+        .post(push_post)
+        .push(Router::with_path("batch").post(push_post_batch))
+        .push(
+            Router::with_path("<post_id>")
+                .get(get_post)
+                .delete(delete_post)
+                .push(Router::with_path("html").get(get_post_html_page))
+                .push(Router::with_path("move").post(move_post_to_repo)),
+        )
 }
 
 #[handler]
@@ -30,17 +46,35 @@ async fn list_post(req: &mut Request, depot: &mut Depot) -> ServiceResult<OpenAp
     let current_user_id = get_current_user_id(depot)?;
     let repo_id = get_req_path(req, "repo_id")?;
 
+    let tag = req.query::<String>("tag");
+
     check_owner_or_subscribe(&repo_id, current_user_id)?;
-    info!("list post in repo {repo_id}");
+    info!("list post in repo {repo_id}, tag {tag:?}");
 
-    let posts = list_posts_by_repo_id(repo_id.as_str())?;
+    let query = get_list_query(req)?;
+    let page = list_posts_by_repo_id(repo_id.as_str(), current_user_id, tag.as_deref(), &query)?;
     // info!("list post result: {post:?}");
-    let mut result = vec![];
-    for post in posts {
-        let comments = list_comments_by_post_id(&post.id)?;
-        result.push(OpenApiPostSummaryResponse::new(post, comments));
+    let ids: Vec<String> = page.items.iter().map(|post| post.id.clone()).collect();
+    let mut comments = list_comments_by_post_ids(&ids)?;
+    let mut reactions = list_reactions_by_target_ids(ChangeKind::Post, &ids, current_user_id)?;
+    let mut unread = list_posts_unread(current_user_id, &ids)?;
+    let mut posts = vec![];
+    for post in page.items {
+        let comments = comments.remove(&post.id).unwrap_or_default();
+        let reactions = reactions.remove(&post.id).unwrap_or_default();
+        let unread = unread.remove(&post.id).unwrap_or_default();
+        posts.push(OpenApiPostSummaryResponse {
+            reactions: Some(reactions.into_iter().map(Into::into).collect()),
+            unread: Some(unread.unread),
+            unread_comments: Some(unread.comments),
+            ..OpenApiPostSummaryResponse::new(post, comments)
+        });
     }
-    Ok(OpenApiListPostResponse(result))
+    Ok(OpenApiListPostResponse {
+        posts,
+        next_cursor: page.next_cursor,
+        paged: page.paged,
+    })
 }
 
 #[handler]
@@ -49,12 +83,42 @@ async fn get_post(req: &mut Request, depot: &mut Depot) -> ServiceResult<OpenApi
     let repo_id = get_req_path(req, "repo_id")?;
     let post_id = get_req_path(req, "post_id")?;
     check_owner_or_subscribe(&repo_id, current_user_id)?;
-    let post = get_post_by_id(&post_id)?;
+    let post = get_repo_post(req, &repo_id, &post_id, current_user_id)?;
     info!("get post {post:?}");
-    match post {
-        Some(post) => Ok(post.into()),
-        None => Err(ServiceError::NotFound("post not found".to_owned())),
-    }
+    let html = match req.query::<String>("render").as_deref() {
+        Some("html") => Some(get_post_html(&post)?),
+        Some(render) => {
+            return Err(ServiceError::BadRequest(format!(
+                "unsupported render {render:?}"
+            )))
+        }
+        None => None,
+    };
+    let reactions = list_reactions(ChangeKind::Post, &post.id, current_user_id)?;
+    Ok(OpenApiGetPostResponse {
+        html,
+        reactions: Some(reactions.into_iter().map(Into::into).collect()),
+        ..post.into()
+    })
+}
+
+/// the post content as sanitized html.
+#[handler]
+async fn get_post_html_page(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<()> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let post_id = get_req_path(req, "post_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    let post = get_repo_post(req, &repo_id, &post_id, current_user_id)?;
+    info!("render post {post_id}");
+    let html = get_post_html(&post)?;
+    set_etag(response, post.version);
+    response.render(Text::Html(html));
+    Ok(())
 }
 
 #[handler]
@@ -62,30 +126,51 @@ async fn push_post(
     request: &mut Request,
     response: &mut Response,
     depot: &mut Depot,
-) -> ServiceResult<()> {
+) -> ServiceResult<OpenApiGetPostResponse> {
     info!("push post");
     let current_user_id = get_current_user_id(depot)?;
     let repo_id = get_req_path(request, "repo_id")?;
-    let post: Post = request.parse_body::<OpenApiPushPostRequest>().await?.into();
-    if post.repo_id != *repo_id {
-        return Err(ServiceError::NotFound("repo_id not match".to_owned()));
-    }
+    let req = request.parse_body::<OpenApiPushPostRequest>().await?;
+    let expected_version = get_expected_version(request, req.expected_version)?;
     if get_repo_by_id(&repo_id)?.is_none_or(|repo| repo.owner != *current_user_id) {
         return Err(ServiceError::Forbidden("auth failed".to_owned()));
     }
-    match get_post_by_id(&post.id)? {
-        Some(_old_post) => {
-            info!("update post {}", post.id);
-            update_post(&post)?;
-            response.status_code(StatusCode::OK);
-        }
-        None => {
-            info!("add post {}", post.id);
-            add_post(&post)?;
-            response.status_code(StatusCode::CREATED);
-        }
+    let (post, outcome) = push_post_to_repo(&repo_id, req, expected_version)?;
+    response.status_code(match outcome {
+        PushOutcome::Created => StatusCode::CREATED,
+        PushOutcome::Updated => StatusCode::OK,
+    });
+    Ok(post.into())
+}
+
+/// push many posts in one transaction, with a result per post.
+#[handler]
+async fn push_post_batch(
+    request: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiBatchPushResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(request, "repo_id")?;
+    let payload = request
+        .payload_with_max_size(MAX_BATCH_BYTES)
+        .await
+        .map_err(|err| ServiceError::BadRequest(format!("read batch: {err}")))?;
+    let req: OpenApiBatchPushRequest = serde_json::from_slice(payload)
+        .map_err(|err| ServiceError::BadRequest(format!("invalid batch: {err}")))?;
+    if req.posts.len() > MAX_BATCH_POSTS {
+        return Err(ServiceError::BadRequest(format!(
+            "at most {MAX_BATCH_POSTS} posts per batch"
+        )));
     }
-    Ok(())
+    if get_repo_by_id(&repo_id)?.is_none_or(|repo| repo.owner != *current_user_id) {
+        return Err(ServiceError::Forbidden("auth failed".to_owned()));
+    }
+    info!(
+        "push {} posts to repo {repo_id}, atomic {}",
+        req.posts.len(),
+        req.atomic
+    );
+    push_posts(&repo_id, req.posts, req.atomic)
 }
 
 #[handler]
@@ -100,13 +185,48 @@ async fn delete_post(
     check_repo_owner(&repo_id, current_user_id)?;
 
     let post_id = get_req_path(req, "post_id")?;
-    let post = get_post_by_id(&post_id)?;
-    let Some(post) = post else {
-        return Err(ServiceError::NotFound("post not found".to_owned()));
-    };
+    let post = get_repo_post(req, &repo_id, &post_id, current_user_id)?;
 
     info!("do delete post {post_id}");
-    erase_post(&post.id)?;
+    erase_post(&post.id, current_user_id)?;
     response.status_code(StatusCode::NO_CONTENT);
     Ok(())
 }
+
+/// move the post to another repo of the same owner, old links redirect to the new one.
+#[handler]
+async fn move_post_to_repo(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiGetPostResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let post_id = get_req_path(req, "post_id")?;
+    let body = req.parse_body::<OpenApiMovePostRequest>().await?;
+    let expected_version = get_expected_version(req, body.expected_version)?;
+    check_repo_owner(&repo_id, current_user_id)?;
+    if body.repo_id == repo_id {
+        return Err(ServiceError::BadRequest(
+            "post is already in that repo".to_owned(),
+        ));
+    }
+    match get_repo_by_id(&body.repo_id)? {
+        Some(repo) if repo.owner == *current_user_id => {}
+        Some(_) => return Err(ServiceError::Forbidden("forbidden".to_owned())),
+        None => {
+            return Err(ServiceError::NotFound(format!(
+                "repo {} not found",
+                body.repo_id
+            )))
+        }
+    }
+    let post = get_repo_post(req, &repo_id, &post_id, current_user_id)?;
+    info!(
+        "move post {post_id} from repo {repo_id} to {}",
+        body.repo_id
+    );
+    match move_post(&post.id, &body.repo_id, expected_version)? {
+        Some(post) => Ok(post.into()),
+        None => Err(post_version_conflict(&post.id)?),
+    }
+}
@@ -0,0 +1,86 @@
+use salvo::{handler, http::StatusCode, Depot, Request, Response, Router};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::{
+        change::ChangeKind,
+        comment::get_comment_by_id,
+        reaction::{
+            add_reaction, list_reactions, normalize_emoji, remove_reaction,
+            OpenApiListReactionResponse,
+        },
+    },
+    router::utils::{check_owner_or_subscribe, get_current_user_id, get_repo_post, get_req_path},
+};
+
+/// mounted at `repo/<repo_id>/post/<post_id>/reaction` and
+/// `repo/<repo_id>/post/<post_id>/comment/<comment_id>/reaction`.
+pub fn router() -> Router {
+    Router::new().get(list_reaction).push(
+        Router::with_path("<emoji>")
+            .put(put_reaction)
+            .delete(delete_reaction),
+    )
+}
+
+/// the post or comment of the path the caller may see, as the target of a reaction.
+fn get_target(req: &mut Request, current_user_id: &str) -> ServiceResult<(ChangeKind, String)> {
+    let repo_id = get_req_path(req, "repo_id")?;
+    let post_id = get_req_path(req, "post_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    get_repo_post(req, &repo_id, &post_id, current_user_id)?;
+    let Some(comment_id) = req.params().get("comment_id").cloned() else {
+        return Ok((ChangeKind::Post, post_id));
+    };
+    match get_comment_by_id(&comment_id)? {
+        Some(comment) if comment.post_id == post_id && comment.repo_id == repo_id => {
+            Ok((ChangeKind::Comment, comment_id))
+        }
+        _ => Err(ServiceError::NotFound("comment not found".to_owned())),
+    }
+}
+
+#[handler]
+async fn list_reaction(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListReactionResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let (kind, target_id) = get_target(req, current_user_id)?;
+    info!("list reaction to {kind} {target_id}");
+    Ok(list_reactions(kind, &target_id, current_user_id)?.into())
+}
+
+/// react with the emoji of the path, once per user. answers with the reactions after.
+#[handler]
+async fn put_reaction(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListReactionResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let (kind, target_id) = get_target(req, current_user_id)?;
+    let emoji = normalize_emoji(&get_req_path(req, "emoji")?)?;
+    info!("add reaction {emoji} to {kind} {target_id}");
+    if add_reaction(kind, &target_id, current_user_id, &emoji)? {
+        response.status_code(StatusCode::CREATED);
+    }
+    Ok(list_reactions(kind, &target_id, current_user_id)?.into())
+}
+
+/// take back the reaction, answers with the reactions after.
+#[handler]
+async fn delete_reaction(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListReactionResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let (kind, target_id) = get_target(req, current_user_id)?;
+    let emoji = get_req_path(req, "emoji")?;
+    info!("remove reaction {emoji} from {kind} {target_id}");
+    if !remove_reaction(kind, &target_id, current_user_id, emoji.trim())? {
+        return Err(ServiceError::NotFound("reaction not found".to_owned()));
+    }
+    Ok(list_reactions(kind, &target_id, current_user_id)?.into())
+}
@@ -0,0 +1,52 @@
+use salvo::{handler, http::StatusCode, Depot, Request, Response, Router};
+use tracing::info;
+
+use crate::{
+    error::ServiceResult,
+    model::read::{mark_post_read, mark_repo_read},
+    router::utils::{check_owner_or_subscribe, get_current_user_id, get_repo_post, get_req_path},
+};
+
+/// mounted at `repo/<repo_id>/post/<post_id>/read`.
+pub fn post_router() -> Router {
+    Router::new().post(read_post)
+}
+
+/// mounted at `repo/<repo_id>/read`.
+pub fn repo_router() -> Router {
+    Router::new().post(read_repo)
+}
+
+/// mark the post and its comments read for the caller.
+#[handler]
+async fn read_post(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<()> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let post_id = get_req_path(req, "post_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    get_repo_post(req, &repo_id, &post_id, current_user_id)?;
+    info!("mark post {post_id} read by {current_user_id}");
+    mark_post_read(current_user_id, &post_id)?;
+    response.status_code(StatusCode::NO_CONTENT);
+    Ok(())
+}
+
+/// mark all posts of the repo read for the caller.
+#[handler]
+async fn read_repo(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<()> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    info!("mark repo {repo_id} read by {current_user_id}");
+    mark_repo_read(current_user_id, &repo_id)?;
+    response.status_code(StatusCode::NO_CONTENT);
+    Ok(())
+}
@@ -1,17 +1,22 @@
+use std::str::FromStr;
+
 use salvo::{handler, http::StatusCode, Depot, Request, Response, Router};
 use tracing::info;
 
 use crate::{
     error::{ServiceError, ServiceResult},
     model::{
+        listing::ListQuery,
         post::list_posts_by_repo_id,
         repo::{
-            add_repo, get_repo_by_id, list_repos_by_owner_id, update_repo, OpenApiGetRepoResponse,
-            OpenApiListRepoResponse, OpenApiPushRepoRequest, Repo, RepoStatus,
+            add_repo, get_repo_by_id, list_repos_by_owner_id, normalize_topics, update_repo,
+            OpenApiGetRepoResponse, OpenApiListRepoResponse, OpenApiPushRepoRequest, Repo,
+            RepoStatus,
         },
+        search::{reindex, Tokenizer},
         sync::OpenApiGetRepoSyncInfoResponse,
     },
-    router::utils::{get_current_user_id, get_req_path},
+    router::utils::{get_current_user_id, get_expected_version, get_list_query, get_req_path},
 };
 
 pub fn router() -> Router {
@@ -27,13 +32,11 @@ pub fn router() -> Router {
 }
 
 #[handler]
-async fn list_repo(depot: &mut Depot) -> ServiceResult<OpenApiListRepoResponse> {
+async fn list_repo(req: &mut Request, depot: &mut Depot) -> ServiceResult<OpenApiListRepoResponse> {
     info!("list repo");
     let current_user_id = get_current_user_id(depot)?;
-    let repos = list_repos_by_owner_id(current_user_id)?;
-    Ok(OpenApiListRepoResponse(
-        repos.into_iter().map(|repo| repo.into()).collect(),
-    ))
+    let query = get_list_query(req)?;
+    Ok(list_repos_by_owner_id(current_user_id, &query)?.into())
 }
 
 #[handler]
@@ -44,7 +47,19 @@ async fn push_repo(
 ) -> ServiceResult<OpenApiGetRepoResponse> {
     info!("push repo");
     let current_user_id = get_current_user_id(depot)?;
-    let repo: Repo = request.parse_body::<OpenApiPushRepoRequest>().await?.into();
+    let req = request.parse_body::<OpenApiPushRepoRequest>().await?;
+    let expected_version = get_expected_version(request, req.expected_version)?;
+    let (keep_public, keep_topics) = (req.public.is_none(), req.topics.is_none());
+    let search_tokenizer = match req.search_tokenizer.as_deref() {
+        None => None,
+        Some("default") => Some(None),
+        Some(tokenizer) => Some(Some(Tokenizer::from_str(tokenizer)?)),
+    };
+    let mut repo: Repo = req.into();
+    repo.topics = normalize_topics(repo.topics)?;
+    if let Some(search_tokenizer) = search_tokenizer {
+        repo.search_tokenizer = search_tokenizer;
+    }
     info!("repo: {:?}", repo);
     if *current_user_id != repo.owner {
         return Err(ServiceError::Forbidden("auth failed".to_owned()));
@@ -54,8 +69,24 @@ async fn push_repo(
             if *current_user_id != _old_repo.owner {
                 return Err(ServiceError::Forbidden("auth failed".to_owned()));
             }
+            if keep_public {
+                repo.public = _old_repo.public;
+            }
+            if keep_topics {
+                repo.topics = _old_repo.topics;
+            }
+            if search_tokenizer.is_none() {
+                repo.search_tokenizer = _old_repo.search_tokenizer;
+            }
             info!("update repo");
-            update_repo(&repo)?;
+            let Some(version) = update_repo(&repo, expected_version)? else {
+                return Err(repo_version_conflict(&repo.id)?);
+            };
+            repo.version = version;
+            if repo.search_tokenizer != _old_repo.search_tokenizer {
+                let count = reindex(Some(&repo.id))?;
+                info!("search tokenizer changed, reindexed {count} items");
+            }
             response.status_code(StatusCode::OK);
         }
         None => {
@@ -72,10 +103,8 @@ async fn get_repo(req: &mut Request, depot: &mut Depot) -> ServiceResult<OpenApi
     info!("get repo");
     let repo_id = get_req_path(req, "repo_id")?;
     let current_user_id = get_current_user_id(depot)?;
-    let repos = list_repos_by_owner_id(current_user_id)?;
-    repos
-        .into_iter()
-        .find(|repo| repo.id == *repo_id)
+    get_repo_by_id(&repo_id)?
+        .filter(|repo| repo.owner == *current_user_id)
         .ok_or(ServiceError::NotFound("repo not found".to_string()))
         .map(|repo| repo.into())
 }
@@ -95,7 +124,10 @@ async fn delete_repo(
         return Err(ServiceError::NotFound(format!("{repo_id} not found")));
     }
     old_repo.status = RepoStatus::Deleted;
-    update_repo(&old_repo)?;
+    let expected_version = get_expected_version(req, None)?;
+    if update_repo(&old_repo, expected_version)?.is_none() {
+        return Err(repo_version_conflict(&repo_id)?);
+    }
     response.status_code(StatusCode::NO_CONTENT);
     Ok(())
 }
@@ -107,15 +139,29 @@ async fn repo_summary(
     depot: &mut Depot,
 ) -> ServiceResult<OpenApiGetRepoSyncInfoResponse> {
     info!("get repo info");
-    let _current_user_id = get_current_user_id(depot)?;
+    let current_user_id = get_current_user_id(depot)?;
     let repo_id = get_req_path(request, "repo_id")?;
     let repo = get_repo_by_id(&repo_id)?;
     // todo check permission maybe?
     match repo {
         Some(repo) => {
-            let posts = list_posts_by_repo_id(&repo_id)?;
-            Ok(OpenApiGetRepoSyncInfoResponse::new(repo, posts))
+            let posts =
+                list_posts_by_repo_id(&repo_id, current_user_id, None, &ListQuery::default())?;
+            Ok(OpenApiGetRepoSyncInfoResponse::new(repo, posts.items))
         }
         None => Err(ServiceError::NotFound(format!("repo {repo_id} not found"))),
     }
 }
+
+fn repo_version_conflict(repo_id: &str) -> ServiceResult<ServiceError> {
+    let current = get_repo_by_id(repo_id)?
+        .map(OpenApiGetRepoResponse::from)
+        .ok_or(ServiceError::NotFound(format!("repo {repo_id} not found")))?;
+    info!(
+        "repo {repo_id} version conflict, current {}",
+        current.version
+    );
+    serde_json::to_value(current)
+        .map(ServiceError::VersionConflict)
+        .map_err(|err| ServiceError::InternalServerError(err.to_string()))
+}
@@ -0,0 +1,149 @@
+use chrono::Utc;
+use salvo::{handler, Depot, Request, Router};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::{
+        category::get_or_add_category,
+        post::{post_version_conflict, update_post, OpenApiGetPostResponse, Post},
+        revision::{
+            diff_lines, get_revision, list_revisions_by_post_id, OpenApiGetRevisionResponse,
+            OpenApiListRevisionResponse, OpenApiRevisionDiffResponse,
+        },
+    },
+    router::utils::{
+        check_owner_or_subscribe, check_repo_owner, get_current_user_id, get_expected_version,
+        get_repo_post, get_req_path,
+    },
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .get(list_revision)
+        .push(Router::with_path("diff").get(diff_revision))
+        .push(
+            Router::with_path("<revision>")
+                .get(get_post_revision)
+                .push(Router::with_path("restore").post(restore_revision)),
+        )
+}
+
+fn parse_revision(revision: &str) -> ServiceResult<i64> {
+    revision
+        .parse()
+        .map_err(|_| ServiceError::BadRequest(format!("invalid revision {revision:?}")))
+}
+
+#[handler]
+async fn list_revision(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListRevisionResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let post_id = get_req_path(req, "post_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    let post = get_repo_post(req, &repo_id, &post_id, current_user_id)?;
+    info!("list revision of post {post_id}");
+    let revisions = list_revisions_by_post_id(&post.id)?;
+    Ok(OpenApiListRevisionResponse(
+        revisions.into_iter().map(Into::into).collect(),
+    ))
+}
+
+#[handler]
+async fn get_post_revision(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiGetRevisionResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let post_id = get_req_path(req, "post_id")?;
+    let revision = parse_revision(&get_req_path(req, "revision")?)?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    let post = get_repo_post(req, &repo_id, &post_id, current_user_id)?;
+    get_revision(&post.id, revision)?
+        .map(Into::into)
+        .ok_or(ServiceError::NotFound("revision not found".to_owned()))
+}
+
+/// diff between revisions `from` and `to`, a missing one stands for the current post.
+#[handler]
+async fn diff_revision(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiRevisionDiffResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let post_id = get_req_path(req, "post_id")?;
+    let from = req
+        .query::<String>("from")
+        .map(|r| parse_revision(&r))
+        .transpose()?;
+    let to = req
+        .query::<String>("to")
+        .map(|r| parse_revision(&r))
+        .transpose()?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    let post = get_repo_post(req, &repo_id, &post_id, current_user_id)?;
+
+    let version = |revision: Option<i64>| -> ServiceResult<(String, String, String)> {
+        match revision {
+            Some(revision) => get_revision(&post.id, revision)?
+                .map(|r| (r.title, r.category, r.content))
+                .ok_or(ServiceError::NotFound(format!(
+                    "revision {revision} not found"
+                ))),
+            None => Ok((
+                post.title.clone(),
+                post.category.clone(),
+                post.content.clone(),
+            )),
+        }
+    };
+    let (old_title, old_category, old_content) = version(from)?;
+    let (new_title, new_category, new_content) = version(to)?;
+    Ok(OpenApiRevisionDiffResponse {
+        from,
+        to,
+        old_title,
+        new_title,
+        old_category,
+        new_category,
+        lines: diff_lines(&old_content, &new_content),
+    })
+}
+
+/// make an old revision the new head, the replaced head is kept as a revision too.
+#[handler]
+async fn restore_revision(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiGetPostResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let post_id = get_req_path(req, "post_id")?;
+    let revision = parse_revision(&get_req_path(req, "revision")?)?;
+    check_repo_owner(&repo_id, current_user_id)?;
+    let post = get_repo_post(req, &repo_id, &post_id, current_user_id)?;
+    let Some(old) = get_revision(&post.id, revision)? else {
+        return Err(ServiceError::NotFound("revision not found".to_owned()));
+    };
+    info!("restore post {post_id} to revision {revision}");
+    let category = get_or_add_category(&repo_id, &old.category)?;
+    let mut post = Post {
+        title: old.title,
+        category: category.name,
+        category_id: Some(category.id),
+        content: old.content,
+        updated_at: Utc::now(),
+        ..post
+    };
+    let expected_version = get_expected_version(req, None)?;
+    let Some(version) = update_post(&post, expected_version)? else {
+        return Err(post_version_conflict(&post.id)?);
+    };
+    post.version = version;
+    Ok(post.into())
+}
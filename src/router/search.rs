@@ -0,0 +1,51 @@
+use salvo::{handler, Depot, Request, Router};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::search::{search, OpenApiSearchResponse},
+    router::utils::{check_owner_or_subscribe, get_current_user_id},
+};
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 100;
+
+pub fn router() -> Router {
+    Router::new().get(search_posts)
+}
+
+/// search posts and comments by `q`, in every readable repo or only in `repo`.
+#[handler]
+async fn search_posts(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiSearchResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let Some(query) = req.query::<String>("q").filter(|q| !q.trim().is_empty()) else {
+        return Err(ServiceError::BadRequest("need query param `q`".to_owned()));
+    };
+    let repo_id = req.query::<String>("repo");
+    if let Some(repo_id) = &repo_id {
+        check_owner_or_subscribe(repo_id, current_user_id)?;
+    }
+    let page = req.query::<u32>("page").unwrap_or(1).max(1);
+    let limit = req.query::<u32>("limit").unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ServiceError::BadRequest(format!(
+            "limit should be in 1..={MAX_LIMIT}"
+        )));
+    }
+    let Some(offset) = (page - 1).checked_mul(limit) else {
+        return Err(ServiceError::BadRequest(format!(
+            "page {page} out of range"
+        )));
+    };
+    info!("search {query:?} in {repo_id:?}");
+    let (hits, total) = search(current_user_id, &query, repo_id.as_deref(), limit, offset)?;
+    Ok(OpenApiSearchResponse {
+        hits: hits.into_iter().map(Into::into).collect(),
+        page,
+        limit,
+        total,
+    })
+}
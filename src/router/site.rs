@@ -0,0 +1,135 @@
+use salvo::{fs::NamedFile, handler, http::StatusCode, Depot, Request, Response, Router};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::{
+        export::{export_path, path_segment},
+        repo::get_repo_by_id,
+        site::{
+            get_site_template, list_site_templates, reset_site_template, set_site_template,
+            write_site_zip, OpenApiListSiteTemplateResponse, OpenApiPutSiteTemplateRequest,
+            OpenApiSiteTemplateResponse, MAX_TEMPLATE_BYTES,
+        },
+    },
+    router::utils::{
+        check_owner_or_subscribe, check_repo_owner, get_current_user_id, get_req_path,
+    },
+};
+
+/// mounted at `repo/<repo_id>/site`.
+pub fn router() -> Router {
+    Router::new().get(download_site).push(
+        Router::with_path("template").get(list_template).push(
+            Router::with_path("<name>")
+                .get(get_template)
+                .put(put_template)
+                .delete(reset_template),
+        ),
+    )
+}
+
+/// the published posts of the repo as a zip of a static website. `base_url` is where
+/// it will be served from, for the links of the rss feed.
+#[handler]
+async fn download_site(
+    req: &mut Request,
+    depot: &mut Depot,
+    response: &mut Response,
+) -> ServiceResult<()> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    let Some(repo) = get_repo_by_id(&repo_id)? else {
+        return Err(ServiceError::NotFound("repo not found".to_owned()));
+    };
+    let base_url = req.query::<String>("base_url");
+    info!("render site of repo {repo_id}, base url {base_url:?}");
+    let path = export_path(&uuid::Uuid::new_v4().to_string());
+    let spool = path.clone();
+    tokio::task::spawn_blocking(move || write_site_zip(&repo_id, base_url.as_deref(), &spool))
+        .await
+        .map_err(|err| ServiceError::InternalServerError(err.to_string()))??;
+    let file = NamedFile::builder(&path)
+        .content_type(mime_infer::from_ext("zip").first_or_octet_stream())
+        .attached_name(format!("{}-site.zip", path_segment(&repo.name, "repo")))
+        .build()
+        .await;
+    // the open file keeps the data around until sent
+    std::fs::remove_file(&path)?;
+    let file = file.map_err(|err| ServiceError::InternalServerError(err.to_string()))?;
+    file.send(req.headers(), response).await;
+    Ok(())
+}
+
+/// the templates of the site, overridden or the defaults.
+#[handler]
+async fn list_template(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListSiteTemplateResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_repo_owner(&repo_id, current_user_id)?;
+    info!("list site template in repo {repo_id}");
+    let templates = list_site_templates(&repo_id)?;
+    Ok(OpenApiListSiteTemplateResponse(
+        templates.into_iter().map(Into::into).collect(),
+    ))
+}
+
+#[handler]
+async fn get_template(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiSiteTemplateResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let name = get_req_path(req, "name")?;
+    check_repo_owner(&repo_id, current_user_id)?;
+    match get_site_template(&repo_id, &name)? {
+        Some(template) => Ok(template.into()),
+        None => Err(ServiceError::NotFound(format!("no template {name:?}"))),
+    }
+}
+
+/// override a template, `{{name}}` placeholders are filled in when rendering.
+#[handler]
+async fn put_template(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiSiteTemplateResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let name = get_req_path(req, "name")?;
+    check_repo_owner(&repo_id, current_user_id)?;
+    let body = req.parse_body::<OpenApiPutSiteTemplateRequest>().await?;
+    if body.body.len() > MAX_TEMPLATE_BYTES {
+        return Err(ServiceError::BadRequest(format!(
+            "template larger than {MAX_TEMPLATE_BYTES} bytes"
+        )));
+    }
+    info!("set site template {name} in repo {repo_id}");
+    Ok(set_site_template(&repo_id, &name, &body.body)?.into())
+}
+
+/// go back to the default template.
+#[handler]
+async fn reset_template(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<()> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let name = get_req_path(req, "name")?;
+    check_repo_owner(&repo_id, current_user_id)?;
+    info!("reset site template {name} in repo {repo_id}");
+    if !reset_site_template(&repo_id, &name)? {
+        return Err(ServiceError::NotFound(format!(
+            "template {name:?} is not overridden"
+        )));
+    }
+    response.status_code(StatusCode::NO_CONTENT);
+    Ok(())
+}
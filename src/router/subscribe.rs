@@ -4,11 +4,12 @@ use tracing::info;
 use crate::{
     error::{ServiceError, ServiceResult},
     model::{
+        read::list_repos_unread,
         repo::{get_repo_by_id, OpenApiGetRepoResponse, OpenApiListRepoResponse},
         subscribe::{add_subscribe, check_subscribe, delete_subscribe, fetch_subscribe},
         sync::OpenApiSubscribeLinkRequest,
     },
-    router::utils::get_current_user_id,
+    router::utils::{get_current_user_id, get_list_query},
 };
 
 pub fn router() -> Router {
@@ -51,18 +52,25 @@ async fn new_subscribe(
 }
 
 #[handler]
-async fn list_subscribe(depot: &mut Depot) -> ServiceResult<OpenApiListRepoResponse> {
+async fn list_subscribe(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiListRepoResponse> {
     let current_user_id = get_current_user_id(depot)?;
-    let repo_ids = fetch_subscribe(current_user_id)?;
-    let mut repos = Vec::new();
-    for repo_id in repo_ids {
-        if let Some(repo) = get_repo_by_id(&repo_id)? {
-            repos.push(repo);
-        }
+    let query = get_list_query(req)?;
+    let mut subscribes: OpenApiListRepoResponse = fetch_subscribe(current_user_id, &query)?.into();
+    let ids: Vec<String> = subscribes
+        .repos
+        .iter()
+        .map(|repo| repo.id.clone())
+        .collect();
+    let unread = list_repos_unread(current_user_id, &ids)?;
+    for repo in subscribes.repos.iter_mut() {
+        let unread = unread.get(&repo.id).copied().unwrap_or_default();
+        repo.unread_posts = Some(unread.posts);
+        repo.unread_comments = Some(unread.comments);
     }
-    Ok(OpenApiListRepoResponse(
-        repos.into_iter().map(|repo| repo.into()).collect(),
-    ))
+    Ok(subscribes)
 }
 
 #[handler]
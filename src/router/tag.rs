@@ -0,0 +1,36 @@
+use salvo::{handler, Depot, Request, Router};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::tag::{list_tags_by_repo_id, OpenApiListTagResponse},
+    router::utils::{check_owner_or_subscribe, get_current_user_id, get_req_path},
+};
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 1000;
+
+pub fn router() -> Router {
+    Router::new().get(list_tag)
+}
+
+/// tags of the repo with their post counts, most used first.
+/// `prefix` narrows them down for autocomplete.
+#[handler]
+async fn list_tag(req: &mut Request, depot: &mut Depot) -> ServiceResult<OpenApiListTagResponse> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_owner_or_subscribe(&repo_id, current_user_id)?;
+    let prefix = req.query::<String>("prefix");
+    let limit = req.query::<u32>("limit").unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ServiceError::BadRequest(format!(
+            "limit should be in 1..={MAX_LIMIT}"
+        )));
+    }
+    info!("list tag in repo {repo_id}, prefix {prefix:?}");
+    let tags = list_tags_by_repo_id(&repo_id, current_user_id, prefix.as_deref(), limit)?;
+    Ok(OpenApiListTagResponse(
+        tags.into_iter().map(Into::into).collect(),
+    ))
+}
@@ -0,0 +1,244 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{Duration, Utc};
+use salvo::{
+    handler,
+    http::{header, HeaderValue, Method, StatusCode},
+    Depot, Request, Response, Router,
+};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    model::{
+        attachment::{add_attachment_file, is_sha256, sha256_file, Attachment},
+        upload::{
+            add_upload, append_upload, delete_upload, get_upload, set_upload_headers, upload_path,
+            OpenApiNewUploadRequest, OpenApiUploadResponse, Upload, UPLOAD_EXPIRY_HOURS,
+        },
+    },
+    router::utils::{check_repo_owner, get_current_user_id, get_req_path},
+};
+
+use super::attachment::max_attachment_size;
+
+/// largest chunk a single `PATCH` may carry.
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// resumable uploads, modeled after tus: `POST` creates an upload, `HEAD` tells how
+/// much of it the server has, `PATCH` appends a chunk at `Upload-Offset`.
+/// the completed upload becomes an attachment of the repo.
+pub fn router() -> Router {
+    Router::new().post(create_upload).push(
+        Router::with_path("<upload_id>")
+            .head(get_upload_offset)
+            .get(get_upload_offset)
+            .patch(patch_upload)
+            .delete(abort_upload),
+    )
+}
+
+/// the upload, if it is in the repo and belongs to the current user.
+fn get_own_upload(req: &mut Request, depot: &mut Depot) -> ServiceResult<Upload> {
+    let current_user_id = get_current_user_id(depot)?;
+    let repo_id = get_req_path(req, "repo_id")?;
+    let upload_id = get_req_path(req, "upload_id")?;
+    match get_upload(&upload_id)? {
+        Some(upload) if upload.repo_id == repo_id && upload.uploader == *current_user_id => {
+            Ok(upload)
+        }
+        _ => Err(ServiceError::NotFound("upload not found".to_owned())),
+    }
+}
+
+#[handler]
+async fn create_upload(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiUploadResponse> {
+    let current_user_id = get_current_user_id(depot)?.clone();
+    let repo_id = get_req_path(req, "repo_id")?;
+    check_repo_owner(&repo_id, &current_user_id)?;
+    let body = req.parse_body::<OpenApiNewUploadRequest>().await?;
+    if body.length > max_attachment_size() {
+        return Err(ServiceError::BadRequest(format!(
+            "attachment larger than {} bytes",
+            max_attachment_size()
+        )));
+    }
+    let sha256 = body.sha256.map(|sha256| sha256.to_lowercase());
+    if sha256.as_deref().is_some_and(|sha256| !is_sha256(sha256)) {
+        return Err(ServiceError::BadRequest("invalid sha256".to_owned()));
+    }
+    let now = Utc::now();
+    let upload = Upload {
+        id: uuid::Uuid::new_v4().to_string(),
+        repo_id,
+        uploader: current_user_id,
+        name: body.name,
+        content_type: body.content_type,
+        length: body.length,
+        offset: 0,
+        sha256,
+        created_at: now,
+        expires_at: now + Duration::hours(UPLOAD_EXPIRY_HOURS),
+    };
+    info!("create upload {} of {} bytes", upload.id, upload.length);
+    add_upload(&upload)?;
+    if let Ok(location) = format!("/repo/{}/upload/{}", upload.repo_id, upload.id).parse() {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    response.status_code(StatusCode::CREATED);
+    Ok(upload.into())
+}
+
+/// `HEAD` only answers with the headers, `GET` with the upload as well.
+#[handler]
+async fn get_upload_offset(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<()> {
+    let upload = get_own_upload(req, depot)?;
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if req.method() == Method::HEAD {
+        set_upload_headers(response, upload.offset, upload.length);
+    } else {
+        response.render(OpenApiUploadResponse::from(upload));
+    }
+    Ok(())
+}
+
+/// `Upload-Checksum: sha256 <base64 digest>` of the chunk, if the client sent one.
+fn check_chunk_checksum(req: &Request, chunk: &[u8]) -> ServiceResult<()> {
+    let Some(checksum) = req.headers().get("Upload-Checksum") else {
+        return Ok(());
+    };
+    let checksum = checksum
+        .to_str()
+        .map_err(|_| ServiceError::BadRequest("invalid Upload-Checksum header".to_owned()))?;
+    let Some(("sha256", digest)) = checksum.trim().split_once(' ') else {
+        return Err(ServiceError::BadRequest(format!(
+            "unsupported Upload-Checksum {checksum:?}, use sha256"
+        )));
+    };
+    let expected = STANDARD
+        .decode(digest.trim())
+        .map_err(|_| ServiceError::BadRequest("invalid Upload-Checksum digest".to_owned()))?;
+    if Sha256::digest(chunk).as_slice() != expected.as_slice() {
+        return Err(ServiceError::BadRequest(
+            "chunk checksum mismatch".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// the upload file as an attachment, if it matches the sha256 the client gave.
+/// a mismatching file is never stored, no attachment would own the blob.
+fn store_upload(upload: &Upload) -> ServiceResult<Attachment> {
+    let path = upload_path(&upload.id);
+    let (sha256, size) = sha256_file(&path)?;
+    if upload
+        .sha256
+        .as_ref()
+        .is_some_and(|expected| *expected != sha256)
+    {
+        return Err(ServiceError::BadRequest(format!(
+            "upload {} does not match its sha256, got {sha256}",
+            upload.id
+        )));
+    }
+    let content_type = upload.content_type.clone().unwrap_or_else(|| {
+        mime_infer::from_path(&upload.name)
+            .first_or_octet_stream()
+            .to_string()
+    });
+    let (attachment, _) = add_attachment_file(
+        &path,
+        Attachment {
+            repo_id: upload.repo_id.clone(),
+            sha256,
+            name: upload.name.clone(),
+            content_type,
+            size,
+            uploader: upload.uploader.clone(),
+            created_at: Utc::now(),
+        },
+    )?;
+    Ok(attachment)
+}
+
+/// hand the complete upload over to the attachment storage.
+fn finish_upload(upload: &Upload) -> ServiceResult<Attachment> {
+    let stored = store_upload(upload);
+    delete_upload(&upload.id)?;
+    stored
+}
+
+#[handler]
+async fn patch_upload(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiUploadResponse> {
+    let upload = get_own_upload(req, depot)?;
+    let offset = req
+        .headers()
+        .get("Upload-Offset")
+        .and_then(|offset| offset.to_str().ok()?.parse::<u64>().ok())
+        .ok_or(ServiceError::BadRequest(
+            "need a valid Upload-Offset header".to_owned(),
+        ))?;
+    if offset != upload.offset {
+        return Err(ServiceError::Conflict(format!(
+            "upload is at offset {}, not {offset}",
+            upload.offset
+        )));
+    }
+    let chunk = req.payload_with_max_size(MAX_CHUNK_SIZE).await?.clone();
+    if upload.offset + chunk.len() as u64 > upload.length {
+        return Err(ServiceError::BadRequest(format!(
+            "chunk goes past the upload length {}",
+            upload.length
+        )));
+    }
+    check_chunk_checksum(req, &chunk)?;
+    // the write, fsync and hashing of the finished file block
+    tokio::task::spawn_blocking(move || {
+        let Some(upload) = append_upload(&upload, &chunk)? else {
+            return Err(ServiceError::Conflict(
+                "upload was patched concurrently, check its offset".to_owned(),
+            ));
+        };
+        info!(
+            "upload {} at {}/{}",
+            upload.id, upload.offset, upload.length
+        );
+        if !upload.is_complete() {
+            return Ok(upload.into());
+        }
+        let attachment = finish_upload(&upload)?;
+        info!("upload {} completed as {}", upload.id, attachment.sha256);
+        Ok(OpenApiUploadResponse {
+            attachment: Some(attachment.into()),
+            ..upload.into()
+        })
+    })
+    .await
+    .map_err(|err| ServiceError::InternalServerError(err.to_string()))?
+}
+
+#[handler]
+async fn abort_upload(
+    req: &mut Request,
+    response: &mut Response,
+    depot: &mut Depot,
+) -> ServiceResult<()> {
+    let upload = get_own_upload(req, depot)?;
+    info!("abort upload {}", upload.id);
+    delete_upload(&upload.id)?;
+    response.status_code(StatusCode::NO_CONTENT);
+    Ok(())
+}
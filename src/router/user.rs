@@ -1,22 +1,29 @@
 use chrono::Utc;
 use salvo::{
     basic_auth::{BasicAuth, BasicAuthValidator},
+    fs::NamedFile,
     handler,
     http::StatusCode,
     Depot, Request, Response, Router,
 };
+use tracing::info;
 
 use crate::{
     error::{ServiceError, ServiceResult},
-    model::user::{
-        add_user, get_user_by_id, get_user_by_name, update_exist_user, OpenApiGetUserResponse,
-        OpenApiNewUserRequest, OpenApiUpdateUserRequest, OpenApiValidateUserResponse, User,
+    model::{
+        media::{avatar_path, decode_image, store_avatar, AVATAR_SIZES},
+        user::{
+            add_user, get_user_by_id, get_user_by_name, update_exist_user, OpenApiGetUserResponse,
+            OpenApiNewUserRequest, OpenApiUpdateUserRequest, OpenApiValidateUserResponse, User,
+        },
     },
-    router::utils::{get_current_user_id, SESSION_USER_ID},
+    router::utils::{get_current_user_id, limit_multipart_body, SESSION_USER_ID},
 };
 
 use super::utils::get_req_path;
 
+const MAX_AVATAR_SIZE: u64 = 10 * 1024 * 1024;
+
 pub struct UserValidator;
 
 impl BasicAuthValidator for UserValidator {
@@ -40,7 +47,12 @@ pub fn router() -> Router {
         .push(Router::with_path("validate-login").post(validate_login));
     let auth_router = Router::new()
         .push(Router::new().get(get_user))
-        .push(Router::with_path("<id>").put(update_user));
+        .push(Router::with_path("<id>").put(update_user))
+        .push(
+            Router::with_path("<id>/avatar")
+                .get(get_avatar)
+                .put(upload_avatar),
+        );
     Router::new()
         .push(non_auth_router)
         .push(Router::with_hoop(BasicAuth::new(UserValidator)).push(auth_router))
@@ -120,7 +132,7 @@ async fn get_user(
     } else if let Some(name) = name {
         get_user_by_name(&name)?.ok_or(ServiceError::NotFound("user not found".to_string()))?
     } else {
-        get_user_by_id(&current_user_id)?
+        get_user_by_id(current_user_id)?
             .ok_or(ServiceError::NotFound("user not found".to_string()))?
     };
     Ok(OpenApiGetUserResponse {
@@ -129,3 +141,76 @@ async fn get_user(
         avatar_url: user.avatar_url,
     })
 }
+
+/// multipart upload of the `file` field as the current user's avatar, cropped to a
+/// square and stored in every size of `AVATAR_SIZES`.
+#[handler]
+async fn upload_avatar(
+    request: &mut Request,
+    depot: &mut Depot,
+) -> ServiceResult<OpenApiGetUserResponse> {
+    let current_user_id = get_current_user_id(depot)?.clone();
+    let id = get_req_path(request, "id")?;
+    if id != current_user_id {
+        return Err(ServiceError::Forbidden("forbidden".to_owned()));
+    }
+    let user = get_user_by_id(&id)?.ok_or(ServiceError::NotFound("user not found".to_string()))?;
+    limit_multipart_body(request, MAX_AVATAR_SIZE)?;
+    request.form_data().await?;
+    let Some(file) = request.file("file").await else {
+        return Err(ServiceError::BadRequest(
+            "need multipart field `file`".to_owned(),
+        ));
+    };
+    if file.size() > MAX_AVATAR_SIZE {
+        return Err(ServiceError::BadRequest(format!(
+            "avatar larger than {MAX_AVATAR_SIZE} bytes"
+        )));
+    }
+    let path = file.path().clone();
+    let user_id = user.id.clone();
+    tokio::task::spawn_blocking(move || -> ServiceResult<()> {
+        let image = decode_image(&std::fs::read(path)?)?;
+        store_avatar(&user_id, &image)
+    })
+    .await
+    .map_err(|err| ServiceError::InternalServerError(err.to_string()))??;
+    let now = Utc::now();
+    let user = User {
+        // the timestamp makes clients drop their cached copy
+        avatar_url: Some(format!("/user/{}/avatar?v={}", user.id, now.timestamp())),
+        updated_at: now,
+        ..user
+    };
+    info!("update avatar of user {}", user.id);
+    update_exist_user(&user)?;
+    Ok(OpenApiGetUserResponse {
+        id: user.id,
+        name: user.name,
+        avatar_url: user.avatar_url,
+    })
+}
+
+/// the avatar as png, `size` is one of `AVATAR_SIZES`.
+#[handler]
+async fn get_avatar(request: &mut Request, response: &mut Response) -> ServiceResult<()> {
+    let id = get_req_path(request, "id")?;
+    let size = request.query::<u32>("size").unwrap_or(AVATAR_SIZES[0]);
+    if !AVATAR_SIZES.contains(&size) {
+        return Err(ServiceError::BadRequest(format!(
+            "size should be one of {AVATAR_SIZES:?}"
+        )));
+    }
+    // only ids of real users make it into the path
+    let user = get_user_by_id(&id)?.ok_or(ServiceError::NotFound("user not found".to_string()))?;
+    let path = avatar_path(&user.id, size);
+    if !path.exists() {
+        return Err(ServiceError::NotFound("avatar not found".to_owned()));
+    }
+    NamedFile::builder(path)
+        .content_type(mime::IMAGE_PNG)
+        .disposition_type("inline")
+        .send(request.headers(), response)
+        .await;
+    Ok(())
+}
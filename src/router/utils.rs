@@ -1,12 +1,36 @@
-use salvo::{Depot, Request};
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use chrono::{DateTime, Utc};
+use salvo::{
+    http::{
+        body::{Body, Frame, ReqBody, SizeHint},
+        header,
+    },
+    hyper::body::Bytes,
+    BoxedError, Depot, Request,
+};
 
 use crate::{
     error::{ServiceError, ServiceResult},
-    model::{repo::get_repo_by_id, subscribe::check_subscribe},
+    model::{
+        listing::{ListCursor, ListQuery, ListSort},
+        post::{get_post_by_id, Post},
+        redirect::get_redirect,
+        repo::get_repo_by_id,
+        subscribe::check_subscribe,
+    },
 };
 
 pub const SESSION_USER_ID: &str = "current_user_id";
 
+const DEFAULT_LIST_LIMIT: u32 = 100;
+const MAX_LIST_LIMIT: u32 = 500;
+/// room for the boundaries and part headers around the files of a multipart body.
+const MULTIPART_OVERHEAD: u64 = 64 * 1024;
+
 pub fn get_current_user_id(depot: &mut Depot) -> ServiceResult<&String> {
     depot
         .get::<String>(SESSION_USER_ID)
@@ -22,6 +46,100 @@ pub fn get_req_path(req: &mut Request, key: &str) -> ServiceResult<String> {
         )))
 }
 
+/// expected version from the `If-Match` header (`"3"`, `W/"3"` or `3`), falling back to the body field.
+pub fn get_expected_version(
+    req: &Request,
+    body_version: Option<i64>,
+) -> ServiceResult<Option<i64>> {
+    let Some(if_match) = req.headers().get(salvo::http::header::IF_MATCH) else {
+        return Ok(body_version);
+    };
+    let if_match = if_match
+        .to_str()
+        .map_err(|_| ServiceError::BadRequest("invalid If-Match header".to_owned()))?;
+    if_match
+        .trim()
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| ServiceError::BadRequest(format!("invalid If-Match header {if_match:?}")))
+}
+
+/// `sort` = created|updated|title, `order` = asc|desc, `limit`, `cursor`, and the
+/// `category`, `author`, `since` and `until` filters of a list endpoint. without `limit`
+/// and `cursor` everything is listed, as before lists had pages.
+pub fn get_list_query(req: &mut Request) -> ServiceResult<ListQuery> {
+    let sort = match req.query::<String>("sort") {
+        Some(sort) => sort.parse::<ListSort>()?,
+        None => ListSort::default(),
+    };
+    let desc = match req.query::<String>("order").as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(order) => return Err(ServiceError::BadRequest(format!("invalid order {order:?}"))),
+    };
+    let after = match req.query::<String>("cursor") {
+        Some(cursor) if !cursor.is_empty() => Some(ListCursor::decode(&cursor)?),
+        _ => None,
+    };
+    let limit = match req.query::<u32>("limit") {
+        Some(limit) if limit == 0 || limit > MAX_LIST_LIMIT => {
+            return Err(ServiceError::BadRequest(format!(
+                "limit should be in 1..={MAX_LIST_LIMIT}"
+            )));
+        }
+        Some(limit) => Some(limit),
+        None => after.is_some().then_some(DEFAULT_LIST_LIMIT),
+    };
+    let date = |key: &str| -> ServiceResult<Option<DateTime<Utc>>> {
+        req.query::<String>(key)
+            .map(|value| {
+                DateTime::parse_from_rfc3339(&value)
+                    .map(|date| date.with_timezone(&Utc))
+                    .map_err(|_| ServiceError::BadRequest(format!("invalid {key} {value:?}")))
+            })
+            .transpose()
+    };
+    Ok(ListQuery {
+        sort,
+        desc,
+        limit,
+        after,
+        category: req.query::<String>("category").filter(|c| !c.is_empty()),
+        author: req.query::<String>("author").filter(|a| !a.is_empty()),
+        since: date("since")?,
+        until: date("until")?,
+    })
+}
+
+/// the post `post_id` of `repo_id`, if `viewer` may see it. a post moved to another repo
+/// answers with a redirect to the same path under the new repo.
+pub fn get_repo_post(
+    req: &Request,
+    repo_id: &str,
+    post_id: &str,
+    viewer: &str,
+) -> ServiceResult<Post> {
+    match get_post_by_id(post_id)? {
+        Some(post) if post.repo_id == repo_id && post.is_visible_to(viewer) => Ok(post),
+        _ => match get_redirect(repo_id, post_id)? {
+            Some(to_repo_id) => {
+                let mut location = req.uri().path().replacen(
+                    &format!("/repo/{repo_id}/"),
+                    &format!("/repo/{to_repo_id}/"),
+                    1,
+                );
+                if let Some(query) = req.uri().query() {
+                    location = format!("{location}?{query}");
+                }
+                Err(ServiceError::Moved(location))
+            }
+            None => Err(ServiceError::NotFound("post not found".to_owned())),
+        },
+    }
+}
+
 pub fn check_repo_owner(repo_id: &str, current_user_id: &str) -> ServiceResult<()> {
     let repo = get_repo_by_id(repo_id)?;
     let Some(repo) = repo else {
@@ -42,3 +160,67 @@ pub fn check_owner_or_subscribe(repo_id: &str, current_user_id: &str) -> Service
         (_, Err(e)) => Err(e),
     }
 }
+
+/// rejects a multipart request declaring more than `max_size` bytes of content and caps
+/// the body stream, so a chunked or understated body fails while the form is parsed
+/// instead of filling the temp dir. call before `req.file`/`req.files`.
+pub fn limit_multipart_body(req: &mut Request, max_size: u64) -> ServiceResult<()> {
+    let max_size = max_size + MULTIPART_OVERHEAD;
+    let length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if length.is_some_and(|length| length > max_size) {
+        return Err(ServiceError::BadRequest(format!(
+            "request larger than {max_size} bytes"
+        )));
+    }
+    let inner = req.take_body();
+    req.replace_body(ReqBody::Boxed {
+        inner: Box::pin(LimitedBody {
+            inner,
+            remaining: max_size,
+        }),
+        fusewire: None,
+    });
+    Ok(())
+}
+
+/// a request body failing once more than `remaining` bytes came through.
+struct LimitedBody {
+    inner: ReqBody,
+    remaining: u64,
+}
+
+impl Body for LimitedBody {
+    type Data = Bytes;
+    type Error = BoxedError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxedError>>> {
+        let frame = match ready!(Pin::new(&mut self.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => frame,
+            Some(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+            None => return Poll::Ready(None),
+        };
+        if let Some(data) = frame.data_ref() {
+            let len = data.len() as u64;
+            if len > self.remaining {
+                return Poll::Ready(Some(Err("request body too large".into())));
+            }
+            self.remaining -= len;
+        }
+        Poll::Ready(Some(Ok(frame)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}